@@ -0,0 +1,1086 @@
+//! Command-line interface: `encora encrypt`, `encora decrypt`, `encora list`,
+//! `encora extract`, `encora info`, `encora keygen` and `encora keyslot`.
+
+use clap::builder::{PossibleValuesParser, TypedValueParser};
+use clap::{Args, Parser, Subcommand};
+use encora::crypto::header::FORMAT_VERSION;
+use encora::{
+    Algorithm, Archive, EncoraError, EncryptOptions, EntryKind, FileMetadata, Header, Identity,
+    Key, KeyFile, KeySlotKind, NewKeySlot, OutputLocation, Recipient, Result, Secret, Vault,
+};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use zeroize::Zeroizing;
+
+/// Any failure not covered by the codes below, mostly I/O errors or a file
+/// changed by another program while being replaced in place.
+const EXIT_FAILURE: u8 = 1;
+/// Invalid arguments or key input (clap also exits with 2 on usage errors).
+const EXIT_USAGE: u8 = 2;
+/// The input was rejected: not an Encora file, wrong key, or corrupted data.
+const EXIT_REJECTED: u8 = 3;
+
+/// Extension added to encrypted files when no output path is given.
+const EXTENSION: &str = "encora";
+
+#[derive(Parser)]
+#[command(
+    name = "encora",
+    version,
+    about = "Encrypt and decrypt files in the Encora format"
+)]
+#[command(
+    after_help = "Exit codes: 0 success, 1 I/O or other failure, 2 invalid usage or key, \
+                        3 input rejected (not an Encora file, wrong key or corrupted data)"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Encrypt a file
+    Encrypt(EncryptArgs),
+    /// Decrypt an Encora file
+    Decrypt(DecryptArgs),
+    /// List the contents of an encrypted folder
+    List {
+        /// Encrypted folder to list
+        input: PathBuf,
+        #[command(flatten)]
+        unlock: UnlockArgs,
+    },
+    /// Extract one file or subfolder from an encrypted folder, decrypting
+    /// only the parts of it that are needed
+    Extract(ExtractArgs),
+    /// Show the header of an Encora file without decrypting it
+    Info {
+        /// Encora file to inspect
+        input: PathBuf,
+    },
+    /// Generate an identity (private key) and print its public key
+    Keygen {
+        /// Write the identity to this file instead of standard output
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// List, add or remove the keyslots of an Encora file
+    #[command(subcommand)]
+    Keyslot(KeyslotCommand),
+}
+
+#[derive(Subcommand)]
+enum KeyslotCommand {
+    /// List the keyslots of an Encora file
+    List {
+        /// Encora file to inspect
+        input: PathBuf,
+    },
+    /// Add a keyslot, rewriting only the header
+    Add(AddKeyslotArgs),
+    /// Remove a keyslot, rewriting only the header
+    Remove(RemoveKeyslotArgs),
+}
+
+#[derive(Args)]
+struct EncryptArgs {
+    /// File or folder to encrypt, or `-` for standard input
+    input: PathBuf,
+    /// Where to write the encrypted file, or `-` for standard output [default: INPUT.encora]
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Encryption algorithm
+    #[arg(short, long, default_value = "xchacha20", value_parser = algorithm_parser())]
+    algorithm: Algorithm,
+    #[command(flatten)]
+    key: KeyArgs,
+    /// Encrypt to this public key (`encora-pk-...`); repeat for several recipients
+    #[arg(short, long, value_name = "PUBKEY", value_parser = Recipient::parse, conflicts_with = "key_out")]
+    recipient: Vec<Recipient>,
+    /// Compress with zstd before encrypting, at LEVEL (1-22, default 3);
+    /// skipped if the data turns out not to compress
+    #[arg(
+        short = 'z',
+        long,
+        value_name = "LEVEL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "3",
+        value_parser = clap::value_parser!(i32).range(1..=22)
+    )]
+    compress: Option<i32>,
+    /// Name the encrypted file `<random>.encora` next to the input, so only
+    /// the encrypted metadata knows the original name
+    #[arg(long, conflicts_with = "output")]
+    opaque_name: bool,
+    /// Replace the input file with the encrypted file, atomically
+    #[arg(long, conflicts_with_all = ["output", "opaque_name"])]
+    in_place: bool,
+    /// After encrypting, overwrite the input 3 times and delete it. Old copies
+    /// may survive on SSDs and copy-on-write file systems
+    #[arg(long, conflicts_with = "in_place")]
+    shred: bool,
+    /// Write the generated key to this file instead of printing it
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["key_file", "key_env", "passphrase", "passphrase_file", "passphrase_env"])]
+    key_out: Option<PathBuf>,
+    #[command(flatten)]
+    vault: VaultArgs,
+    /// Overwrite the output file if it already exists
+    #[arg(short, long)]
+    force: bool,
+}
+
+/// Recording the file key in the key vault the GUI keeps.
+#[derive(Args)]
+struct VaultArgs {
+    /// Record the file key in the key vault, prompting for its master password
+    #[arg(long = "vault")]
+    record: bool,
+    /// Vault to record the key in [default: ~/.encora/vault.encora]
+    #[arg(long, value_name = "PATH", requires = "record")]
+    vault_file: Option<PathBuf>,
+    /// Read the vault's master password from an environment variable
+    #[arg(long, value_name = "VAR", requires = "record")]
+    vault_password_env: Option<String>,
+}
+
+#[derive(Args)]
+struct DecryptArgs {
+    /// Encora file to decrypt, or `-` for standard input
+    input: PathBuf,
+    /// Where to write the decrypted file or folder, or `-` for standard output
+    /// [default: the original name, or INPUT without its .encora extension]
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    #[command(flatten)]
+    unlock: UnlockArgs,
+    /// Replace the input file with the decrypted file, atomically
+    #[arg(long, conflicts_with = "output")]
+    in_place: bool,
+    /// Overwrite the output file if it already exists
+    #[arg(short, long)]
+    force: bool,
+}
+
+#[derive(Args)]
+struct ExtractArgs {
+    /// Encrypted folder to extract from
+    input: PathBuf,
+    /// Path of the entry inside the folder, as shown by `encora list`
+    path: String,
+    /// Where to write the entry, or `-` to write a file to standard output
+    /// [default: its name, in the current directory]
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    #[command(flatten)]
+    unlock: UnlockArgs,
+}
+
+#[derive(Args)]
+struct AddKeyslotArgs {
+    /// Encora file to change
+    file: PathBuf,
+    #[command(flatten)]
+    unlock: UnlockArgs,
+    #[command(flatten)]
+    new_slot: NewKeySlotArgs,
+    /// Overwrite the file given to --new-key-file if it already exists
+    #[arg(short, long)]
+    force: bool,
+}
+
+#[derive(Args)]
+struct RemoveKeyslotArgs {
+    /// Encora file to change
+    file: PathBuf,
+    /// Number of the keyslot to remove, as shown by `encora keyslot list`
+    #[arg(required_unless_present = "recipient")]
+    slot: Option<usize>,
+    /// Remove the keyslot of this public key instead
+    #[arg(long, value_name = "PUBKEY", value_parser = Recipient::parse, conflicts_with = "slot")]
+    recipient: Option<Recipient>,
+    #[command(flatten)]
+    unlock: UnlockArgs,
+}
+
+/// What opens an existing file: the options of [`KeyArgs`] or an identity.
+#[derive(Args)]
+struct UnlockArgs {
+    #[command(flatten)]
+    key: KeyArgs,
+    /// Unlock with the identity in this file (see `encora keygen`)
+    #[arg(short, long, value_name = "PATH", conflicts_with_all = ["key_file", "key_env", "passphrase", "passphrase_file", "passphrase_env"])]
+    identity: Option<PathBuf>,
+}
+
+/// What the new keyslot is unlocked with.
+#[derive(Args)]
+#[group(required = true, multiple = false)]
+struct NewKeySlotArgs {
+    /// Prompt for the passphrase of the new keyslot
+    #[arg(long)]
+    new_passphrase: bool,
+    /// Read the passphrase of the new keyslot from the first line of a file
+    #[arg(long, value_name = "PATH")]
+    new_passphrase_file: Option<PathBuf>,
+    /// Read the passphrase of the new keyslot from an environment variable
+    #[arg(long, value_name = "VAR")]
+    new_passphrase_env: Option<String>,
+    /// Generate a key for the new keyslot and write it to this key file
+    #[arg(long, value_name = "PATH")]
+    new_key_file: Option<PathBuf>,
+    /// Add a keyslot for this public key (`encora-pk-...`)
+    #[arg(long, value_name = "PUBKEY", value_parser = Recipient::parse)]
+    new_recipient: Option<Recipient>,
+}
+
+/// Where the key or passphrase comes from. Without any of these, encryption
+/// generates a random key and decryption prompts for what the file needs.
+#[derive(Args)]
+#[group(multiple = false)]
+struct KeyArgs {
+    /// Read the key from a `.encora-key` file or a file holding the hex key
+    #[arg(long, value_name = "PATH")]
+    key_file: Option<PathBuf>,
+    /// Read the hex-encoded key from an environment variable
+    #[arg(long, value_name = "VAR")]
+    key_env: Option<String>,
+    /// Prompt for a passphrase
+    #[arg(short, long)]
+    passphrase: bool,
+    /// Read the passphrase from the first line of a file
+    #[arg(long, value_name = "PATH")]
+    passphrase_file: Option<PathBuf>,
+    /// Read the passphrase from an environment variable
+    #[arg(long, value_name = "VAR")]
+    passphrase_env: Option<String>,
+}
+
+enum Credential {
+    Key(Key),
+    KeyFile(KeyFile),
+    Passphrase(Zeroizing<String>),
+    Identity(Identity),
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Command::Encrypt(args) => encrypt(args),
+        Command::Decrypt(args) => decrypt(args),
+        Command::List { input, unlock } => list(&input, &unlock),
+        Command::Extract(args) => extract(args),
+        Command::Info { input } => info(&input),
+        Command::Keygen { output } => keygen(output.as_deref()),
+        Command::Keyslot(KeyslotCommand::List { input }) => list_keyslots(&input),
+        Command::Keyslot(KeyslotCommand::Add(args)) => add_keyslot(args),
+        Command::Keyslot(KeyslotCommand::Remove(args)) => remove_keyslot(args),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("encora: {}", e);
+            if let Some(hint) = e.hint() {
+                eprintln!("hint: {}", hint);
+            }
+            ExitCode::from(exit_code(&e))
+        }
+    }
+}
+
+/// Accepts the short name of every registered algorithm.
+fn algorithm_parser() -> impl TypedValueParser<Value = Algorithm> {
+    PossibleValuesParser::new(
+        Algorithm::ALL
+            .iter()
+            .map(|algorithm| algorithm.short_name()),
+    )
+    .map(|name| Algorithm::from_name(&name).expect("offered by the parser"))
+}
+
+fn exit_code(error: &EncoraError) -> u8 {
+    match error {
+        EncoraError::InvalidKey(_) | EncoraError::InvalidInput(_) => EXIT_USAGE,
+        EncoraError::Io(_) | EncoraError::SourceModified(_) | EncoraError::Cancelled => {
+            EXIT_FAILURE
+        }
+        _ => EXIT_REJECTED,
+    }
+}
+
+fn encrypt(args: EncryptArgs) -> Result<()> {
+    if args.opaque_name && is_stdio(&args.input) {
+        return Err(EncoraError::InvalidInput(
+            "--opaque-name needs an input file".to_string(),
+        ));
+    }
+    if args.in_place && is_stdio(&args.input) {
+        return Err(EncoraError::InvalidInput(
+            "--in-place needs an input file".to_string(),
+        ));
+    }
+    if args.shred && is_stdio(&args.input) {
+        return Err(EncoraError::InvalidInput(
+            "--shred needs an input file".to_string(),
+        ));
+    }
+    if args.vault.record
+        && args
+            .output
+            .as_deref()
+            .map_or(is_stdio(&args.input), is_stdio)
+    {
+        return Err(EncoraError::InvalidInput(
+            "--vault needs an output file".to_string(),
+        ));
+    }
+    // A trailing slash on a folder would end up inside the generated name.
+    let input: PathBuf = args.input.components().collect();
+    let output = match args.output {
+        Some(output) => output,
+        None if is_stdio(&args.input) => PathBuf::from("-"),
+        None if args.in_place => input.clone(),
+        None if args.opaque_name => {
+            OutputLocation::AlongsideSource.opaque_encrypted_path(&input)?
+        }
+        None => {
+            let mut name = input.clone().into_os_string();
+            name.push(".");
+            name.push(EXTENSION);
+            PathBuf::from(name)
+        }
+    };
+
+    let mut options = EncryptOptions::new(args.algorithm);
+    options.recipients = args.recipient;
+    options.compression = args.compress;
+    let is_dir = args.input.is_dir();
+    if !is_stdio(&args.input) && !is_dir {
+        options.metadata = Some(FileMetadata::from_path(&args.input)?);
+    }
+    let generated = match read_credential(&args.key, true)? {
+        Some(Credential::Key(key)) => {
+            options.key = Some(key);
+            false
+        }
+        Some(Credential::KeyFile(key_file)) => {
+            options.key = Some(key_file.key);
+            false
+        }
+        Some(Credential::Passphrase(passphrase)) => {
+            options.passphrase = Some(passphrase);
+            false
+        }
+        Some(Credential::Identity(_)) => unreachable!("only read for decryption"),
+        // The recipients' identities open the file; the key itself is not needed.
+        None => options.recipients.is_empty(),
+    };
+
+    // Unlock the vault first, so a wrong master password costs no encryption.
+    let mut vault = if args.vault.record {
+        Some(open_vault(&args.vault)?)
+    } else {
+        None
+    };
+    // Create the key file before encrypting, so a generated key always has
+    // somewhere to go, and remove it again if encryption fails.
+    let key_out = match &args.key_out {
+        Some(path) if generated => Some((path, create_secret_file(path, args.force)?)),
+        _ => None,
+    };
+    let result = if args.in_place {
+        encora::encrypt_in_place(&input, &options)
+    } else {
+        write_output(&output, &input, args.force, |writer| {
+            if is_dir {
+                encora::encrypt_archive(&input, writer, &options)
+            } else {
+                encora::encrypt(&mut open_input(&input)?, writer, &options)
+            }
+        })
+    };
+    let key = match result {
+        Ok(key) => key,
+        Err(e) => {
+            if let Some((path, _)) = key_out {
+                let _ = std::fs::remove_file(path);
+            }
+            return Err(e);
+        }
+    };
+
+    if generated {
+        match key_out {
+            Some((path, mut file)) => {
+                writeln!(file, "{}", *key.to_hex())?;
+                eprintln!("Key written to {}", path.display());
+            }
+            // Keep standard output clean when it carries the ciphertext.
+            None if is_stdio(&output) => eprintln!("{}", *key.to_hex()),
+            None => println!("{}", *key.to_hex()),
+        }
+    }
+    // The key has already been shown or saved, so a failure here loses nothing.
+    if let Some(vault) = &mut vault {
+        let header = encora::read_header(&output)?;
+        let name = std::fs::canonicalize(&output).unwrap_or_else(|_| output.clone());
+        vault.record(&name.display().to_string(), KeyFile::new(&header, key))?;
+        eprintln!("Key recorded in {}", vault.path().display());
+    }
+    if args.in_place {
+        eprintln!("Encrypted {} in place", output.display());
+    } else if !is_stdio(&output) {
+        eprintln!("Encrypted to {}", output.display());
+    }
+    // Only once the encrypted file is complete.
+    if args.shred {
+        eprintln!(
+            "{}",
+            encora::shred(&input, encora::crypto::shred::DEFAULT_PASSES)?
+        );
+    }
+    Ok(())
+}
+
+fn decrypt(args: DecryptArgs) -> Result<()> {
+    // The header decides what to prompt for, so read it before anything else
+    // and hand it back to the decryptor in front of the rest of the input.
+    let mut input = open_input(&args.input)?;
+    let header = Header::read_from(&mut input)?;
+    let input = io::Cursor::new(header.to_bytes()).chain(input);
+
+    let to_stdout = args
+        .output
+        .as_deref()
+        .map_or(is_stdio(&args.input), is_stdio);
+    if header.archive {
+        check_folder_input(&args.input)?;
+        if args.in_place {
+            return Err(EncoraError::InvalidInput(
+                "An encrypted folder cannot be decrypted in place".into(),
+            ));
+        }
+        if to_stdout {
+            return Err(EncoraError::InvalidInput(
+                "An encrypted folder cannot be written to standard output; \
+                 use `encora extract` to write one file from it"
+                    .into(),
+            ));
+        }
+    }
+
+    if args.in_place && is_stdio(&args.input) {
+        return Err(EncoraError::InvalidInput(
+            "--in-place needs an input file".to_string(),
+        ));
+    }
+
+    let credential = read_unlock_credential(&args.unlock, &header)?;
+    let secret = credential.secret(&header)?;
+    if header.archive {
+        return extract_folder(&args, secret);
+    }
+    if args.in_place {
+        drop(input);
+        encora::decrypt_in_place(&args.input, secret, None)?;
+        eprintln!("Decrypted {} in place", args.input.display());
+        return Ok(());
+    }
+
+    // The output is only opened once the key is known to be right, and may be
+    // named after the original file.
+    let mut output = None;
+    let result = encora::decrypt_with(input, secret, |metadata| {
+        let path = args
+            .output
+            .clone()
+            .unwrap_or_else(|| default_decrypted_path(&args.input, metadata));
+        let writer = open_output(&path, &args.input, args.force)?;
+        output = Some(path);
+        Ok(writer)
+    });
+    let Some(output) = output else {
+        return result.map(|_| ());
+    };
+
+    let result = result.and_then(|(_, metadata, writer)| {
+        drop(writer);
+        match metadata {
+            Some(metadata) if !is_stdio(&output) => metadata.restore(&output),
+            _ => Ok(()),
+        }
+    });
+    if result.is_err() && !is_stdio(&output) {
+        let _ = std::fs::remove_file(&output);
+    }
+    result?;
+    if !is_stdio(&output) {
+        eprintln!("Decrypted to {}", output.display());
+    }
+    Ok(())
+}
+
+/// Decrypts an encrypted folder into a new directory, named after the
+/// original folder unless `-o` is given.
+fn extract_folder(args: &DecryptArgs, secret: Secret) -> Result<()> {
+    let mut archive = Archive::open(&args.input, secret)?;
+    let output = args
+        .output
+        .clone()
+        .unwrap_or_else(|| default_decrypted_path(&args.input, archive.metadata()));
+    archive.extract_all(&output)?;
+    eprintln!("Decrypted folder to {}", output.display());
+    Ok(())
+}
+
+/// Encrypted folders are read out of order, so they must come from a file.
+fn check_folder_input(input: &Path) -> Result<()> {
+    if is_stdio(input) {
+        return Err(EncoraError::InvalidInput(
+            "An encrypted folder cannot be read from standard input; save it to a file first"
+                .into(),
+        ));
+    }
+    Ok(())
+}
+
+fn open_archive(input: &Path, unlock: &UnlockArgs) -> Result<Archive<File>> {
+    check_folder_input(input)?;
+    let header = encora::read_header(input)?;
+    if !header.archive {
+        return Err(EncoraError::InvalidInput(format!(
+            "{} is an encrypted file, not a folder",
+            input.display()
+        )));
+    }
+    let credential = read_unlock_credential(unlock, &header)?;
+    Archive::open(input, credential.secret(&header)?)
+}
+
+fn list(input: &Path, unlock: &UnlockArgs) -> Result<()> {
+    let archive = open_archive(input, unlock)?;
+    for entry in archive.entries() {
+        let mut line = format!(
+            "{} {:>12}  {}",
+            permissions(entry.kind, entry.mode),
+            entry.size,
+            entry.path
+        );
+        match entry.kind {
+            EntryKind::Directory => line.push('/'),
+            EntryKind::Symlink => {
+                line.push_str(" -> ");
+                line.push_str(entry.link_target.as_deref().unwrap_or(""));
+            }
+            EntryKind::File => {}
+        }
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+/// `ls -l` style kind and permissions, e.g. `drwxr-xr-x`.
+fn permissions(kind: EntryKind, mode: Option<u32>) -> String {
+    let mut text = String::from(match kind {
+        EntryKind::File => '-',
+        EntryKind::Directory => 'd',
+        EntryKind::Symlink => 'l',
+    });
+    for shift in [6, 3, 0] {
+        for (bit, letter) in [(4, 'r'), (2, 'w'), (1, 'x')] {
+            let set = mode.is_some_and(|mode| mode >> shift & bit != 0);
+            text.push(if set { letter } else { '-' });
+        }
+    }
+    text
+}
+
+fn extract(args: ExtractArgs) -> Result<()> {
+    let mut archive = open_archive(&args.input, &args.unlock)?;
+    let entry = archive
+        .find(&args.path)
+        .ok_or_else(|| EncoraError::InvalidInput(format!("{} is not in the folder", args.path)))?;
+    let output = args
+        .output
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(entry.name()));
+    if is_stdio(&output) {
+        let mut stdout = io::stdout().lock();
+        io::copy(&mut archive.read(&args.path)?, &mut stdout)?;
+        stdout.flush()?;
+        return Ok(());
+    }
+    archive.extract(&args.path, &output)?;
+    eprintln!("Extracted {} to {}", args.path, output.display());
+    Ok(())
+}
+
+fn default_decrypted_path(input: &Path, metadata: Option<&FileMetadata>) -> PathBuf {
+    if is_stdio(input) {
+        PathBuf::from("-")
+    } else if let Some(name) = metadata.and_then(FileMetadata::file_name) {
+        input.with_file_name(name)
+    } else if input.extension().is_some_and(|ext| ext == EXTENSION) {
+        input.with_extension("")
+    } else {
+        let mut name = input.as_os_str().to_owned();
+        name.push(".decrypted");
+        PathBuf::from(name)
+    }
+}
+
+fn info(input: &Path) -> Result<()> {
+    let header = encora::read_header(input)?;
+    println!("Format version: {}", FORMAT_VERSION);
+    println!("Algorithm:      {}", header.algorithm);
+    println!("Chunk size:     {} bytes", header.chunk_size);
+    println!("File id:        {}", hex::encode(header.file_id));
+    println!(
+        "Contents:       {}",
+        if header.archive { "folder" } else { "file" }
+    );
+    println!(
+        "Compression:    {}",
+        if header.compressed { "zstd" } else { "none" }
+    );
+    println!(
+        "File metadata:  {}",
+        if header.metadata {
+            "name, size, time and permissions stored encrypted"
+        } else {
+            "none"
+        }
+    );
+    print_keyslots(&header);
+    Ok(())
+}
+
+fn list_keyslots(input: &Path) -> Result<()> {
+    print_keyslots(&encora::read_header(input)?);
+    Ok(())
+}
+
+fn print_keyslots(header: &Header) {
+    let used = header.keyslots.iter().flatten().count();
+    println!(
+        "Keyslots:       {} of {} in use{}",
+        used,
+        header.keyslots.len(),
+        if used == 0 {
+            " (opened by the file key only)"
+        } else {
+            ""
+        }
+    );
+    for (index, keyslot) in header.keyslots.iter().enumerate() {
+        if let Some(keyslot) = keyslot {
+            println!("  {}: {}", index, keyslot);
+        }
+    }
+}
+
+fn add_keyslot(args: AddKeyslotArgs) -> Result<()> {
+    let header = encora::read_header(&args.file)?;
+    let credential = read_unlock_credential(&args.unlock, &header)?;
+    let secret = credential.secret(&header)?;
+
+    let new = &args.new_slot;
+    let (passphrase, slot_key);
+    let new_slot = if let Some(recipient) = &new.new_recipient {
+        NewKeySlot::Recipient(recipient)
+    } else if let Some(path) = &new.new_key_file {
+        slot_key = Key::generate();
+        // Write the key file first, so a slot is never added for a key that was lost.
+        let mut file = create_secret_file(path, args.force)?;
+        file.write_all(KeyFile::new(&header, slot_key.clone()).to_text().as_bytes())?;
+        NewKeySlot::Key(&slot_key)
+    } else {
+        passphrase = read_new_passphrase(new)?;
+        NewKeySlot::Passphrase(&passphrase)
+    };
+
+    match encora::add_keyslot(&args.file, secret, new_slot) {
+        Ok(index) => {
+            eprintln!("Added keyslot {}", index);
+            if let Some(path) = &new.new_key_file {
+                eprintln!(
+                    "Key file for keyslot {} written to {}",
+                    index,
+                    path.display()
+                );
+            }
+            Ok(())
+        }
+        Err(e) => {
+            if let Some(path) = &new.new_key_file {
+                let _ = std::fs::remove_file(path);
+            }
+            Err(e)
+        }
+    }
+}
+
+fn remove_keyslot(args: RemoveKeyslotArgs) -> Result<()> {
+    let header = encora::read_header(&args.file)?;
+    let index = match (args.slot, &args.recipient) {
+        (Some(index), _) => index,
+        (None, Some(recipient)) => header
+            .keyslots
+            .iter()
+            .position(|keyslot| {
+                keyslot
+                    .as_ref()
+                    .is_some_and(|keyslot| keyslot.is_for_recipient(recipient))
+            })
+            .ok_or_else(|| EncoraError::InvalidInput(format!("No keyslot for {}", recipient)))?,
+        (None, None) => unreachable!("clap requires a slot or --recipient"),
+    };
+    let credential = read_unlock_credential(&args.unlock, &header)?;
+    let secret = credential.secret(&header)?;
+
+    let removed = encora::remove_keyslot(&args.file, secret, index)?;
+    eprintln!("Removed keyslot {}: {}", index, removed);
+    Ok(())
+}
+
+/// Unlocks the vault `args` name with its master password.
+fn open_vault(args: &VaultArgs) -> Result<Vault> {
+    let path = match &args.vault_file {
+        Some(path) => path.clone(),
+        None => Vault::default_path().ok_or_else(|| {
+            EncoraError::InvalidInput(
+                "No home folder to find the vault in; pass --vault-file".into(),
+            )
+        })?,
+    };
+    if !path.exists() {
+        return Err(EncoraError::InvalidInput(format!(
+            "No vault at {}; create one in the app first",
+            path.display()
+        )));
+    }
+    let password = match &args.vault_password_env {
+        Some(var) => env_var(var)?,
+        None => prompt("Vault master password: ")?,
+    };
+    Vault::open(&path, &password)
+}
+
+fn keygen(output: Option<&Path>) -> Result<()> {
+    let identity = Identity::generate();
+    match output {
+        Some(path) => {
+            identity.save(path)?;
+            eprintln!("Identity written to {}", path.display());
+        }
+        None => print!("{}", *identity.to_text()),
+    }
+    eprintln!("Public key: {}", identity.recipient());
+    Ok(())
+}
+
+impl Credential {
+    fn secret(&self, header: &Header) -> Result<Secret<'_>> {
+        Ok(match self {
+            Credential::Key(key) => Secret::Key(key),
+            Credential::KeyFile(key_file) if !key_file.matches(header) => {
+                return Err(EncoraError::InvalidKey(
+                    "key file belongs to a different encrypted file".into(),
+                ));
+            }
+            Credential::KeyFile(key_file) => Secret::Key(&key_file.key),
+            Credential::Passphrase(passphrase) => Secret::Passphrase(passphrase),
+            Credential::Identity(identity) => Secret::Identity(identity),
+        })
+    }
+}
+
+/// Reads what opens the file with `header`, prompting for whatever its
+/// keyslots accept when nothing was given on the command line.
+fn read_unlock_credential(args: &UnlockArgs, header: &Header) -> Result<Credential> {
+    if let Some(path) = &args.identity {
+        return Ok(Credential::Identity(Identity::load(path)?));
+    }
+    match read_credential(&args.key, false)? {
+        Some(credential) => Ok(credential),
+        None if header.has_keyslot(KeySlotKind::Passphrase) => {
+            Ok(Credential::Passphrase(prompt("Passphrase: ")?))
+        }
+        None if header.has_keyslot(KeySlotKind::Recipient) => Err(EncoraError::InvalidInput(
+            "File was encrypted to public keys; pass --identity with your identity file".into(),
+        )),
+        None => Ok(Credential::Key(Key::from_hex(&prompt("Key: ")?)?)),
+    }
+}
+
+fn read_new_passphrase(args: &NewKeySlotArgs) -> Result<Zeroizing<String>> {
+    if let Some(path) = &args.new_passphrase_file {
+        return read_passphrase_file(path);
+    }
+    if let Some(var) = &args.new_passphrase_env {
+        return env_var(var);
+    }
+    let passphrase = prompt("New passphrase: ")?;
+    if prompt("Confirm new passphrase: ")? != passphrase {
+        return Err(EncoraError::InvalidKey("passphrases do not match".into()));
+    }
+    Ok(passphrase)
+}
+
+/// Returns `None` when no key source was given on the command line.
+fn read_credential(args: &KeyArgs, confirm: bool) -> Result<Option<Credential>> {
+    if let Some(path) = &args.key_file {
+        let contents = Zeroizing::new(std::fs::read_to_string(path)?);
+        if KeyFile::is_key_file(&contents) {
+            return KeyFile::parse(&contents).map(|k| Some(Credential::KeyFile(k)));
+        }
+        return Key::from_hex(&contents).map(|k| Some(Credential::Key(k)));
+    }
+    if let Some(var) = &args.key_env {
+        return Key::from_hex(&env_var(var)?).map(|k| Some(Credential::Key(k)));
+    }
+    if let Some(path) = &args.passphrase_file {
+        return read_passphrase_file(path).map(|p| Some(Credential::Passphrase(p)));
+    }
+    if let Some(var) = &args.passphrase_env {
+        return Ok(Some(Credential::Passphrase(env_var(var)?)));
+    }
+    if args.passphrase {
+        let passphrase = prompt("Passphrase: ")?;
+        if confirm && prompt("Confirm passphrase: ")? != passphrase {
+            return Err(EncoraError::InvalidKey("passphrases do not match".into()));
+        }
+        return Ok(Some(Credential::Passphrase(passphrase)));
+    }
+    Ok(None)
+}
+
+/// The first line of the file at `path`.
+fn read_passphrase_file(path: &Path) -> Result<Zeroizing<String>> {
+    let contents = Zeroizing::new(std::fs::read_to_string(path)?);
+    Ok(Zeroizing::new(
+        contents.lines().next().unwrap_or("").to_string(),
+    ))
+}
+
+fn env_var(name: &str) -> Result<Zeroizing<String>> {
+    std::env::var(name)
+        .map(Zeroizing::new)
+        .map_err(|e| EncoraError::InvalidInput(format!("Environment variable {}: {}", name, e)))
+}
+
+fn prompt(message: &str) -> Result<Zeroizing<String>> {
+    Ok(Zeroizing::new(rpassword::prompt_password(message)?))
+}
+
+fn is_stdio(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+fn open_input(path: &Path) -> Result<Box<dyn Read>> {
+    if is_stdio(path) {
+        Ok(Box::new(io::stdin().lock()))
+    } else {
+        Ok(Box::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+/// Runs `f` against the output, removing a partially written file on error.
+fn write_output<T>(
+    path: &Path,
+    input: &Path,
+    force: bool,
+    f: impl FnOnce(&mut dyn Write) -> Result<T>,
+) -> Result<T> {
+    let mut writer = open_output(path, input, force)?;
+    let result = f(&mut writer).and_then(|value| Ok(writer.flush().map(|()| value)?));
+    drop(writer);
+    if result.is_err() && !is_stdio(path) {
+        let _ = std::fs::remove_file(path);
+    }
+    result
+}
+
+/// Opens standard output or creates the file at `path`, refusing to replace
+/// an existing file unless `force` is set, and always refusing to replace
+/// `input`, which is still to be read.
+fn open_output(path: &Path, input: &Path, force: bool) -> Result<Box<dyn Write>> {
+    if is_stdio(path) {
+        return Ok(Box::new(io::stdout().lock()));
+    }
+    if !is_stdio(input) && encora::crypto::same_file(path, input) {
+        return Err(EncoraError::InvalidInput(format!(
+            "{} is also the input; choose another output or use --in-place",
+            path.display()
+        )));
+    }
+
+    let mut options = OpenOptions::new();
+    options.write(true);
+    if force {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+    let file = options.open(path).map_err(|e| match e.kind() {
+        io::ErrorKind::AlreadyExists => io::Error::new(
+            e.kind(),
+            format!(
+                "{} already exists (use --force to overwrite)",
+                path.display()
+            ),
+        ),
+        _ => e,
+    })?;
+    Ok(Box::new(BufWriter::new(file)))
+}
+
+/// Creates a file only the current user can read, refusing to replace an
+/// existing file unless `force` is set.
+fn create_secret_file(path: &Path, force: bool) -> Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true);
+    if force {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let file = options.open(path).map_err(|e| match e.kind() {
+        io::ErrorKind::AlreadyExists => io::Error::new(
+            e.kind(),
+            format!(
+                "{} already exists (use --force to overwrite)",
+                path.display()
+            ),
+        ),
+        _ => io::Error::new(e.kind(), format!("{}: {}", path.display(), e)),
+    })?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A new, empty directory under the system's temporary directory.
+    fn scratch_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("encora-cli-{:016x}", rand::random::<u64>()));
+        std::fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    /// Runs `encora encrypt` with `args`.
+    fn encrypt_with(args: &[&std::ffi::OsStr]) -> Result<()> {
+        let cli = Cli::try_parse_from(
+            [std::ffi::OsStr::new("encora"), "encrypt".as_ref()]
+                .iter()
+                .chain(args),
+        )
+        .unwrap();
+        match cli.command {
+            Command::Encrypt(args) => encrypt(args),
+            _ => unreachable!("parsed an encrypt command"),
+        }
+    }
+
+    #[test]
+    fn keeps_the_original_when_the_key_file_exists() {
+        let dir = scratch_dir();
+        let (input, key_out) = (dir.join("a.txt"), dir.join("k.key"));
+        std::fs::write(&input, b"plaintext").unwrap();
+        std::fs::write(&key_out, b"old key").unwrap();
+
+        let error = encrypt_with(&[
+            input.as_os_str(),
+            "--in-place".as_ref(),
+            "--key-out".as_ref(),
+            key_out.as_os_str(),
+        ])
+        .unwrap_err();
+        assert!(error.to_string().contains(&key_out.display().to_string()));
+        assert_eq!(std::fs::read(&input).unwrap(), b"plaintext");
+        assert_eq!(std::fs::read(&key_out).unwrap(), b"old key");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn writes_no_ciphertext_when_the_key_file_exists() {
+        let dir = scratch_dir();
+        let (input, key_out) = (dir.join("a.txt"), dir.join("k.key"));
+        std::fs::write(&input, b"plaintext").unwrap();
+        std::fs::write(&key_out, b"old key").unwrap();
+
+        encrypt_with(&[input.as_os_str(), "--key-out".as_ref(), key_out.as_os_str()]).unwrap_err();
+        assert!(!dir.join("a.txt.encora").exists());
+        assert_eq!(std::fs::read(&key_out).unwrap(), b"old key");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn removes_the_key_file_when_encryption_fails() {
+        let dir = scratch_dir();
+        let (input, output, key_out) = (
+            dir.join("a.txt"),
+            dir.join("a.txt.encora"),
+            dir.join("k.key"),
+        );
+        std::fs::write(&input, b"plaintext").unwrap();
+        std::fs::write(&output, b"existing").unwrap();
+
+        encrypt_with(&[input.as_os_str(), "--key-out".as_ref(), key_out.as_os_str()]).unwrap_err();
+        assert!(!key_out.exists());
+        assert_eq!(std::fs::read(&output).unwrap(), b"existing");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn records_the_file_key_in_the_vault() {
+        let dir = scratch_dir();
+        let (input, output, vault) = (
+            dir.join("a.txt"),
+            dir.join("a.txt.encora"),
+            dir.join("vault.encora"),
+        );
+        std::fs::write(&input, b"plaintext").unwrap();
+        Vault::create(&vault, "master").unwrap();
+        std::env::set_var("ENCORA_TEST_VAULT_PASSWORD", "master");
+
+        encrypt_with(&[
+            input.as_os_str(),
+            "--passphrase-env".as_ref(),
+            "ENCORA_TEST_VAULT_PASSWORD".as_ref(),
+            "--vault".as_ref(),
+            "--vault-file".as_ref(),
+            vault.as_os_str(),
+            "--vault-password-env".as_ref(),
+            "ENCORA_TEST_VAULT_PASSWORD".as_ref(),
+        ])
+        .unwrap();
+        let vault = Vault::open(&vault, "master").unwrap();
+        let header = encora::read_header(&output).unwrap();
+        let key_file = vault.find(&header).unwrap();
+        let mut plaintext = Vec::new();
+        encora::decrypt(
+            File::open(&output).unwrap(),
+            &mut plaintext,
+            Secret::Key(&key_file.key),
+        )
+        .unwrap();
+        assert_eq!(plaintext, b"plaintext");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_to_record_in_the_vault_without_an_output_file() {
+        let error = encrypt_with(&["-".as_ref(), "--vault".as_ref()]).unwrap_err();
+        assert!(matches!(error, EncoraError::InvalidInput(_)));
+    }
+}
@@ -0,0 +1,999 @@
+//! Directory trees packed into a single payload, so a folder is encrypted as
+//! one Encora file whose entries can be listed and read one at a time.
+//!
+//! When the header has the archive flag set, the plaintext after the metadata
+//! block is:
+//!
+//! ```text
+//! entry data   the contents of every file back to back, each compressed on
+//!              its own with zstd when compression was asked for and helps
+//! index        u32 entry count, then one record per entry
+//! trailer      u64 offset and u64 length of the index
+//! ```
+//!
+//! Offsets are relative to the start of the entry data. An index record is:
+//!
+//! ```text
+//! u8   kind (1 file, 2 directory, 3 symbolic link)
+//! u8   fields present (0x01 compressed, 0x02 modification time, 0x04 permissions)
+//! u64  size in bytes
+//! i64  modification time, seconds since the Unix epoch
+//! u32  modification time, nanoseconds
+//! u32  Unix permission bits
+//! u64  offset of the stored data
+//! u64  length of the stored data
+//! u16  path length, followed by the UTF-8 path with `/` between names
+//! u16  link target length, followed by the UTF-8 link target
+//! ```
+//!
+//! Entries are in depth-first name order, every directory before its
+//! contents. The payload is encrypted in chunks like any other, so the index
+//! is as confidential and authenticated as the data. [`Archive`] decrypts the
+//! first chunk to check the key and the last ones to read the index, and
+//! after that only the chunks holding the entries asked for. Opening refuses
+//! an index with any path that leaves the folder, or link that points out of
+//! it or through another link.
+
+use super::compress;
+use super::error::{EncoraError, Result};
+use super::header::Header;
+use super::key::{Key, Secret};
+use super::keyslot::unlock;
+use super::metadata::{self, FileMetadata};
+use super::progress::{self, Progress};
+use super::stream::TAG_LEN;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path};
+use std::time::SystemTime;
+use zeroize::Zeroizing;
+
+const KIND_FILE: u8 = 1;
+const KIND_DIRECTORY: u8 = 2;
+const KIND_SYMLINK: u8 = 3;
+
+const COMPRESSED: u8 = 0x01;
+const HAS_MODIFIED: u8 = 0x02;
+const HAS_MODE: u8 = 0x04;
+
+/// Index offset and length at the very end of the payload.
+const TRAILER_LEN: u64 = 16;
+
+/// What an archive entry is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    Symlink,
+}
+
+impl EntryKind {
+    fn id(self) -> u8 {
+        match self {
+            EntryKind::File => KIND_FILE,
+            EntryKind::Directory => KIND_DIRECTORY,
+            EntryKind::Symlink => KIND_SYMLINK,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            KIND_FILE => Some(EntryKind::File),
+            KIND_DIRECTORY => Some(EntryKind::Directory),
+            KIND_SYMLINK => Some(EntryKind::Symlink),
+            _ => None,
+        }
+    }
+}
+
+/// A file, directory or link in an encrypted folder.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Path inside the folder, with `/` between names.
+    pub path: String,
+    pub kind: EntryKind,
+    /// Size of a file's contents in bytes; zero for anything else.
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    /// Unix permission bits.
+    pub mode: Option<u32>,
+    /// Where a symbolic link points.
+    pub link_target: Option<String>,
+    offset: u64,
+    stored_len: u64,
+    compressed: bool,
+}
+
+impl Entry {
+    /// Last name in the path.
+    pub fn name(&self) -> &str {
+        self.path
+            .rsplit_once('/')
+            .map_or(self.path.as_str(), |(_, name)| name)
+    }
+
+    /// Path of the directory holding the entry, or `None` at the top level.
+    pub fn parent(&self) -> Option<&str> {
+        self.path.rsplit_once('/').map(|(parent, _)| parent)
+    }
+
+    fn metadata(&self) -> FileMetadata {
+        FileMetadata {
+            name: self.name().to_string(),
+            size: self.size,
+            modified: self.modified,
+            mode: self.mode,
+        }
+    }
+
+    fn write_to(&self, bytes: &mut Vec<u8>) {
+        let (seconds, nanos) = self.modified.map_or((0, 0), metadata::unix_time);
+        let target = self.link_target.as_deref().unwrap_or("");
+        let mut fields = 0;
+        if self.compressed {
+            fields |= COMPRESSED;
+        }
+        if self.modified.is_some() {
+            fields |= HAS_MODIFIED;
+        }
+        if self.mode.is_some() {
+            fields |= HAS_MODE;
+        }
+
+        bytes.push(self.kind.id());
+        bytes.push(fields);
+        bytes.extend_from_slice(&self.size.to_be_bytes());
+        bytes.extend_from_slice(&seconds.to_be_bytes());
+        bytes.extend_from_slice(&nanos.to_be_bytes());
+        bytes.extend_from_slice(&self.mode.unwrap_or(0).to_be_bytes());
+        bytes.extend_from_slice(&self.offset.to_be_bytes());
+        bytes.extend_from_slice(&self.stored_len.to_be_bytes());
+        for text in [self.path.as_str(), target] {
+            bytes.extend_from_slice(&(text.len() as u16).to_be_bytes());
+            bytes.extend_from_slice(text.as_bytes());
+        }
+    }
+
+    fn read_from(bytes: &mut &[u8]) -> Result<Self> {
+        let [kind, fields] = take(bytes)?;
+        let kind = EntryKind::from_id(kind).ok_or_else(|| invalid("unknown entry kind"))?;
+        let size = u64::from_be_bytes(take(bytes)?);
+        let seconds = i64::from_be_bytes(take(bytes)?);
+        let nanos = u32::from_be_bytes(take(bytes)?);
+        let mode = u32::from_be_bytes(take(bytes)?);
+        let offset = u64::from_be_bytes(take(bytes)?);
+        let stored_len = u64::from_be_bytes(take(bytes)?);
+        let path = take_text(bytes)?;
+        let target = take_text(bytes)?;
+        Ok(Self {
+            path,
+            kind,
+            size,
+            modified: (fields & HAS_MODIFIED != 0)
+                .then(|| metadata::from_unix_time(seconds, nanos))
+                .flatten(),
+            mode: (fields & HAS_MODE != 0).then_some(mode),
+            link_target: (kind == EntryKind::Symlink).then_some(target),
+            offset,
+            stored_len,
+            compressed: fields & COMPRESSED != 0,
+        })
+    }
+}
+
+/// Writes the tree under `dir` to `writer` as an archive and returns the
+/// writer. With a `compression` level, each file is compressed unless a
+/// trial shows it does not shrink. The files are counted in `progress` as
+/// they are read.
+pub fn write_archive<W: Write>(
+    dir: &Path,
+    writer: W,
+    compression: Option<i32>,
+    progress: Option<&Progress>,
+) -> Result<W> {
+    let mut writer = CountingWriter {
+        inner: writer,
+        written: 0,
+    };
+    let mut entries = Vec::new();
+    append_tree(&mut writer, &mut entries, dir, "", compression, progress)?;
+
+    let index_offset = writer.written;
+    let mut index = (entries.len() as u32).to_be_bytes().to_vec();
+    for entry in &entries {
+        entry.write_to(&mut index);
+    }
+    writer.write_all(&index)?;
+    writer.write_all(&index_offset.to_be_bytes())?;
+    writer.write_all(&(index.len() as u64).to_be_bytes())?;
+    Ok(writer.inner)
+}
+
+/// Writes the files in `dir` in name order and adds entries for everything
+/// in it, with paths under `prefix`.
+fn append_tree<W: Write>(
+    writer: &mut CountingWriter<W>,
+    entries: &mut Vec<Entry>,
+    dir: &Path,
+    prefix: &str,
+    compression: Option<i32>,
+    progress: Option<&Progress>,
+) -> Result<()> {
+    let mut children = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    children.sort_by_key(|child| child.file_name());
+    for child in children {
+        let path = child.path();
+        let name = child
+            .file_name()
+            .into_string()
+            .map_err(|_| cannot_archive(&path, "its name is not valid UTF-8"))?;
+        let stat = FileMetadata::from_metadata(String::new(), &child.metadata()?);
+        let mut entry = Entry {
+            path: format!("{}{}", prefix, name),
+            kind: EntryKind::File,
+            size: 0,
+            modified: stat.modified,
+            mode: stat.mode,
+            link_target: None,
+            offset: writer.written,
+            stored_len: 0,
+            compressed: false,
+        };
+
+        let file_type = child.file_type()?;
+        if file_type.is_dir() {
+            entry.kind = EntryKind::Directory;
+        } else if file_type.is_file() {
+            let file = progress::track(File::open(&path)?, progress);
+            (entry.size, entry.compressed) = write_contents(&mut *writer, file, compression)?;
+            entry.stored_len = writer.written - entry.offset;
+        } else if file_type.is_symlink() {
+            entry.kind = EntryKind::Symlink;
+            entry.link_target = Some(
+                fs::read_link(&path)?
+                    .into_os_string()
+                    .into_string()
+                    .map_err(|_| cannot_archive(&path, "its link target is not valid UTF-8"))?,
+            );
+        } else {
+            return Err(cannot_archive(
+                &path,
+                "only files, folders and symbolic links are supported",
+            ));
+        }
+        let target_len = entry.link_target.as_ref().map_or(0, String::len);
+        if entry.path.len().max(target_len) > u16::MAX as usize {
+            return Err(cannot_archive(&path, "its path is too long"));
+        }
+
+        let subdir = (entry.kind == EntryKind::Directory).then(|| format!("{}/", entry.path));
+        entries.push(entry);
+        if let Some(subdir) = subdir {
+            append_tree(writer, entries, &path, &subdir, compression, progress)?;
+        }
+    }
+    Ok(())
+}
+
+/// Copies `file` to `writer`, compressed at `compression` if a trial shows
+/// it helps. Returns the number of bytes read and whether they were
+/// compressed.
+fn write_contents<W: Write, R: Read>(
+    mut writer: W,
+    mut file: R,
+    compression: Option<i32>,
+) -> Result<(u64, bool)> {
+    let Some(level) = compression else {
+        return Ok((io::copy(&mut file, &mut writer)?, false));
+    };
+    let (compressible, sample) = compress::sample(&mut file, level)?;
+    let mut contents = Cursor::new(sample).chain(file);
+    if !compressible {
+        return Ok((io::copy(&mut contents, &mut writer)?, false));
+    }
+    let mut encoder = compress::encoder(writer, level)?;
+    let size = io::copy(&mut contents, &mut encoder)?;
+    encoder.finish()?;
+    Ok((size, true))
+}
+
+/// Total size of the files in the tree under `dir`, which is what
+/// [`write_archive`] will read.
+pub(crate) fn tree_size(dir: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    for child in fs::read_dir(dir)? {
+        let child = child?;
+        let file_type = child.file_type()?;
+        if file_type.is_dir() {
+            size += tree_size(&child.path())?;
+        } else if file_type.is_file() {
+            size += child.metadata()?.len();
+        }
+    }
+    Ok(size)
+}
+
+/// Counts the bytes written, which gives the offsets stored in the index.
+struct CountingWriter<W> {
+    inner: W,
+    written: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// An encrypted folder opened for listing its entries and reading or
+/// extracting them one at a time.
+pub struct Archive<R> {
+    plaintext: Plaintext<R>,
+    metadata: Option<FileMetadata>,
+    /// Offset of the entry data in the plaintext.
+    data_start: u64,
+    entries: Vec<Entry>,
+}
+
+impl Archive<File> {
+    /// Opens the encrypted folder at `path`.
+    pub fn open<P: AsRef<Path>>(path: P, secret: Secret) -> Result<Self> {
+        Self::new(File::open(path.as_ref())?, secret)
+    }
+}
+
+impl<R: Read + Seek> Archive<R> {
+    /// Reads the header of the encrypted folder in `reader`, unlocks it and
+    /// reads the index of its entries.
+    pub fn new(mut reader: R, secret: Secret) -> Result<Self> {
+        let header = Header::read_from(&mut reader)?;
+        if !header.archive {
+            return Err(EncoraError::InvalidInput(
+                "This is an encrypted file, not a folder; decrypt it with decrypt_file".into(),
+            ));
+        }
+        let (key, unlocked_by) = unlock(&header, secret)?;
+        let mut plaintext = Plaintext::new(reader, key, header, unlocked_by.is_some())?;
+        let metadata = if plaintext.header.metadata {
+            Some(FileMetadata::read_from(&mut plaintext)?)
+        } else {
+            None
+        };
+
+        let data_start = plaintext.position;
+        let data_len = plaintext.len - data_start;
+        if data_len < TRAILER_LEN {
+            return Err(invalid("the folder ends before its index"));
+        }
+        plaintext.position = plaintext.len - TRAILER_LEN;
+        let index_offset = u64::from_be_bytes(read_array(&mut plaintext)?);
+        let index_len = u64::from_be_bytes(read_array(&mut plaintext)?);
+        if index_offset.checked_add(index_len) != Some(data_len - TRAILER_LEN) {
+            return Err(invalid("the index is out of place"));
+        }
+        plaintext.position = data_start + index_offset;
+        let mut index = Vec::new();
+        (&mut plaintext).take(index_len).read_to_end(&mut index)?;
+        let entries = read_index(&index, index_offset)?;
+
+        Ok(Self {
+            plaintext,
+            metadata,
+            data_start,
+            entries,
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.plaintext.header
+    }
+
+    /// The name, modification time and permissions of the folder itself.
+    pub fn metadata(&self) -> Option<&FileMetadata> {
+        self.metadata.as_ref()
+    }
+
+    /// Every entry, each directory before its contents.
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// The entry at `path`, with `/` between names.
+    pub fn find(&self, path: &str) -> Option<&Entry> {
+        let path = path.trim_matches('/');
+        self.entries.iter().find(|entry| entry.path == path)
+    }
+
+    /// Counts the bytes of entry data read from now on in `progress`, and
+    /// makes reading fail with [`EncoraError::Cancelled`] once it is
+    /// cancelled. [`extract`](Self::extract) sets its total to the data it
+    /// will read. `None` stops counting.
+    pub fn set_progress(&mut self, progress: Option<&Progress>) {
+        self.plaintext.progress = progress.cloned();
+    }
+
+    /// Reader over the contents of the file at `path`. Only the chunks that
+    /// hold it are decrypted.
+    pub fn read(&mut self, path: &str) -> Result<Box<dyn Read + '_>> {
+        let entry = self.entry(path)?.clone();
+        if entry.kind != EntryKind::File {
+            return Err(EncoraError::InvalidInput(format!(
+                "{} is not a file",
+                entry.path
+            )));
+        }
+        self.open_entry(&entry)
+    }
+
+    /// Extracts the entry at `path` to `output`, which must not exist yet: a
+    /// file with its contents, a link, or a directory with everything in it.
+    /// Nothing is left at `output` if that fails. Links must point inside
+    /// what is extracted, so a link is never extracted on its own.
+    pub fn extract<P: AsRef<Path>>(&mut self, path: &str, output: P) -> Result<()> {
+        let entry = self.entry(path)?.clone();
+        let prefix = format!("{}/", entry.path);
+        let kinds: HashMap<String, EntryKind> = self
+            .entries
+            .iter()
+            .map(|entry| (entry.path.clone(), entry.kind))
+            .collect();
+        for link in self
+            .entries
+            .iter()
+            .filter(|other| other.path == entry.path || other.path.starts_with(&prefix))
+        {
+            if let Some(target) = &link.link_target {
+                check_link(&link.path, target, &kinds, &entry.path)?;
+            }
+        }
+        let total = self
+            .entries
+            .iter()
+            .filter(|other| other.path == entry.path || other.path.starts_with(&prefix))
+            .map(|other| other.stored_len)
+            .sum();
+        progress::set_total(self.plaintext.progress.as_ref(), total);
+        match entry.kind {
+            EntryKind::Directory => {
+                self.extract_dir(&prefix, Some(entry.metadata()), output.as_ref())
+            }
+            _ => self.extract_entry(&entry, output.as_ref()),
+        }
+    }
+
+    /// Extracts the whole folder into a new directory at `output`, which
+    /// must not exist yet, and gives it the stored modification time and
+    /// permissions. Nothing is left at `output` if that fails.
+    pub fn extract_all<P: AsRef<Path>>(&mut self, output: P) -> Result<()> {
+        let metadata = self.metadata.clone();
+        self.extract_dir("", metadata, output.as_ref())
+    }
+
+    fn entry(&self, path: &str) -> Result<&Entry> {
+        self.find(path)
+            .ok_or_else(|| EncoraError::InvalidInput(format!("{} is not in the folder", path)))
+    }
+
+    fn open_entry(&mut self, entry: &Entry) -> Result<Box<dyn Read + '_>> {
+        self.plaintext.position = self.data_start + entry.offset;
+        let stored = (&mut self.plaintext).take(entry.stored_len);
+        if entry.compressed {
+            Ok(compress::decompressor(stored)?)
+        } else {
+            Ok(Box::new(stored))
+        }
+    }
+
+    /// Creates the directory `output` and extracts the entries under
+    /// `prefix` into it.
+    fn extract_dir(
+        &mut self,
+        prefix: &str,
+        metadata: Option<FileMetadata>,
+        output: &Path,
+    ) -> Result<()> {
+        create_new(output, |path| fs::create_dir(path))?;
+        let result = self
+            .extract_tree(prefix, output)
+            .and_then(|()| metadata.map_or(Ok(()), |metadata| metadata.restore(output)));
+        if result.is_err() {
+            let _ = fs::remove_dir_all(output);
+        }
+        result
+    }
+
+    fn extract_tree(&mut self, prefix: &str, root: &Path) -> Result<()> {
+        let entries: Vec<Entry> = self
+            .entries
+            .iter()
+            .filter(|entry| entry.path.starts_with(prefix))
+            .cloned()
+            .collect();
+        // Links are made last so nothing is ever written through one, and
+        // directories get their times and permissions once their contents
+        // are written, deepest first, so read-only ones can still be filled.
+        let (links, entries): (Vec<_>, Vec<_>) = entries
+            .iter()
+            .partition(|entry| entry.kind == EntryKind::Symlink);
+        let mut dirs = Vec::new();
+        for entry in entries.into_iter().chain(links) {
+            let target = root.join(&entry.path[prefix.len()..]);
+            if entry.kind == EntryKind::Directory {
+                create_new(&target, |path| fs::create_dir(path))?;
+                dirs.push((target, entry));
+            } else {
+                self.extract_entry(entry, &target)?;
+            }
+        }
+        for (dir, entry) in dirs.iter().rev() {
+            entry.metadata().restore(dir)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the file or link `entry` to the new path `path`.
+    fn extract_entry(&mut self, entry: &Entry, path: &Path) -> Result<()> {
+        if let Some(target) = &entry.link_target {
+            return create_new(path, |path| symlink(target, path));
+        }
+        let file = create_new(path, |path| {
+            File::options().write(true).create_new(true).open(path)
+        })?;
+        let result = self
+            .copy_entry(entry, file)
+            .and_then(|()| entry.metadata().restore(path));
+        if result.is_err() {
+            let _ = fs::remove_file(path);
+        }
+        result
+    }
+
+    fn copy_entry(&mut self, entry: &Entry, file: File) -> Result<()> {
+        let mut writer = BufWriter::new(file);
+        let size = io::copy(&mut self.open_entry(entry)?, &mut writer)?;
+        writer.flush()?;
+        if size != entry.size {
+            return Err(invalid(format!(
+                "{} is not the size it should be",
+                entry.path
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// The decrypted payload of an Encora file, read from wherever it is asked
+/// for by decrypting the chunk that holds it.
+struct Plaintext<R> {
+    reader: R,
+    key: Key,
+    header: Header,
+    /// Offset of the first chunk in `reader`.
+    start: u64,
+    chunks: u64,
+    len: u64,
+    position: u64,
+    /// Which chunk `chunk` holds, if any.
+    loaded: Option<u64>,
+    /// Wiped when dropped, as it holds plaintext.
+    chunk: Zeroizing<Vec<u8>>,
+    /// Whether a keyslot or an opened chunk has shown the key to be right.
+    key_proven: bool,
+    /// Counts the bytes read, and stops reading once cancelled.
+    progress: Option<Progress>,
+}
+
+impl<R: Read + Seek> Plaintext<R> {
+    /// Works out the chunks that follow the header in `reader` and opens the
+    /// first, which proves the key unless `key_proven` says a keyslot did.
+    fn new(mut reader: R, key: Key, header: Header, key_proven: bool) -> Result<Self> {
+        let start = reader.stream_position()?;
+        let end = reader.seek(SeekFrom::End(0))?;
+        let chunk_size = header.chunk_size as u64;
+        let sealed = chunk_size + TAG_LEN as u64;
+        let sealed_len = end.saturating_sub(start);
+        let chunks = sealed_len.div_ceil(sealed).max(1);
+        let last_len = sealed_len - (chunks - 1) * sealed;
+        if last_len < TAG_LEN as u64 {
+            return Err(EncoraError::Truncated);
+        }
+        if chunks - 1 > u32::MAX as u64 {
+            return Err(io::Error::other("File is too large for the chunk counter").into());
+        }
+
+        let mut plaintext = Self {
+            reader,
+            key,
+            header,
+            start,
+            chunks,
+            len: (chunks - 1) * chunk_size + last_len - TAG_LEN as u64,
+            position: 0,
+            loaded: None,
+            // Room for a whole chunk up front: `Zeroizing` only wipes the
+            // buffer it ends with, and growing would free earlier ones unwiped.
+            chunk: Zeroizing::new(Vec::with_capacity(sealed as usize)),
+            key_proven,
+            progress: None,
+        };
+        plaintext.load(0)?;
+        Ok(plaintext)
+    }
+
+    fn load(&mut self, index: u64) -> Result<()> {
+        if self.loaded == Some(index) {
+            return Ok(());
+        }
+        self.loaded = None;
+        let chunk_size = self.header.chunk_size as u64;
+        let last = index + 1 == self.chunks;
+        let len = if last {
+            self.len - index * chunk_size
+        } else {
+            chunk_size
+        };
+        self.chunk.resize(len as usize + TAG_LEN, 0);
+        self.reader.seek(SeekFrom::Start(
+            self.start + index * (chunk_size + TAG_LEN as u64),
+        ))?;
+        self.reader.read_exact(&mut self.chunk)?;
+        self.header.algorithm.cipher().decrypt_chunk(
+            &self.key,
+            &self.header,
+            index as u32,
+            last,
+            self.key_proven,
+            &mut self.chunk,
+        )?;
+        self.loaded = Some(index);
+        self.key_proven = true;
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for Plaintext<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.progress.as_ref().is_some_and(Progress::is_cancelled) {
+            return Err(EncoraError::Cancelled.into());
+        }
+        let chunk_size = self.header.chunk_size as u64;
+        let index = self.position / chunk_size;
+        self.load(index)?;
+        let offset = (self.position - index * chunk_size) as usize;
+        let n = buf.len().min(self.chunk.len() - offset);
+        buf[..n].copy_from_slice(&self.chunk[offset..offset + n]);
+        self.position += n as u64;
+        if let Some(progress) = &self.progress {
+            progress.add(n as u64);
+        }
+        Ok(n)
+    }
+}
+
+/// Parses the index and checks every entry against those before it and the
+/// `data_len` bytes of entry data.
+fn read_index(mut index: &[u8], data_len: u64) -> Result<Vec<Entry>> {
+    let count = u32::from_be_bytes(take(&mut index)?);
+    let mut kinds = HashMap::new();
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let entry = Entry::read_from(&mut index)?;
+        if !entry.path.split('/').all(is_plain_name) {
+            return Err(unsafe_entry(&entry.path, "its path leaves the folder"));
+        }
+        if kinds.contains_key(&entry.path) {
+            return Err(invalid(format!("{} is listed twice", entry.path)));
+        }
+        if let Some(parent) = entry.parent() {
+            if kinds.get(parent) != Some(&EntryKind::Directory) {
+                return Err(invalid(format!("{} is not inside a folder", entry.path)));
+            }
+        }
+        if entry
+            .offset
+            .checked_add(entry.stored_len)
+            .is_none_or(|end| end > data_len)
+        {
+            return Err(invalid(format!(
+                "the data of {} is out of place",
+                entry.path
+            )));
+        }
+        kinds.insert(entry.path.clone(), entry.kind);
+        entries.push(entry);
+    }
+    if !index.is_empty() {
+        return Err(invalid("the index is longer than its entries"));
+    }
+    // Links are checked once every entry is known, as any of them may be a link
+    for entry in &entries {
+        if let Some(target) = &entry.link_target {
+            check_link(&entry.path, target, &kinds, "")?;
+        }
+    }
+    Ok(entries)
+}
+
+/// Whether `name` is a single, ordinary file name.
+fn is_plain_name(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(first)), None) if first == OsStr::new(name) && !name.contains('\0')
+    )
+}
+
+/// Checks that the link at `link` points inside `root`, the entry that is
+/// extracted, or the whole folder when it is empty. The target is resolved
+/// against the entries in `kinds`: one passing through another link, which
+/// the path text says nothing about, is refused.
+fn check_link(
+    link: &str,
+    target: &str,
+    kinds: &HashMap<String, EntryKind>,
+    root: &str,
+) -> Result<()> {
+    let depth = root.split('/').filter(|name| !name.is_empty()).count();
+    let outside = if root.is_empty() {
+        "it links outside the folder"
+    } else {
+        "it links outside what is extracted"
+    };
+    let mut resolved: Vec<&str> = link.split('/').collect();
+    resolved.pop();
+    if resolved.len() < depth {
+        return Err(unsafe_entry(link, outside));
+    }
+    let mut components = Path::new(target).components().peekable();
+    while let Some(component) = components.next() {
+        match component {
+            Component::Normal(name) => {
+                resolved.push(name.to_str().expect("link targets are UTF-8"));
+                if components.peek().is_some()
+                    && kinds.get(&resolved.join("/")) == Some(&EntryKind::Symlink)
+                {
+                    return Err(unsafe_entry(link, "it links through another link"));
+                }
+            }
+            Component::CurDir => {}
+            Component::ParentDir if resolved.len() > depth => {
+                resolved.pop();
+            }
+            _ => return Err(unsafe_entry(link, outside)),
+        }
+    }
+    Ok(())
+}
+
+/// Creates `path` with `create`, reporting one that already exists as
+/// invalid input rather than an I/O error.
+fn create_new<T>(path: &Path, create: impl FnOnce(&Path) -> io::Result<T>) -> Result<T> {
+    create(path).map_err(|e| match e.kind() {
+        io::ErrorKind::AlreadyExists => {
+            EncoraError::InvalidInput(format!("{} already exists", path.display()))
+        }
+        _ => EncoraError::Io(e),
+    })
+}
+
+#[cfg(unix)]
+fn symlink(target: &str, path: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(not(unix))]
+fn symlink(_target: &str, _path: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Symbolic links can only be extracted on Unix",
+    ))
+}
+
+fn read_array<const N: usize, R: Read>(reader: &mut R) -> io::Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Splits the next `N` bytes off `bytes`.
+fn take<const N: usize>(bytes: &mut &[u8]) -> Result<[u8; N]> {
+    let head = bytes
+        .split_first_chunk::<N>()
+        .ok_or_else(|| invalid("the index ends inside an entry"))?;
+    *bytes = head.1;
+    Ok(*head.0)
+}
+
+fn take_text(bytes: &mut &[u8]) -> Result<String> {
+    let len = u16::from_be_bytes(take(bytes)?) as usize;
+    if bytes.len() < len {
+        return Err(invalid("the index ends inside an entry"));
+    }
+    let (text, rest) = bytes.split_at(len);
+    *bytes = rest;
+    String::from_utf8(text.to_vec()).map_err(|_| invalid("a name is not valid UTF-8"))
+}
+
+fn cannot_archive(path: &Path, reason: &str) -> EncoraError {
+    EncoraError::InvalidInput(format!("Cannot archive {}: {}", path.display(), reason))
+}
+
+fn unsafe_entry(path: &str, reason: &str) -> EncoraError {
+    EncoraError::UnsafeArchive(format!("{} ({})", path, reason))
+}
+
+fn invalid(reason: impl std::fmt::Display) -> EncoraError {
+    EncoraError::CorruptHeader(format!("invalid folder index: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{decrypt_dir, encrypt_dir, EncryptOptions};
+    use std::path::PathBuf;
+
+    /// A new, empty directory under the system's temporary directory.
+    fn scratch_dir() -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("encora-archive-{:016x}", rand::random::<u64>()));
+        fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    fn entry(path: &str, kind: EntryKind, link_target: Option<&str>) -> Entry {
+        Entry {
+            path: path.to_string(),
+            kind,
+            size: 0,
+            modified: None,
+            mode: None,
+            link_target: link_target.map(str::to_string),
+            offset: 0,
+            stored_len: 0,
+            compressed: false,
+        }
+    }
+
+    /// The index listing `entries`, as [`encrypt_archive`](crate::crypto::encrypt_archive)
+    /// writes it, so hostile ones can be made.
+    fn index(entries: &[Entry]) -> Vec<u8> {
+        let mut index = (entries.len() as u32).to_be_bytes().to_vec();
+        for entry in entries {
+            entry.write_to(&mut index);
+        }
+        index
+    }
+
+    fn is_unsafe(result: Result<Vec<Entry>>, reason: &str) -> bool {
+        matches!(result, Err(EncoraError::UnsafeArchive(ref entry)) if entry.ends_with(&format!("({})", reason)))
+    }
+
+    #[test]
+    fn refuses_paths_that_leave_the_folder() {
+        for path in [
+            "../evil",
+            "/etc/passwd",
+            "sub/../../evil",
+            "sub/./evil",
+            "",
+            "sub//evil",
+        ] {
+            let entries = [
+                entry("sub", EntryKind::Directory, None),
+                entry(path, EntryKind::File, None),
+            ];
+            assert!(
+                is_unsafe(
+                    read_index(&index(&entries), 0),
+                    "its path leaves the folder"
+                ),
+                "{:?} was accepted",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_links_that_point_outside_the_folder() {
+        for (link, target) in [
+            ("up", ".."),
+            ("sub/out", "../../evil"),
+            ("abs", "/etc/passwd"),
+            ("sub/deep", "../sub/../../evil"),
+        ] {
+            let entries = [
+                entry("sub", EntryKind::Directory, None),
+                entry(link, EntryKind::Symlink, Some(target)),
+            ];
+            assert!(
+                is_unsafe(
+                    read_index(&index(&entries), 0),
+                    "it links outside the folder"
+                ),
+                "{} -> {} was accepted",
+                link,
+                target
+            );
+        }
+    }
+
+    #[test]
+    fn accepts_links_that_stay_inside_the_folder() {
+        let entries = [
+            entry("file", EntryKind::File, None),
+            entry("sub", EntryKind::Directory, None),
+            entry("sub/back", EntryKind::Symlink, Some("../file")),
+            entry("sub/here", EntryKind::Symlink, Some("./back")),
+        ];
+        assert_eq!(read_index(&index(&entries), 0).unwrap(), entries);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_links_that_point_outside_an_extracted_subfolder() {
+        let scratch = scratch_dir();
+        let input = scratch.join("in");
+        fs::create_dir_all(input.join("sub/deeper/nested")).unwrap();
+        fs::write(input.join("file"), b"outside sub").unwrap();
+        fs::write(input.join("sub/deeper/inner"), b"inside deeper").unwrap();
+        symlink("../file", &input.join("sub/back")).unwrap();
+        symlink("../inner", &input.join("sub/deeper/nested/up")).unwrap();
+        let encrypted = scratch.join("in.encora");
+        let key = encrypt_dir(&input, &encrypted, &EncryptOptions::default()).unwrap();
+        let mut archive = Archive::open(&encrypted, Secret::Key(&key)).unwrap();
+
+        let output = scratch.join("sub");
+        let error = archive.extract("sub", &output).unwrap_err();
+        assert!(
+            matches!(error, EncoraError::UnsafeArchive(ref entry) if entry.starts_with("sub/back "))
+        );
+        assert!(!output.exists());
+        let error = archive.extract("sub/back", &output).unwrap_err();
+        assert!(matches!(error, EncoraError::UnsafeArchive(_)));
+        assert!(!output.exists());
+
+        // Within the subfolder, links may go up as far as its top
+        archive.extract("sub/deeper", &output).unwrap();
+        assert_eq!(
+            fs::read(output.join("nested/up")).unwrap(),
+            b"inside deeper"
+        );
+        archive.extract_all(scratch.join("all")).unwrap();
+        assert_eq!(
+            fs::read(scratch.join("all/sub/back")).unwrap(),
+            b"outside sub"
+        );
+        fs::remove_dir_all(scratch).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_link_through_another_link() {
+        let scratch = scratch_dir();
+        let input = scratch.join("in");
+        fs::create_dir_all(input.join("sub")).unwrap();
+        symlink("..", &input.join("sub/up")).unwrap();
+        symlink("sub/up/..", &input.join("esc")).unwrap();
+        let encrypted = scratch.join("in.encora");
+        let key = encrypt_dir(&input, &encrypted, &EncryptOptions::default()).unwrap();
+
+        let output = scratch.join("out");
+        let error = decrypt_dir(&encrypted, &output, Secret::Key(&key)).unwrap_err();
+        assert!(
+            matches!(error, EncoraError::UnsafeArchive(ref entry) if entry.starts_with("esc "))
+        );
+        assert!(!output.exists());
+        fs::remove_dir_all(scratch).unwrap();
+    }
+}
@@ -0,0 +1,267 @@
+//! Replacing a file with an encrypted or decrypted version of itself without
+//! ever leaving a half-written file behind.
+//!
+//! The new contents go to a temporary file in the same directory, which is
+//! flushed to disk and then renamed over the original. The rename is atomic,
+//! so after a crash the path holds either the old file or the complete new
+//! one, plus at worst a stray `.<name>.<random>.tmp` file. Just before the
+//! rename the original is checked again, and if another program changed it
+//! in the meantime it is left alone.
+
+use super::error::{EncoraError, Result};
+use super::metadata::FileMetadata;
+use rand::Rng;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// A temporary file that replaces `path` when committed and is removed if
+/// dropped before that.
+pub(crate) struct AtomicFile {
+    path: PathBuf,
+    temp_path: PathBuf,
+    writer: Option<BufWriter<File>>,
+    original: Snapshot,
+    permissions: fs::Permissions,
+}
+
+impl AtomicFile {
+    /// Opens the regular file at `path` for reading and creates the
+    /// temporary file that will replace it.
+    pub(crate) fn open(path: &Path) -> Result<(BufReader<File>, Self)> {
+        let metadata = fs::symlink_metadata(path)?;
+        if !metadata.is_file() {
+            return Err(EncoraError::InvalidInput(format!(
+                "{} is not a regular file; only files can be replaced in place",
+                path.display()
+            )));
+        }
+        let source = File::open(path)?;
+        let original = Snapshot::of(&source.metadata()?);
+
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let random: [u8; 8] = rand::thread_rng().gen();
+        let random: String = random.iter().map(|b| format!("{:02x}", b)).collect();
+        let temp_path = path.with_file_name(format!(".{}.{}.tmp", name, random));
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let temp = options.open(&temp_path)?;
+
+        let file = Self {
+            path: path.to_path_buf(),
+            temp_path,
+            writer: Some(BufWriter::new(temp)),
+            original,
+            permissions: metadata.permissions(),
+        };
+        Ok((BufReader::new(source), file))
+    }
+
+    pub(crate) fn writer(&mut self) -> &mut BufWriter<File> {
+        self.writer.as_mut().expect("only taken by commit")
+    }
+
+    /// Flushes the new contents to disk, gives them the stored `metadata`,
+    /// or else the original's permissions, and renames them over the
+    /// original unless it has changed since it was opened. The source
+    /// reader should be dropped first.
+    pub(crate) fn commit(mut self, metadata: Option<&FileMetadata>) -> Result<()> {
+        let file = self
+            .writer
+            .take()
+            .expect("only taken by commit")
+            .into_inner()
+            .map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        drop(file);
+        match metadata {
+            Some(metadata) => metadata.restore(&self.temp_path)?,
+            None => fs::set_permissions(&self.temp_path, self.permissions.clone())?,
+        }
+
+        if Snapshot::of(&fs::symlink_metadata(&self.path)?) != self.original {
+            return Err(EncoraError::SourceModified(self.path.display().to_string()));
+        }
+        fs::rename(&self.temp_path, &self.path)?;
+        // The rename itself is only durable once the directory is synced.
+        #[cfg(unix)]
+        {
+            let dir = match self.path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            File::open(dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        // Still there unless the rename happened.
+        let _ = fs::remove_file(&self.temp_path);
+    }
+}
+
+/// What changes when another program writes to or replaces a file.
+#[derive(Debug, PartialEq, Eq)]
+struct Snapshot {
+    len: u64,
+    modified: Option<SystemTime>,
+    /// Device, inode and status change time; the last can not be set back.
+    #[cfg(unix)]
+    identity: (u64, u64, i64, i64),
+}
+
+impl Snapshot {
+    fn of(metadata: &fs::Metadata) -> Self {
+        #[cfg(unix)]
+        use std::os::unix::fs::MetadataExt;
+        Self {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            #[cfg(unix)]
+            identity: (
+                metadata.dev(),
+                metadata.ino(),
+                metadata.ctime(),
+                metadata.ctime_nsec(),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::key::Secret;
+    use crate::crypto::{decrypt_in_place, encrypt_in_place, EncryptOptions};
+    use std::io::{Read, Write};
+
+    /// A new, empty directory under the system's temporary directory.
+    fn scratch_dir() -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("encora-atomic-{:016x}", rand::random::<u64>()));
+        fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    /// Names in `dir`, to check no temporary file is left behind.
+    fn names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn commit_replaces_the_file() {
+        let dir = scratch_dir();
+        let path = dir.join("file.txt");
+        fs::write(&path, b"old contents").unwrap();
+
+        let (mut source, mut file) = AtomicFile::open(&path).unwrap();
+        let mut old = String::new();
+        source.read_to_string(&mut old).unwrap();
+        file.writer()
+            .write_all(old.replace("old", "new").as_bytes())
+            .unwrap();
+        drop(source);
+        file.commit(None).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new contents");
+        assert_eq!(names(&dir), ["file.txt"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn leaves_the_file_alone_when_dropped_or_changed() {
+        let dir = scratch_dir();
+        let path = dir.join("file.txt");
+        fs::write(&path, b"old contents").unwrap();
+
+        let (_, mut file) = AtomicFile::open(&path).unwrap();
+        file.writer().write_all(b"never used").unwrap();
+        drop(file);
+        assert_eq!(fs::read(&path).unwrap(), b"old contents");
+        assert_eq!(names(&dir), ["file.txt"]);
+
+        let (source, mut file) = AtomicFile::open(&path).unwrap();
+        file.writer().write_all(b"new contents").unwrap();
+        drop(source);
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b", changed meanwhile")
+            .unwrap();
+        assert!(matches!(
+            file.commit(None),
+            Err(EncoraError::SourceModified(_))
+        ));
+        assert_eq!(fs::read(&path).unwrap(), b"old contents, changed meanwhile");
+        assert_eq!(names(&dir), ["file.txt"]);
+
+        assert!(matches!(
+            AtomicFile::open(&dir),
+            Err(EncoraError::InvalidInput(_))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn encrypts_and_decrypts_in_place() {
+        let dir = scratch_dir();
+        let path = dir.join("diary.txt");
+        fs::write(&path, b"dear diary").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        }
+
+        let key = encrypt_in_place(&path, &EncryptOptions::default()).unwrap();
+        assert_ne!(fs::read(&path).unwrap(), b"dear diary");
+        let header = decrypt_in_place(&path, Secret::Key(&key), None).unwrap();
+        assert!(header.metadata);
+        assert_eq!(fs::read(&path).unwrap(), b"dear diary");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(
+                fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+                0o640
+            );
+        }
+        assert_eq!(names(&dir), ["diary.txt"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_ciphertext_after_a_wrong_key() {
+        let dir = scratch_dir();
+        let path = dir.join("diary.txt");
+        fs::write(&path, b"dear diary").unwrap();
+        encrypt_in_place(&path, &EncryptOptions::default()).unwrap();
+        let encrypted = fs::read(&path).unwrap();
+
+        let wrong = crate::crypto::key::Key::generate();
+        assert!(matches!(
+            decrypt_in_place(&path, Secret::Key(&wrong), None),
+            Err(EncoraError::WrongKey)
+        ));
+        assert_eq!(fs::read(&path).unwrap(), encrypted);
+        assert_eq!(names(&dir), ["diary.txt"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,280 @@
+//! The algorithms Encora can encrypt with.
+//!
+//! Every algorithm is a [`FileCipher`] implementation listed in
+//! [`Algorithm::ALL`]. The header parser, the GUI's algorithm picker and the
+//! command line all enumerate that list, so supporting another AEAD means
+//! implementing the trait (usually through [`AeadCipher`]) and adding it there.
+
+use super::error::{EncoraError, Result};
+use super::header::Header;
+use super::key::Key;
+use super::stream::{decrypt_stream, encrypt_stream, open_chunk, DecryptReader, NONCE_SUFFIX_LEN};
+use aes_gcm::aead::generic_array::typenum::Unsigned;
+use aes_gcm::aead::{AeadInPlace, KeyInit};
+use aes_gcm::Aes256Gcm;
+use chacha20poly1305::{ChaCha20Poly1305, XChaCha20Poly1305};
+use std::io::{Read, Write};
+use std::marker::PhantomData;
+
+/// An authenticated cipher that can encrypt and decrypt Encora files.
+pub trait FileCipher: Sync {
+    /// Algorithm id stored in the header. Ids must never be reused.
+    fn id(&self) -> u8;
+
+    /// Name shown to users, e.g. `AES-256-GCM`.
+    fn name(&self) -> &'static str;
+
+    /// Short lowercase name accepted on the command line, e.g. `aes`.
+    fn short_name(&self) -> &'static str;
+
+    /// Key size in bytes; Encora keys are 32 bytes, so this must be too.
+    fn key_len(&self) -> usize;
+
+    /// Nonce size in bytes. It must leave room for the random nonce prefix
+    /// after the 5-byte chunk counter and final-chunk flag.
+    fn nonce_len(&self) -> usize;
+
+    /// Writes `header` followed by the chunked encryption of `reader`.
+    fn encrypt_stream(
+        &self,
+        key: &Key,
+        header: &Header,
+        reader: &mut dyn Read,
+        writer: &mut dyn Write,
+    ) -> Result<()>;
+
+    /// Decrypts the chunks that follow `header` in `reader` into `writer` and
+    /// returns the number of plaintext bytes written. `key_proven` says
+    /// whether a keyslot opened `key`, so that a first chunk failing to open
+    /// is reported as tampering rather than as the wrong key.
+    fn decrypt_stream(
+        &self,
+        key: &Key,
+        header: &Header,
+        reader: &mut dyn Read,
+        writer: &mut dyn Write,
+        key_proven: bool,
+    ) -> Result<u64>;
+
+    /// Authenticates and decrypts chunk number `counter` in place, as
+    /// [`super::stream::open_chunk`] does.
+    fn decrypt_chunk(
+        &self,
+        key: &Key,
+        header: &Header,
+        counter: u32,
+        last: bool,
+        key_proven: bool,
+        chunk: &mut Vec<u8>,
+    ) -> Result<()>;
+
+    /// Reader over the plaintext of the chunks that follow `header` in
+    /// `reader`, with `key_proven` as for [`FileCipher::decrypt_stream`].
+    fn decrypt_reader<'a>(
+        &self,
+        key: &Key,
+        header: &Header,
+        reader: Box<dyn Read + 'a>,
+        key_proven: bool,
+    ) -> Result<Box<dyn Read + 'a>>;
+}
+
+/// [`FileCipher`] for any RustCrypto AEAD, using the STREAM construction in
+/// [`super::stream`].
+pub struct AeadCipher<A> {
+    id: u8,
+    name: &'static str,
+    short_name: &'static str,
+    aead: PhantomData<fn() -> A>,
+}
+
+impl<A> AeadCipher<A> {
+    pub const fn new(id: u8, name: &'static str, short_name: &'static str) -> Self {
+        Self {
+            id,
+            name,
+            short_name,
+            aead: PhantomData,
+        }
+    }
+}
+
+impl<A: AeadInPlace + KeyInit + 'static> AeadCipher<A> {
+    fn cipher(&self, key: &Key) -> Result<A> {
+        A::new_from_slice(key.as_bytes()).map_err(|_| {
+            EncoraError::InvalidKey(format!("{} needs a {}-byte key", self.name, self.key_len()))
+        })
+    }
+}
+
+impl<A: AeadInPlace + KeyInit + 'static> FileCipher for AeadCipher<A> {
+    fn id(&self) -> u8 {
+        self.id
+    }
+
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn short_name(&self) -> &'static str {
+        self.short_name
+    }
+
+    fn key_len(&self) -> usize {
+        A::KeySize::USIZE
+    }
+
+    fn nonce_len(&self) -> usize {
+        A::NonceSize::USIZE
+    }
+
+    fn encrypt_stream(
+        &self,
+        key: &Key,
+        header: &Header,
+        reader: &mut dyn Read,
+        writer: &mut dyn Write,
+    ) -> Result<()> {
+        encrypt_stream(self.cipher(key)?, header, reader, writer)?;
+        Ok(())
+    }
+
+    fn decrypt_stream(
+        &self,
+        key: &Key,
+        header: &Header,
+        reader: &mut dyn Read,
+        writer: &mut dyn Write,
+        key_proven: bool,
+    ) -> Result<u64> {
+        Ok(decrypt_stream(
+            self.cipher(key)?,
+            header,
+            reader,
+            writer,
+            key_proven,
+        )?)
+    }
+
+    fn decrypt_chunk(
+        &self,
+        key: &Key,
+        header: &Header,
+        counter: u32,
+        last: bool,
+        key_proven: bool,
+        chunk: &mut Vec<u8>,
+    ) -> Result<()> {
+        let cipher = self.cipher(key)?;
+        let aad = header.authenticated_bytes();
+        open_chunk(
+            &cipher,
+            &header.nonce_prefix,
+            &aad,
+            counter,
+            last,
+            key_proven,
+            chunk,
+        )
+    }
+
+    fn decrypt_reader<'a>(
+        &self,
+        key: &Key,
+        header: &Header,
+        reader: Box<dyn Read + 'a>,
+        key_proven: bool,
+    ) -> Result<Box<dyn Read + 'a>> {
+        let cipher = self.cipher(key)?;
+        Ok(Box::new(DecryptReader::new(
+            cipher, header, reader, key_proven,
+        )))
+    }
+}
+
+/// Handle to one of the registered [`FileCipher`]s.
+#[derive(Clone, Copy)]
+pub struct Algorithm(&'static dyn FileCipher);
+
+impl Algorithm {
+    pub const AES_256_GCM: Algorithm =
+        Algorithm(&AeadCipher::<Aes256Gcm>::new(1, "AES-256-GCM", "aes"));
+    pub const CHACHA20_POLY1305: Algorithm = Algorithm(&AeadCipher::<ChaCha20Poly1305>::new(
+        2,
+        "ChaCha20-Poly1305",
+        "chacha20",
+    ));
+    pub const XCHACHA20_POLY1305: Algorithm = Algorithm(&AeadCipher::<XChaCha20Poly1305>::new(
+        3,
+        "XChaCha20-Poly1305",
+        "xchacha20",
+    ));
+
+    /// Every supported algorithm, in the order they are offered to users.
+    pub const ALL: &'static [Algorithm] = &[
+        Algorithm::AES_256_GCM,
+        Algorithm::CHACHA20_POLY1305,
+        Algorithm::XCHACHA20_POLY1305,
+    ];
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|algorithm| algorithm.id() == id)
+    }
+
+    /// Looks an algorithm up by its name or short name, ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|algorithm| {
+            algorithm.name().eq_ignore_ascii_case(name)
+                || algorithm.short_name().eq_ignore_ascii_case(name)
+        })
+    }
+
+    pub fn cipher(self) -> &'static dyn FileCipher {
+        self.0
+    }
+
+    pub fn id(self) -> u8 {
+        self.0.id()
+    }
+
+    pub fn name(self) -> &'static str {
+        self.0.name()
+    }
+
+    pub fn short_name(self) -> &'static str {
+        self.0.short_name()
+    }
+
+    pub fn nonce_len(self) -> usize {
+        self.0.nonce_len()
+    }
+
+    /// Random part of the per-chunk nonces stored in the header; the rest is
+    /// the chunk counter and final-chunk flag.
+    pub fn nonce_prefix_len(self) -> usize {
+        self.nonce_len() - NONCE_SUFFIX_LEN
+    }
+}
+
+impl PartialEq for Algorithm {
+    fn eq(&self, other: &Self) -> bool {
+        self.id() == other.id()
+    }
+}
+
+impl Eq for Algorithm {}
+
+impl std::fmt::Debug for Algorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Algorithm({})", self.name())
+    }
+}
+
+impl std::fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
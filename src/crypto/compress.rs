@@ -0,0 +1,146 @@
+//! Optional zstd compression of the data before it is encrypted.
+//!
+//! When the header has the compressed flag set, the plaintext after the
+//! metadata block is a zstd stream. Compression is decided before anything is
+//! written: the start of the input is compressed as a trial, and data that
+//! barely shrinks (already compressed media, archives, encrypted files) is
+//! stored as it is. Encrypted folders compress each file on its own instead,
+//! as described in [`super::archive`].
+
+use super::error::{EncoraError, Result};
+use std::io::{self, Read, Write};
+use std::ops::RangeInclusive;
+use zeroize::Zeroizing;
+
+/// Level used when compression is asked for without one.
+pub const DEFAULT_LEVEL: i32 = 3;
+
+/// Levels zstd accepts, from fastest to smallest.
+pub const LEVELS: RangeInclusive<i32> = 1..=22;
+
+/// Bytes of input compressed as a trial before deciding.
+const SAMPLE_LEN: u64 = 128 * 1024;
+
+/// The trial must shrink to at most this fraction of the sample.
+const MAX_RATIO: f64 = 0.9;
+
+pub(crate) fn validate_level(level: i32) -> Result<()> {
+    if !LEVELS.contains(&level) {
+        return Err(EncoraError::InvalidInput(format!(
+            "Compression level must be between {} and {}",
+            LEVELS.start(),
+            LEVELS.end()
+        )));
+    }
+    Ok(())
+}
+
+/// Reads the start of `reader` and decides whether compressing at `level` is
+/// worth it. Returns the decision and the bytes read, which the caller must
+/// put back in front of the rest. Both the sample and its trial compression
+/// are plaintext, so they are wiped when dropped.
+pub(crate) fn sample<R: Read>(
+    reader: &mut R,
+    level: i32,
+) -> io::Result<(bool, Zeroizing<Vec<u8>>)> {
+    // Sized up front for the same reason as archive chunk buffers.
+    let mut sample = Zeroizing::new(Vec::with_capacity(SAMPLE_LEN as usize));
+    reader.take(SAMPLE_LEN).read_to_end(&mut sample)?;
+    if sample.is_empty() {
+        return Ok((false, sample));
+    }
+    let compressed = Zeroizing::new(zstd::bulk::compress(&sample, level)?);
+    let worth_it = (compressed.len() as f64) <= sample.len() as f64 * MAX_RATIO;
+    Ok((worth_it, sample))
+}
+
+/// Reader over the zstd compression of `reader`.
+pub(crate) fn compressor<'a, R: Read + 'a>(
+    reader: R,
+    level: i32,
+) -> io::Result<Box<dyn Read + 'a>> {
+    Ok(Box::new(zstd::stream::read::Encoder::new(reader, level)?))
+}
+
+/// Writer that compresses into `writer`; `finish` must be called to end the
+/// stream.
+pub(crate) fn encoder<W: Write>(
+    writer: W,
+    level: i32,
+) -> io::Result<zstd::stream::write::Encoder<'static, W>> {
+    zstd::stream::write::Encoder::new(writer, level)
+}
+
+/// Reader over the decompression of the zstd stream in `reader`.
+pub(crate) fn decompressor<'a, R: Read + 'a>(reader: R) -> io::Result<Box<dyn Read + 'a>> {
+    Ok(Box::new(zstd::stream::read::Decoder::new(reader)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{decrypt, encrypt, EncryptOptions, Secret};
+    use rand::RngCore;
+
+    fn random_bytes(len: usize) -> Vec<u8> {
+        let mut bytes = vec![0u8; len];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        bytes
+    }
+
+    /// Encrypts `plaintext` with compression asked for, checks that it
+    /// decrypts back, and returns whether the header says it was compressed.
+    fn round_trip(plaintext: &[u8]) -> bool {
+        let options = EncryptOptions {
+            compression: Some(DEFAULT_LEVEL),
+            ..EncryptOptions::default()
+        };
+        let mut encrypted = Vec::new();
+        let key = encrypt(&mut &plaintext[..], &mut encrypted, &options).unwrap();
+        let mut decrypted = Vec::new();
+        let header = decrypt(&encrypted[..], &mut decrypted, Secret::Key(&key)).unwrap();
+        assert_eq!(decrypted, plaintext);
+        header.compressed
+    }
+
+    #[test]
+    fn compresses_data_that_shrinks() {
+        let plaintext = b"the same line over and over\n".repeat(20_000);
+        assert!(round_trip(&plaintext));
+    }
+
+    #[test]
+    fn stores_incompressible_data_as_it_is() {
+        assert!(!round_trip(&random_bytes(300 * 1024)));
+        assert!(!round_trip(b""));
+    }
+
+    #[test]
+    fn sample_reads_only_the_start_and_decides() {
+        let plaintext = [vec![0u8; SAMPLE_LEN as usize], random_bytes(1000)].concat();
+        let mut reader = &plaintext[..];
+        let (worth_it, start) = sample(&mut reader, DEFAULT_LEVEL).unwrap();
+        assert!(worth_it);
+        assert_eq!(&start[..], &plaintext[..SAMPLE_LEN as usize]);
+        assert_eq!(reader.len(), 1000);
+
+        // Saving less than a tenth is not worth it
+        let mostly_random = [random_bytes(95_000), vec![0u8; 5_000]].concat();
+        let (worth_it, _) = sample(&mut &mostly_random[..], DEFAULT_LEVEL).unwrap();
+        assert!(!worth_it);
+    }
+
+    #[test]
+    fn refuses_levels_out_of_range() {
+        assert!(validate_level(1).is_ok());
+        assert!(validate_level(22).is_ok());
+        assert!(matches!(
+            validate_level(0),
+            Err(EncoraError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            validate_level(23),
+            Err(EncoraError::InvalidInput(_))
+        ));
+    }
+}
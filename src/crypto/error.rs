@@ -0,0 +1,147 @@
+use std::fmt;
+use std::io;
+
+/// Everything that can go wrong while encrypting or decrypting.
+///
+/// AEAD ciphers cannot tell a wrong key from modified ciphertext, so the
+/// decryptor infers the cause from where authentication fails: the very first
+/// chunk failing is reported as [`EncoraError::WrongKey`], a later chunk
+/// failing as [`EncoraError::Tampered`]. When a keyslot opened the file, the
+/// key is already known to be right and any chunk failing is tampering.
+#[derive(Debug)]
+pub enum EncoraError {
+    /// The input does not start with an Encora header.
+    NotEncoraFile,
+    /// The header has a format version this build cannot read.
+    UnsupportedVersion(u8),
+    /// The header names an algorithm id this build does not know.
+    UnsupportedAlgorithm(u8),
+    /// The header is recognisable but one of its fields is invalid.
+    CorruptHeader(String),
+    /// The key or passphrase does not open the file.
+    WrongKey,
+    /// The file was encrypted to public keys, none of them the identity's.
+    NotARecipient,
+    /// Part of the ciphertext failed authentication after the key was accepted.
+    Tampered,
+    /// The file ends before its final chunk.
+    Truncated,
+    /// An encrypted folder holds an entry that would be extracted outside it.
+    UnsafeArchive(String),
+    /// The file being replaced in place was changed by something else meanwhile.
+    SourceModified(String),
+    /// The operation was stopped through its [`super::progress::Progress`]
+    /// handle; whatever it had written was removed.
+    Cancelled,
+    /// A key, key file or passphrase could not be used as given.
+    InvalidKey(String),
+    /// The request itself is inconsistent, e.g. both a key and a passphrase.
+    InvalidInput(String),
+    /// Reading or writing failed.
+    Io(io::Error),
+}
+
+impl EncoraError {
+    /// A suggestion for the user on what to do about the error, if there is one.
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            EncoraError::NotEncoraFile => Some("Choose a file that was encrypted with Encora."),
+            EncoraError::UnsupportedVersion(_) | EncoraError::UnsupportedAlgorithm(_) => {
+                Some("The file was written by a newer version of Encora; update Encora to open it.")
+            }
+            EncoraError::WrongKey => {
+                Some("Check that the key or passphrase is the one used to encrypt this file.")
+            }
+            EncoraError::NotARecipient => {
+                Some("Ask the sender to encrypt the file to your public key.")
+            }
+            EncoraError::Tampered | EncoraError::CorruptHeader(_) => {
+                Some("The file was modified or damaged after encryption; restore it from a backup.")
+            }
+            EncoraError::Truncated => Some("The file is incomplete; copy or download it again."),
+            EncoraError::UnsafeArchive(_) => Some(
+                "The folder was packed to write elsewhere on your disk; do not trust its sender.",
+            ),
+            EncoraError::SourceModified(_) => {
+                Some("Another program changed the file; try again once it is done with it.")
+            }
+            EncoraError::InvalidKey(_) => {
+                Some("Keys are 64 hexadecimal characters, as shown after encryption.")
+            }
+            EncoraError::Cancelled | EncoraError::InvalidInput(_) | EncoraError::Io(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for EncoraError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncoraError::NotEncoraFile => write!(f, "Not an Encora file"),
+            EncoraError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported format version: {}", version)
+            }
+            EncoraError::UnsupportedAlgorithm(id) => write!(f, "Unknown algorithm id: {}", id),
+            EncoraError::CorruptHeader(reason) => write!(f, "Corrupt header: {}", reason),
+            EncoraError::WrongKey => write!(f, "Wrong key or passphrase"),
+            EncoraError::NotARecipient => write!(f, "File was not encrypted to this identity"),
+            EncoraError::Tampered => {
+                write!(f, "Encrypted data has been tampered with or corrupted")
+            }
+            EncoraError::Truncated => write!(f, "Encrypted file is truncated"),
+            EncoraError::UnsafeArchive(entry) => {
+                write!(f, "Refusing to extract unsafe entry {}", entry)
+            }
+            EncoraError::SourceModified(path) => {
+                write!(
+                    f,
+                    "{} was modified while being processed and was left as it was",
+                    path
+                )
+            }
+            EncoraError::Cancelled => write!(f, "Cancelled"),
+            EncoraError::InvalidKey(reason) => write!(f, "Invalid key: {}", reason),
+            EncoraError::InvalidInput(reason) => f.write_str(reason),
+            EncoraError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for EncoraError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EncoraError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Unwraps errors that travelled through `Read`/`Write` as `io::Error`.
+impl From<io::Error> for EncoraError {
+    fn from(error: io::Error) -> Self {
+        if error
+            .get_ref()
+            .is_some_and(|inner| inner.is::<EncoraError>())
+        {
+            let inner = error.into_inner().and_then(|inner| inner.downcast().ok());
+            return *inner.expect("checked to be an EncoraError");
+        }
+        EncoraError::Io(error)
+    }
+}
+
+/// Lets the stream types report an `EncoraError` through `Read`/`Write`.
+impl From<EncoraError> for io::Error {
+    fn from(error: EncoraError) -> Self {
+        let kind = match error {
+            EncoraError::Io(e) => return e,
+            EncoraError::Truncated => io::ErrorKind::UnexpectedEof,
+            EncoraError::InvalidKey(_) | EncoraError::InvalidInput(_) => {
+                io::ErrorKind::InvalidInput
+            }
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, error)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, EncoraError>;
@@ -0,0 +1,255 @@
+pub use super::cipher::Algorithm;
+use super::error::{EncoraError, Result};
+use super::keyslot::{KeySlot, KeySlotKind, DEFAULT_KEYSLOTS, KEYSLOT_LEN, MAX_KEYSLOTS};
+use super::stream::{DEFAULT_CHUNK_SIZE, MAX_CHUNK_SIZE, MIN_CHUNK_SIZE};
+use rand::Rng;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Every Encora file starts with these bytes.
+pub const MAGIC: [u8; 6] = *b"ENCORA";
+
+/// Current version of the container layout.
+pub const FORMAT_VERSION: u8 = 4;
+
+/// Size of the fixed part of the header: magic, version, algorithm, flags,
+/// chunk size, nonce prefix length.
+const FIXED_LEN: usize = MAGIC.len() + 8;
+
+/// Length of the random id every file gets when it is encrypted.
+pub const FILE_ID_LEN: usize = 16;
+
+/// Identifies one encrypted file, for key files and the key vault.
+pub type FileId = [u8; FILE_ID_LEN];
+
+/// Flag: the plaintext starts with a [`FileMetadata`](super::metadata::FileMetadata) block.
+pub const FLAG_METADATA: u8 = 0x01;
+
+/// Flag: the data is [compressed](super::compress) with zstd.
+pub const FLAG_COMPRESSED: u8 = 0x02;
+
+/// Flag: the data is a directory [archive](super::archive).
+pub const FLAG_ARCHIVE: u8 = 0x04;
+
+const KNOWN_FLAGS: u8 = FLAG_METADATA | FLAG_COMPRESSED | FLAG_ARCHIVE;
+
+/// Header written in front of the ciphertext of every Encora file.
+///
+/// Layout (all integers big-endian):
+///
+/// | bytes   | field               |
+/// |---------|---------------------|
+/// | 6       | magic `ENCORA`      |
+/// | 1       | format version      |
+/// | 1       | algorithm id        |
+/// | 1       | flags               |
+/// | 4       | chunk size          |
+/// | 1       | nonce prefix length |
+/// | n       | nonce prefix        |
+/// | 16      | file id             |
+/// | 1       | keyslot count       |
+/// | 96 each | keyslots            |
+///
+/// Everything up to the file id is authenticated with every chunk and never
+/// changes, so the random file id names the file for as long as it exists.
+/// The keyslots described in [`super::keyslot`] follow; empty slots are kept
+/// so slots can be added without moving the ciphertext.
+/// The flags are [`FLAG_METADATA`], [`FLAG_COMPRESSED`] and [`FLAG_ARCHIVE`].
+///
+/// The header is followed by the chunked ciphertext described in
+/// [`super::stream`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub algorithm: Algorithm,
+    pub chunk_size: u32,
+    pub nonce_prefix: Vec<u8>,
+    pub file_id: FileId,
+    /// Whether the plaintext starts with an encrypted block describing the
+    /// original file.
+    pub metadata: bool,
+    /// Whether the data after the metadata is compressed with zstd.
+    pub compressed: bool,
+    /// Whether the data is an archive of a directory tree.
+    pub archive: bool,
+    /// Every keyslot, including empty ones; the file key itself is the only
+    /// way in when all are empty.
+    pub keyslots: Vec<Option<KeySlot>>,
+}
+
+impl Header {
+    pub fn new(algorithm: Algorithm, nonce_prefix: &[u8], file_id: FileId) -> Self {
+        Self {
+            algorithm,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            nonce_prefix: nonce_prefix.to_vec(),
+            file_id,
+            metadata: false,
+            compressed: false,
+            archive: false,
+            keyslots: vec![None; DEFAULT_KEYSLOTS],
+        }
+    }
+
+    /// Header for a new file with a fresh random nonce prefix and file id.
+    pub fn generate(algorithm: Algorithm) -> Self {
+        let mut rng = rand::thread_rng();
+        let mut nonce_prefix = vec![0u8; algorithm.nonce_prefix_len()];
+        rng.fill(nonce_prefix.as_mut_slice());
+        Self::new(algorithm, &nonce_prefix, rng.gen())
+    }
+
+    /// Whether any keyslot is of `kind`.
+    pub fn has_keyslot(&self, kind: KeySlotKind) -> bool {
+        self.keyslots
+            .iter()
+            .flatten()
+            .any(|keyslot| keyslot.kind() == kind)
+    }
+
+    /// How many keyslots are of `kind`.
+    pub fn count_keyslots(&self, kind: KeySlotKind) -> usize {
+        self.keyslots
+            .iter()
+            .flatten()
+            .filter(|keyslot| keyslot.kind() == kind)
+            .count()
+    }
+
+    /// The part of the header that never changes: everything before the
+    /// keyslots. Chunks are authenticated against it.
+    pub fn authenticated_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(FIXED_LEN + self.nonce_prefix.len() + FILE_ID_LEN);
+        bytes.extend_from_slice(&MAGIC);
+        bytes.push(FORMAT_VERSION);
+        bytes.push(self.algorithm.id());
+        let mut flags = 0;
+        if self.metadata {
+            flags |= FLAG_METADATA;
+        }
+        if self.compressed {
+            flags |= FLAG_COMPRESSED;
+        }
+        if self.archive {
+            flags |= FLAG_ARCHIVE;
+        }
+        bytes.push(flags);
+        bytes.extend_from_slice(&self.chunk_size.to_be_bytes());
+        bytes.push(self.nonce_prefix.len() as u8);
+        bytes.extend_from_slice(&self.nonce_prefix);
+        bytes.extend_from_slice(&self.file_id);
+        bytes
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.authenticated_bytes();
+        bytes.push(self.keyslots.len() as u8);
+        for keyslot in &self.keyslots {
+            let mut slot = [0u8; KEYSLOT_LEN];
+            if let Some(keyslot) = keyslot {
+                keyslot.write_to(&mut slot);
+            }
+            bytes.extend_from_slice(&slot);
+        }
+        bytes
+    }
+
+    /// Checks fields a caller may have set by hand before the header is written.
+    pub fn validate(&self) -> Result<()> {
+        validate_chunk_size(self.chunk_size)?;
+        if self.nonce_prefix.len() != self.algorithm.nonce_prefix_len() {
+            return Err(corrupt("invalid nonce length"));
+        }
+        if self.keyslots.len() > MAX_KEYSLOTS {
+            return Err(EncoraError::InvalidInput(format!(
+                "A file can have at most {} keyslots",
+                MAX_KEYSLOTS
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut fixed = [0u8; FIXED_LEN];
+        reader.read_exact(&mut fixed).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => EncoraError::NotEncoraFile,
+            _ => e.into(),
+        })?;
+
+        if fixed[..MAGIC.len()] != MAGIC {
+            return Err(EncoraError::NotEncoraFile);
+        }
+
+        let version = fixed[6];
+        if version != FORMAT_VERSION {
+            return Err(EncoraError::UnsupportedVersion(version));
+        }
+
+        let algorithm =
+            Algorithm::from_id(fixed[7]).ok_or(EncoraError::UnsupportedAlgorithm(fixed[7]))?;
+
+        let flags = fixed[8];
+        if flags & !KNOWN_FLAGS != 0 {
+            return Err(corrupt(format!("unsupported flags {:#04x}", flags)));
+        }
+
+        let chunk_size = u32::from_be_bytes([fixed[9], fixed[10], fixed[11], fixed[12]]);
+        validate_chunk_size(chunk_size)?;
+
+        let nonce_prefix_len = fixed[13] as usize;
+        if nonce_prefix_len != algorithm.nonce_prefix_len() {
+            return Err(corrupt("invalid nonce length"));
+        }
+        let mut nonce_prefix = vec![0u8; nonce_prefix_len];
+        read_rest(reader, &mut nonce_prefix)?;
+        let mut file_id = [0u8; FILE_ID_LEN];
+        read_rest(reader, &mut file_id)?;
+
+        let mut count = [0u8; 1];
+        read_rest(reader, &mut count)?;
+        let mut keyslots = Vec::with_capacity(count[0] as usize);
+        for _ in 0..count[0] {
+            let mut slot = [0u8; KEYSLOT_LEN];
+            read_rest(reader, &mut slot)?;
+            keyslots.push(KeySlot::read_from(&slot)?);
+        }
+
+        Ok(Self {
+            algorithm,
+            chunk_size,
+            nonce_prefix,
+            file_id,
+            metadata: flags & FLAG_METADATA != 0,
+            compressed: flags & FLAG_COMPRESSED != 0,
+            archive: flags & FLAG_ARCHIVE != 0,
+            keyslots,
+        })
+    }
+}
+
+/// Reads only the header of the file at `file_path`, e.g. to find out which
+/// algorithm it was encrypted with before asking for a key.
+pub fn read_header<T: AsRef<Path>>(file_path: T) -> Result<Header> {
+    let mut file = File::open(file_path.as_ref())?;
+    Header::read_from(&mut file)
+}
+
+fn validate_chunk_size(chunk_size: u32) -> Result<()> {
+    if !(MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&chunk_size) {
+        return Err(corrupt(format!("invalid chunk size {}", chunk_size)));
+    }
+    Ok(())
+}
+
+/// Reads the part of the header after the fixed fields, which is only missing
+/// if the file was cut short.
+fn read_rest<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<()> {
+    reader.read_exact(buf).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => EncoraError::Truncated,
+        _ => e.into(),
+    })
+}
+
+fn corrupt<S: Into<String>>(reason: S) -> EncoraError {
+    EncoraError::CorruptHeader(reason.into())
+}
@@ -0,0 +1,131 @@
+use super::error::{EncoraError, Result};
+use super::recipient::Identity;
+use super::shamir::Share;
+use argon2::{Argon2, Params, Version};
+use rand::Rng;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+pub const KEY_LEN: usize = 32;
+pub const SALT_LEN: usize = 16;
+
+/// Upper bounds for KDF parameters read from a header, so a crafted file
+/// cannot make decryption allocate gigabytes or spin for hours: one run costs
+/// at most 512 MiB and 16 passes, and
+/// [`MAX_PASSPHRASE_SLOTS`](super::keyslot::MAX_PASSPHRASE_SLOTS) bounds the
+/// runs per unlock.
+const MAX_M_COST: u32 = 512 * 1024;
+const MAX_T_COST: u32 = 16;
+const MAX_P_COST: u32 = 16;
+
+/// A 256-bit file key, wiped from memory when dropped.
+#[derive(Clone, PartialEq, Eq, Zeroize, ZeroizeOnDrop)]
+pub struct Key([u8; KEY_LEN]);
+
+impl Key {
+    pub fn generate() -> Self {
+        // Filled in place so no copy of the key is left on the stack.
+        let mut key = Self([0u8; KEY_LEN]);
+        rand::thread_rng().fill(&mut key.0);
+        key
+    }
+
+    pub fn from_bytes(bytes: [u8; KEY_LEN]) -> Self {
+        Self(bytes)
+    }
+
+    /// Copies `bytes` into a key if it is exactly [`KEY_LEN`] long.
+    pub(crate) fn from_slice(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != KEY_LEN {
+            return None;
+        }
+        let mut key = Self([0u8; KEY_LEN]);
+        key.0.copy_from_slice(bytes);
+        Some(key)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; KEY_LEN] {
+        &self.0
+    }
+
+    pub fn to_hex(&self) -> Zeroizing<String> {
+        Zeroizing::new(hex::encode(self.0))
+    }
+
+    /// Parses the hex form produced by [`Key::to_hex`], ignoring surrounding whitespace.
+    pub fn from_hex(key_hex: &str) -> Result<Self> {
+        let bytes = Zeroizing::new(
+            hex::decode(key_hex.trim()).map_err(|e| EncoraError::InvalidKey(e.to_string()))?,
+        );
+        Self::from_slice(&bytes).ok_or_else(|| {
+            EncoraError::InvalidKey(format!("expected {} bytes, got {}", KEY_LEN, bytes.len()))
+        })
+    }
+}
+
+impl std::fmt::Debug for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+/// What the user hands over to decrypt a file.
+#[derive(Debug, Clone, Copy)]
+pub enum Secret<'a> {
+    /// The key returned at encryption time, or the key of a keyslot.
+    Key(&'a Key),
+    /// The hex-encoded form of [`Secret::Key`].
+    KeyHex(&'a str),
+    /// The passphrase of a keyslot.
+    Passphrase(&'a str),
+    /// The private key of one of the recipients the file was encrypted to.
+    Identity(&'a Identity),
+    /// At least the threshold of the shares [`split_key`](super::shamir::split_key)
+    /// made of [`Secret::Key`].
+    Shares(&'a [Share]),
+}
+
+/// Argon2id parameters recorded in a passphrase keyslot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KdfParams {
+    pub salt: [u8; SALT_LEN],
+    /// Memory cost in KiB.
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+impl KdfParams {
+    /// Fresh random salt with the argon2 crate's default (OWASP recommended) costs.
+    pub fn generate() -> Self {
+        let mut salt = [0u8; SALT_LEN];
+        rand::thread_rng().fill(&mut salt);
+        Self {
+            salt,
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.m_cost > MAX_M_COST || self.t_cost > MAX_T_COST || self.p_cost > MAX_P_COST {
+            return Err(EncoraError::CorruptHeader(
+                "key derivation parameters out of range".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn derive_key(&self, passphrase: &str) -> Result<Key> {
+        self.validate()?;
+        let params = Params::new(self.m_cost, self.t_cost, self.p_cost, Some(KEY_LEN))
+            .map_err(|e| EncoraError::CorruptHeader(format!("key derivation parameters: {}", e)))?;
+        let argon2 = Argon2::new(argon2::Algorithm::Argon2id, Version::V0x13, params);
+
+        let mut key = Key([0u8; KEY_LEN]);
+        argon2
+            .hash_password_into(passphrase.as_bytes(), &self.salt, &mut key.0)
+            .map_err(|e| EncoraError::CorruptHeader(format!("key derivation failed: {}", e)))?;
+        Ok(key)
+    }
+}
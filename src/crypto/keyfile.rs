@@ -0,0 +1,387 @@
+//! `.encora-key` files, which store the key of one encrypted file so it can be
+//! loaded again instead of pasting hex by hand.
+//!
+//! The format is plain text, one `name: value` field per line:
+//!
+//! ```text
+//! ENCORA-KEY 2
+//! algorithm: XChaCha20-Poly1305
+//! key: <64 hex digits>
+//! nonce: <hex nonce prefix from the header>
+//! id: <hex file id from the header>
+//! created: <seconds since the Unix epoch>
+//! ```
+//!
+//! The random file id in the header identifies the encrypted file a key file
+//! belongs to, and never changes, whatever keyslots are added or removed.
+//! The key is either the file key itself or the key of a keyslot.
+//!
+//! A key bundle holds the keys of many encrypted files, such as a batch
+//! encrypted with a different key each. It is an `ENCORA-KEY-BUNDLE 2` line
+//! followed by one key file per encrypted file, each with an extra `file:`
+//! field naming the encrypted file it was made for. The name is only a label;
+//! keys are matched to files by their id.
+
+use super::error::{EncoraError, Result};
+use super::header::{Algorithm, FileId, Header};
+use super::key::Key;
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use zeroize::Zeroizing;
+
+/// Extension of key files.
+pub const KEY_FILE_EXTENSION: &str = "encora-key";
+
+/// Extension of key bundles.
+pub const KEY_BUNDLE_EXTENSION: &str = "encora-keys";
+
+/// First line of every key file.
+const KEY_FILE_MAGIC: &str = "ENCORA-KEY 2";
+
+/// First line of every key bundle.
+const KEY_BUNDLE_MAGIC: &str = "ENCORA-KEY-BUNDLE 2";
+
+/// Contents of a `.encora-key` file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyFile {
+    pub algorithm: Algorithm,
+    pub key: Key,
+    pub nonce_prefix: Vec<u8>,
+    /// The id in the header of the encrypted file.
+    pub file_id: FileId,
+    /// Seconds since the Unix epoch.
+    pub created: u64,
+}
+
+impl KeyFile {
+    /// Key file for the encrypted file that starts with `header`.
+    pub fn new(header: &Header, key: Key) -> Self {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        Self {
+            algorithm: header.algorithm,
+            key,
+            nonce_prefix: header.nonce_prefix.clone(),
+            file_id: header.file_id,
+            created,
+        }
+    }
+
+    /// Whether this key file was made for the encrypted file with `header`.
+    pub fn matches(&self, header: &Header) -> bool {
+        self.file_id == header.file_id && self.nonce_prefix == header.nonce_prefix
+    }
+
+    pub fn to_text(&self) -> Zeroizing<String> {
+        Zeroizing::new(format!(
+            "{}\nalgorithm: {}\nkey: {}\nnonce: {}\nid: {}\ncreated: {}\n",
+            KEY_FILE_MAGIC,
+            self.algorithm,
+            *self.key.to_hex(),
+            hex::encode(&self.nonce_prefix),
+            hex::encode(self.file_id),
+            self.created
+        ))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+        if lines.next() != Some(KEY_FILE_MAGIC) {
+            return Err(invalid_key_file("missing `ENCORA-KEY 2` line"));
+        }
+
+        let (mut algorithm, mut key, mut nonce_prefix, mut file_id, mut created) =
+            (None, None, None, None, None);
+        for line in lines {
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| invalid_key_file("expected `name: value`"))?;
+            let value = value.trim();
+            match name.trim() {
+                "algorithm" => algorithm = Some(parse_algorithm(value)?),
+                "key" => key = Some(Key::from_hex(value)?),
+                "nonce" => nonce_prefix = Some(decode_hex(value, "nonce")?),
+                "id" => {
+                    let bytes = decode_hex(value, "id")?;
+                    file_id = Some(
+                        <FileId>::try_from(bytes).map_err(|_| invalid_key_file("bad id length"))?,
+                    );
+                }
+                "created" => {
+                    created = Some(
+                        value
+                            .parse()
+                            .map_err(|_| invalid_key_file("bad creation time"))?,
+                    )
+                }
+                // Ignore fields added by later versions.
+                _ => {}
+            }
+        }
+
+        let key_file = Self {
+            algorithm: algorithm.ok_or_else(|| invalid_key_file("missing algorithm"))?,
+            key: key.ok_or_else(|| invalid_key_file("missing key"))?,
+            nonce_prefix: nonce_prefix.ok_or_else(|| invalid_key_file("missing nonce"))?,
+            file_id: file_id.ok_or_else(|| invalid_key_file("missing id"))?,
+            created: created.unwrap_or(0),
+        };
+        if key_file.nonce_prefix.len() != key_file.algorithm.nonce_prefix_len() {
+            return Err(invalid_key_file("nonce does not match the algorithm"));
+        }
+        Ok(key_file)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        save_private(path.as_ref(), &self.to_text())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::parse(&Zeroizing::new(std::fs::read_to_string(path)?))
+    }
+
+    /// Whether `text` looks like a key file rather than a bare hex key.
+    pub fn is_key_file(text: &str) -> bool {
+        text.trim_start().starts_with(KEY_FILE_MAGIC)
+    }
+}
+
+/// One key of a [`KeyBundle`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BundleEntry {
+    /// Name of the encrypted file the key was made for.
+    pub file_name: String,
+    pub key_file: KeyFile,
+}
+
+/// Contents of a key bundle: the keys of several encrypted files.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyBundle {
+    pub entries: Vec<BundleEntry>,
+}
+
+impl KeyBundle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the key of the encrypted file named `file_name`. Line breaks in
+    /// the name are replaced, as they would end the field.
+    pub fn push(&mut self, file_name: &str, key_file: KeyFile) {
+        self.entries.push(BundleEntry {
+            file_name: file_name.replace(['\n', '\r'], " "),
+            key_file,
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The key file made for the encrypted file with `header`, if the bundle
+    /// has one.
+    pub fn find(&self, header: &Header) -> Option<&KeyFile> {
+        self.entries
+            .iter()
+            .map(|entry| &entry.key_file)
+            .find(|key_file| key_file.matches(header))
+    }
+
+    pub fn to_text(&self) -> Zeroizing<String> {
+        let mut text = Zeroizing::new(format!("{}\n", KEY_BUNDLE_MAGIC));
+        for entry in &self.entries {
+            text.push('\n');
+            text.push_str(&entry.key_file.to_text());
+            text.push_str(&format!("file: {}\n", entry.file_name));
+        }
+        text
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+        if lines.next() != Some(KEY_BUNDLE_MAGIC) {
+            return Err(invalid_key_file("missing `ENCORA-KEY-BUNDLE 2` line"));
+        }
+
+        // Every key file starts with its own magic line
+        let mut blocks: Vec<Vec<&str>> = Vec::new();
+        for line in lines {
+            match blocks.last_mut() {
+                Some(block) if line != KEY_FILE_MAGIC => block.push(line),
+                None if line != KEY_FILE_MAGIC => {
+                    return Err(invalid_key_file("expected `ENCORA-KEY 2` line"))
+                }
+                _ => blocks.push(vec![line]),
+            }
+        }
+
+        let mut bundle = Self::new();
+        for block in blocks {
+            let file_name = block
+                .iter()
+                .filter_map(|line| line.split_once(':'))
+                .find(|(name, _)| name.trim() == "file")
+                .map_or("", |(_, value)| value.trim());
+            let key_file = KeyFile::parse(&Zeroizing::new(block.join("\n")))?;
+            bundle.push(file_name, key_file);
+        }
+        Ok(bundle)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        save_private(path.as_ref(), &self.to_text())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::parse(&Zeroizing::new(std::fs::read_to_string(path)?))
+    }
+
+    /// Whether `text` looks like a key bundle rather than a single key file.
+    pub fn is_key_bundle(text: &str) -> bool {
+        text.trim_start().starts_with(KEY_BUNDLE_MAGIC)
+    }
+}
+
+/// Writes `text` to `path`, readable only by its owner.
+pub(crate) fn save_private(path: &Path, text: &str) -> Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    io::Write::write_all(&mut options.open(path)?, text.as_bytes())?;
+    Ok(())
+}
+
+fn parse_algorithm(name: &str) -> Result<Algorithm> {
+    Algorithm::from_name(name).ok_or_else(|| invalid_key_file("unknown algorithm"))
+}
+
+fn decode_hex(value: &str, field: &str) -> Result<Vec<u8>> {
+    hex::decode(value).map_err(|_| invalid_key_file(&format!("bad {}", field)))
+}
+
+fn invalid_key_file(reason: &str) -> EncoraError {
+    EncoraError::InvalidKey(format!("not a valid key file: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_file_round_trips_through_text_and_disk() {
+        let header = Header::generate(Algorithm::AES_256_GCM);
+        let key_file = KeyFile::new(&header, Key::generate());
+        assert!(key_file.matches(&header));
+        assert!(!key_file.matches(&Header::generate(Algorithm::AES_256_GCM)));
+        assert!(KeyFile::is_key_file(&key_file.to_text()));
+        assert_eq!(KeyFile::parse(&key_file.to_text()).unwrap(), key_file);
+
+        let path = std::env::temp_dir().join(format!(
+            "encora-keyfile-{:016x}.{}",
+            rand::random::<u64>(),
+            KEY_FILE_EXTENSION
+        ));
+        key_file.save(&path).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        assert_eq!(KeyFile::load(&path).unwrap(), key_file);
+        std::fs::remove_file(path).unwrap();
+
+        // Unknown fields are left for later versions
+        let extended = format!("{}comment: from a later version\n", *key_file.to_text());
+        assert_eq!(KeyFile::parse(&extended).unwrap(), key_file);
+    }
+
+    #[test]
+    fn rejects_malformed_key_files() {
+        let header = Header::generate(Algorithm::XCHACHA20_POLY1305);
+        let text = KeyFile::new(&header, Key::generate()).to_text();
+        let replace = |field: &str, value: &str| {
+            text.lines()
+                .map(|line| match line.split_once(':') {
+                    Some((name, _)) if name == field => format!("{}: {}", field, value),
+                    _ => line.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        let without = |field: &str| {
+            text.lines()
+                .filter(|line| !line.starts_with(&format!("{}:", field)))
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        for bad in [
+            text.replacen(KEY_FILE_MAGIC, "ENCORA-KEY 1", 1),
+            replace("algorithm", "ROT13"),
+            replace("key", "00"),
+            replace("nonce", "0011"),
+            replace("nonce", "not hex"),
+            replace("id", "0011"),
+            without("key"),
+            without("id"),
+            format!("{}no separator\n", *text),
+        ] {
+            assert!(
+                matches!(KeyFile::parse(&bad), Err(EncoraError::InvalidKey(_))),
+                "{}",
+                bad
+            );
+        }
+        assert!(!KeyFile::is_key_file(&hex::encode([0u8; 32])));
+        assert!(matches!(
+            KeyBundle::parse(&text),
+            Err(EncoraError::InvalidKey(_))
+        ));
+    }
+
+    #[test]
+    fn bundle_finds_the_key_of_each_file_by_its_header() {
+        let headers: Vec<_> = (0..3)
+            .map(|_| Header::generate(Algorithm::XCHACHA20_POLY1305))
+            .collect();
+        let keys: Vec<_> = headers.iter().map(|_| Key::generate()).collect();
+        let mut bundle = KeyBundle::new();
+        for (number, (header, key)) in headers.iter().zip(&keys).enumerate() {
+            bundle.push(
+                &format!("file {}", number),
+                KeyFile::new(header, key.clone()),
+            );
+        }
+
+        let bundle = KeyBundle::parse(&bundle.to_text()).unwrap();
+        for (header, key) in headers.iter().zip(&keys) {
+            assert_eq!(&bundle.find(header).unwrap().key, key);
+        }
+        assert!(bundle
+            .find(&Header::generate(Algorithm::XCHACHA20_POLY1305))
+            .is_none());
+    }
+
+    #[test]
+    fn bundle_ignores_a_key_for_another_file_with_the_same_id() {
+        let header = Header::generate(Algorithm::XCHACHA20_POLY1305);
+        let mut bundle = KeyBundle::new();
+        bundle.push("file", KeyFile::new(&header, Key::generate()));
+
+        // Same id, different nonce prefix: not the file the key was made for
+        let mut other = Header::generate(Algorithm::XCHACHA20_POLY1305);
+        other.file_id = header.file_id;
+        assert!(bundle.find(&other).is_none());
+        assert!(bundle.find(&header).is_some());
+    }
+}
@@ -0,0 +1,565 @@
+//! Keyslots, in the style of LUKS: independent ways to unlock the same file key.
+//!
+//! The chunks of an Encora file are encrypted with a random file key. Each
+//! keyslot in the header holds a copy of that key wrapped with something a
+//! user has: a passphrase, the key from a key file, or an X25519 identity.
+//! Chunks are authenticated against the fixed part of the header only, so
+//! slots can be added and removed by rewriting the header in place, however
+//! large the file is. Removing a slot revokes whoever used it; anyone holding
+//! the file key itself keeps access.
+//!
+//! Every slot seals the file key with ChaCha20-Poly1305 under a
+//! key-encryption key of its own and an all-zero nonce:
+//!
+//! | slot       | key-encryption key                                               |
+//! |------------|------------------------------------------------------------------|
+//! | passphrase | Argon2id(passphrase, random salt)                                |
+//! | key        | HKDF-SHA256(key, salt = nonce prefix, info = "encora key slot v1") |
+//! | public key | see [`super::recipient`]                                         |
+//!
+//! A key-encryption key only ever wraps the key of one file, so the fixed
+//! nonce is never reused with different plaintext.
+//!
+//! In the header every slot takes [`KEYSLOT_LEN`] bytes, zero-padded: a kind
+//! byte (`0` empty, `1` passphrase, `2` key, `3` public key) followed by
+//!
+//! - passphrase: Argon2id salt (16), memory, time and parallelism costs (`u32` each), wrapped key (48)
+//! - key: key id (4), wrapped key (48)
+//! - public key: key id (4), ephemeral public key (32), wrapped key (48)
+//!
+//! Key ids are the first bytes of the BLAKE2b-256 hash of the slot's key or
+//! public key, so the slot belonging to a key file or colleague can be found.
+
+use super::error::{EncoraError, Result};
+use super::header::{read_header, Header};
+use super::key::{KdfParams, Key, Secret, KEY_LEN};
+use super::recipient::{Recipient, Stanza};
+use super::shamir::combine_shares;
+use super::stream::TAG_LEN;
+use blake2::digest::consts::U32;
+use blake2::{Blake2b, Digest};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::ChaCha20Poly1305;
+use hkdf::Hkdf;
+use sha2::Sha256;
+use std::fs::OpenOptions;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use zeroize::Zeroizing;
+
+/// Bytes of a wrapped file key: the encrypted key and its tag.
+pub const WRAPPED_KEY_LEN: usize = KEY_LEN + TAG_LEN;
+
+/// Bytes of the key id stored in key and public-key slots.
+pub const KEY_ID_LEN: usize = 4;
+
+/// Bytes every keyslot takes up in the header, whatever its kind.
+pub const KEYSLOT_LEN: usize = 96;
+
+/// Keyslots reserved in new headers, so slots can be added later in place.
+pub const DEFAULT_KEYSLOTS: usize = 8;
+
+/// Most keyslots a header can hold; it stores the count in a byte.
+pub const MAX_KEYSLOTS: usize = u8::MAX as usize;
+
+/// Most passphrase keyslots a file can have, so a passphrase is put through
+/// Argon2 at most this many times when unlocking.
+pub const MAX_PASSPHRASE_SLOTS: usize = 4;
+
+const KIND_EMPTY: u8 = 0;
+const KIND_PASSPHRASE: u8 = 1;
+const KIND_KEY: u8 = 2;
+const KIND_RECIPIENT: u8 = 3;
+
+const KEY_SLOT_INFO: &[u8] = b"encora key slot v1";
+
+pub type WrappedKey = [u8; WRAPPED_KEY_LEN];
+pub type KeyId = [u8; KEY_ID_LEN];
+
+/// One wrapped copy of the file key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeySlot {
+    /// Unlocked with a passphrase, through Argon2id.
+    Passphrase {
+        kdf: KdfParams,
+        wrapped_key: WrappedKey,
+    },
+    /// Unlocked with a 256-bit key, usually from a key file.
+    Key { id: KeyId, wrapped_key: WrappedKey },
+    /// Unlocked with the identity of an X25519 recipient.
+    Recipient(Stanza),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeySlotKind {
+    Passphrase,
+    Key,
+    Recipient,
+}
+
+/// What a new keyslot will be unlocked with.
+#[derive(Debug, Clone, Copy)]
+pub enum NewKeySlot<'a> {
+    Passphrase(&'a str),
+    Key(&'a Key),
+    Recipient(&'a Recipient),
+}
+
+impl KeySlot {
+    /// Wraps `file_key` for the file with `header`.
+    pub fn new(file_key: &Key, new_slot: NewKeySlot, header: &Header) -> Result<Self> {
+        match new_slot {
+            NewKeySlot::Passphrase(passphrase) => {
+                if passphrase.is_empty() {
+                    return Err(EncoraError::InvalidKey(
+                        "passphrase must not be empty".into(),
+                    ));
+                }
+                let kdf = KdfParams::generate();
+                let wrapped_key = wrap_key(kdf.derive_key(passphrase)?.as_bytes(), file_key);
+                Ok(KeySlot::Passphrase { kdf, wrapped_key })
+            }
+            NewKeySlot::Key(slot_key) => Ok(KeySlot::Key {
+                id: key_id(slot_key.as_bytes()),
+                wrapped_key: wrap_key(&key_slot_kek(slot_key, header), file_key),
+            }),
+            NewKeySlot::Recipient(recipient) => {
+                Stanza::wrap(file_key, recipient).map(KeySlot::Recipient)
+            }
+        }
+    }
+
+    pub fn kind(&self) -> KeySlotKind {
+        match self {
+            KeySlot::Passphrase { .. } => KeySlotKind::Passphrase,
+            KeySlot::Key { .. } => KeySlotKind::Key,
+            KeySlot::Recipient(_) => KeySlotKind::Recipient,
+        }
+    }
+
+    /// Whether this slot was made for the key file key `slot_key`.
+    pub fn is_for_key(&self, slot_key: &Key) -> bool {
+        matches!(self, KeySlot::Key { id, .. } if *id == key_id(slot_key.as_bytes()))
+    }
+
+    /// Whether this slot was made for `recipient`.
+    pub fn is_for_recipient(&self, recipient: &Recipient) -> bool {
+        matches!(self, KeySlot::Recipient(stanza) if stanza.id == recipient.id())
+    }
+
+    /// The file key, if `secret` opens this slot.
+    fn unlock(&self, secret: &Secret, header: &Header) -> Result<Option<Key>> {
+        Ok(match (self, secret) {
+            (KeySlot::Passphrase { kdf, wrapped_key }, Secret::Passphrase(passphrase)) => {
+                unwrap_key(kdf.derive_key(passphrase)?.as_bytes(), wrapped_key)
+            }
+            (KeySlot::Key { wrapped_key, .. }, Secret::Key(slot_key))
+                if self.is_for_key(slot_key) =>
+            {
+                unwrap_key(&key_slot_kek(slot_key, header), wrapped_key)
+            }
+            (KeySlot::Recipient(stanza), Secret::Identity(identity)) => stanza.unwrap(identity),
+            _ => None,
+        })
+    }
+
+    pub(crate) fn write_to(&self, slot: &mut [u8; KEYSLOT_LEN]) {
+        slot.fill(0);
+        let (kind, mut fields) = slot.split_first_mut().expect("keyslots are not empty");
+        match self {
+            KeySlot::Passphrase { kdf, wrapped_key } => {
+                *kind = KIND_PASSPHRASE;
+                put(&mut fields, &kdf.salt);
+                put(&mut fields, &kdf.m_cost.to_be_bytes());
+                put(&mut fields, &kdf.t_cost.to_be_bytes());
+                put(&mut fields, &kdf.p_cost.to_be_bytes());
+                put(&mut fields, wrapped_key);
+            }
+            KeySlot::Key { id, wrapped_key } => {
+                *kind = KIND_KEY;
+                put(&mut fields, id);
+                put(&mut fields, wrapped_key);
+            }
+            KeySlot::Recipient(stanza) => {
+                *kind = KIND_RECIPIENT;
+                put(&mut fields, &stanza.id);
+                put(&mut fields, &stanza.ephemeral);
+                put(&mut fields, &stanza.wrapped_key);
+            }
+        }
+    }
+
+    /// Parses a slot as stored in the header; `None` for an empty slot.
+    pub(crate) fn read_from(slot: &[u8; KEYSLOT_LEN]) -> Result<Option<Self>> {
+        let mut fields = &slot[1..];
+        let keyslot = match slot[0] {
+            KIND_EMPTY => return Ok(None),
+            KIND_PASSPHRASE => {
+                let kdf = KdfParams {
+                    salt: take(&mut fields),
+                    m_cost: u32::from_be_bytes(take(&mut fields)),
+                    t_cost: u32::from_be_bytes(take(&mut fields)),
+                    p_cost: u32::from_be_bytes(take(&mut fields)),
+                };
+                kdf.validate()?;
+                KeySlot::Passphrase {
+                    kdf,
+                    wrapped_key: take(&mut fields),
+                }
+            }
+            KIND_KEY => KeySlot::Key {
+                id: take(&mut fields),
+                wrapped_key: take(&mut fields),
+            },
+            KIND_RECIPIENT => KeySlot::Recipient(Stanza {
+                id: take(&mut fields),
+                ephemeral: take(&mut fields),
+                wrapped_key: take(&mut fields),
+            }),
+            kind => {
+                return Err(EncoraError::CorruptHeader(format!(
+                    "unknown keyslot kind {}",
+                    kind
+                )))
+            }
+        };
+        Ok(Some(keyslot))
+    }
+}
+
+impl std::fmt::Display for KeySlot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeySlot::Passphrase { kdf, .. } => write!(
+                f,
+                "passphrase (Argon2id, m={} KiB, t={}, p={})",
+                kdf.m_cost, kdf.t_cost, kdf.p_cost
+            ),
+            KeySlot::Key { id, .. } => write!(f, "key file (key id {})", hex::encode(id)),
+            KeySlot::Recipient(stanza) => {
+                write!(f, "public key (key id {})", hex::encode(stanza.id))
+            }
+        }
+    }
+}
+
+/// Recovers the file key from `secret`, along with the index of the keyslot
+/// that opened it. The index is `None` when `secret` is the file key itself,
+/// which is only checked once the first chunk is decrypted.
+pub(crate) fn unlock(header: &Header, secret: Secret) -> Result<(Key, Option<usize>)> {
+    let parsed_key;
+    let secret = match secret {
+        Secret::KeyHex(key_hex) => {
+            parsed_key = Key::from_hex(key_hex)?;
+            Secret::Key(&parsed_key)
+        }
+        Secret::Shares(shares) => {
+            parsed_key = combine_shares(shares)?;
+            Secret::Key(&parsed_key)
+        }
+        secret => secret,
+    };
+    if matches!(secret, Secret::Passphrase(_))
+        && header.count_keyslots(KeySlotKind::Passphrase) > MAX_PASSPHRASE_SLOTS
+    {
+        return Err(EncoraError::CorruptHeader(format!(
+            "more than {} passphrase keyslots",
+            MAX_PASSPHRASE_SLOTS
+        )));
+    }
+
+    for (index, keyslot) in header.keyslots.iter().enumerate() {
+        if let Some(keyslot) = keyslot {
+            if let Some(key) = keyslot.unlock(&secret, header)? {
+                return Ok((key, Some(index)));
+            }
+        }
+    }
+
+    match secret {
+        Secret::Key(key) => Ok((key.clone(), None)),
+        Secret::Passphrase(_) if !header.has_keyslot(KeySlotKind::Passphrase) => Err(
+            EncoraError::InvalidKey("file has no passphrase keyslot".into()),
+        ),
+        Secret::Passphrase(_) => Err(EncoraError::WrongKey),
+        Secret::Identity(_) if !header.has_keyslot(KeySlotKind::Recipient) => Err(
+            EncoraError::InvalidKey("file was not encrypted to public keys".into()),
+        ),
+        Secret::Identity(_) => Err(EncoraError::NotARecipient),
+        Secret::KeyHex(_) | Secret::Shares(_) => unreachable!("parsed above"),
+    }
+}
+
+/// Adds a keyslot to the Encora file at `path`, rewriting only its header.
+/// `secret` must open the file. Returns the index of the new slot.
+pub fn add_keyslot<P: AsRef<Path>>(path: P, secret: Secret, new_slot: NewKeySlot) -> Result<usize> {
+    update_keyslots(path.as_ref(), secret, |header, file_key, _| {
+        if matches!(new_slot, NewKeySlot::Passphrase(_))
+            && header.count_keyslots(KeySlotKind::Passphrase) >= MAX_PASSPHRASE_SLOTS
+        {
+            return Err(EncoraError::InvalidInput(format!(
+                "A file can have at most {} passphrase keyslots; remove one first",
+                MAX_PASSPHRASE_SLOTS
+            )));
+        }
+        let keyslot = KeySlot::new(file_key, new_slot, header)?;
+        let index = header
+            .keyslots
+            .iter()
+            .position(Option::is_none)
+            .ok_or_else(|| {
+                EncoraError::InvalidInput(format!(
+                    "All {} keyslots are in use; remove one first",
+                    header.keyslots.len()
+                ))
+            })?;
+        header.keyslots[index] = Some(keyslot);
+        Ok(index)
+    })
+}
+
+/// Removes keyslot `index` from the Encora file at `path`, rewriting only its
+/// header. `secret` must open the file. Returns the removed slot.
+pub fn remove_keyslot<P: AsRef<Path>>(path: P, secret: Secret, index: usize) -> Result<KeySlot> {
+    update_keyslots(path.as_ref(), secret, |header, _, unlocked_by| {
+        let keyslot = header
+            .keyslots
+            .get_mut(index)
+            .and_then(Option::take)
+            .ok_or_else(|| EncoraError::InvalidInput(format!("Keyslot {} is empty", index)))?;
+        // Without any slot left, only the file key opens the file, and the
+        // caller has only shown they know it if it was not unlocked by a slot.
+        if unlocked_by.is_some() && header.keyslots.iter().all(Option::is_none) {
+            return Err(EncoraError::InvalidInput(
+                "Cannot remove the last keyslot; the file could no longer be decrypted".into(),
+            ));
+        }
+        Ok(keyslot)
+    })
+}
+
+/// Unlocks the file at `path` with `secret`, lets `change` edit the keyslots
+/// and writes the header back over the old one, which has the same size.
+///
+/// Only the header is rewritten, so that changing the keyslots of a large
+/// file takes no longer than of a small one; copying the ciphertext through
+/// a temporary file would not. The header is synced to disk and read back
+/// through a new handle, so a write that did not stick fails here instead of
+/// when the file is next opened. Every slot holds the same file key and the
+/// ciphertext is never touched, so a write cut short by a crash can at worst
+/// spoil the slots being changed.
+fn update_keyslots<T>(
+    path: &Path,
+    secret: Secret,
+    change: impl FnOnce(&mut Header, &Key, Option<usize>) -> Result<T>,
+) -> Result<T> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let mut header = Header::read_from(&mut file)?;
+    let (file_key, unlocked_by) = unlock(&header, secret)?;
+    if unlocked_by.is_none() {
+        verify_file_key(&header, &file_key, &mut file)?;
+    }
+
+    let old_len = header.to_bytes().len();
+    let result = change(&mut header, &file_key, unlocked_by)?;
+    header.validate()?;
+    let bytes = header.to_bytes();
+    debug_assert_eq!(bytes.len(), old_len, "keyslot changes keep the header size");
+
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&bytes)?;
+    file.sync_all()?;
+    drop(file);
+    if read_header(path)?.to_bytes() != bytes {
+        return Err(EncoraError::CorruptHeader(
+            "the new keyslots did not read back as written".into(),
+        ));
+    }
+    Ok(result)
+}
+
+/// Checks a file key that did not come out of a keyslot by decrypting the
+/// first chunk, so it is not wrapped into a new slot by mistake.
+fn verify_file_key<R: Read>(header: &Header, file_key: &Key, reader: &mut R) -> Result<()> {
+    let first_chunk = header.chunk_size as u64 + TAG_LEN as u64;
+    let result = header.algorithm.cipher().decrypt_stream(
+        file_key,
+        header,
+        &mut reader.take(first_chunk),
+        &mut io::sink(),
+        false,
+    );
+    match result {
+        // Only the first chunk was read, so a longer file looks truncated.
+        Ok(_) | Err(EncoraError::Truncated) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Short id of a key, stored in its slot so the slot can be found again.
+pub(crate) fn key_id(key: &[u8]) -> KeyId {
+    let digest = Blake2b::<U32>::digest(key);
+    digest[..KEY_ID_LEN]
+        .try_into()
+        .expect("digest is longer than a key id")
+}
+
+/// Seals `file_key` under the single-use key-encryption key `kek`.
+pub(crate) fn wrap_key(kek: &[u8; KEY_LEN], file_key: &Key) -> WrappedKey {
+    ChaCha20Poly1305::new(kek.into())
+        .encrypt(&Default::default(), file_key.as_bytes().as_slice())
+        .expect("a key is well within the ChaCha20-Poly1305 message limit")
+        .try_into()
+        .expect("wrapped key is key plus tag")
+}
+
+/// The file key sealed in `wrapped_key`, if `kek` is the key that sealed it.
+pub(crate) fn unwrap_key(kek: &[u8; KEY_LEN], wrapped_key: &WrappedKey) -> Option<Key> {
+    let key = Zeroizing::new(
+        ChaCha20Poly1305::new(kek.into())
+            .decrypt(&Default::default(), wrapped_key.as_slice())
+            .ok()?,
+    );
+    Key::from_slice(&key)
+}
+
+/// HKDF-SHA256 of `ikm` as a key-encryption key.
+pub(crate) fn derive_kek(ikm: &[u8], salt: &[u8], info: &[u8]) -> Zeroizing<[u8; KEY_LEN]> {
+    let mut kek = Zeroizing::new([0u8; KEY_LEN]);
+    Hkdf::<Sha256>::new(Some(salt), ikm)
+        .expand(info, kek.as_mut_slice())
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    kek
+}
+
+fn key_slot_kek(slot_key: &Key, header: &Header) -> Zeroizing<[u8; KEY_LEN]> {
+    derive_kek(slot_key.as_bytes(), &header.nonce_prefix, KEY_SLOT_INFO)
+}
+
+/// Copies `bytes` to the start of `fields` and moves past them.
+fn put(fields: &mut &mut [u8], bytes: &[u8]) {
+    let (head, rest) = std::mem::take(fields).split_at_mut(bytes.len());
+    head.copy_from_slice(bytes);
+    *fields = rest;
+}
+
+/// Splits the next `N` bytes off `fields`.
+fn take<const N: usize>(fields: &mut &[u8]) -> [u8; N] {
+    let (head, rest) = fields.split_at(N);
+    *fields = rest;
+    head.try_into().expect("split at N")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::header::read_header;
+    use crate::crypto::{decrypt, encrypt_file, EncryptOptions};
+    use std::fs::{self, File};
+    use std::path::PathBuf;
+
+    /// An Encora file with no keyslots in a new scratch directory, and its
+    /// file key.
+    fn encrypted_file() -> (PathBuf, Key) {
+        let dir =
+            std::env::temp_dir().join(format!("encora-keyslot-{:016x}", rand::random::<u64>()));
+        fs::create_dir(&dir).unwrap();
+        let input = dir.join("plain.txt");
+        fs::write(&input, b"keyslot test").unwrap();
+        let encrypted = dir.join("plain.txt.encora");
+        let key = encrypt_file(&input, &encrypted, &EncryptOptions::default()).unwrap();
+        (encrypted, key)
+    }
+
+    fn open(path: &Path, secret: Secret) -> Result<Vec<u8>> {
+        let mut plaintext = Vec::new();
+        decrypt(File::open(path)?, &mut plaintext, secret)?;
+        Ok(plaintext)
+    }
+
+    fn remove_scratch(path: &Path) {
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn adds_and_removes_keyslots() {
+        let (path, file_key) = encrypted_file();
+        let (first, second) = (Key::generate(), Key::generate());
+        let index = add_keyslot(&path, Secret::Key(&file_key), NewKeySlot::Key(&first)).unwrap();
+        add_keyslot(&path, Secret::Key(&first), NewKeySlot::Key(&second)).unwrap();
+        let header = read_header(&path).unwrap();
+        assert_eq!(header.count_keyslots(KeySlotKind::Key), 2);
+        assert!(header.keyslots[index].as_ref().unwrap().is_for_key(&first));
+        assert_eq!(open(&path, Secret::Key(&first)).unwrap(), b"keyslot test");
+
+        let removed = remove_keyslot(&path, Secret::Key(&second), index).unwrap();
+        assert!(removed.is_for_key(&first));
+        assert_eq!(
+            read_header(&path).unwrap().count_keyslots(KeySlotKind::Key),
+            1
+        );
+        assert!(matches!(
+            open(&path, Secret::Key(&first)),
+            Err(EncoraError::WrongKey)
+        ));
+        assert_eq!(open(&path, Secret::Key(&second)).unwrap(), b"keyslot test");
+        remove_scratch(&path);
+    }
+
+    #[test]
+    fn rewrites_only_the_header() {
+        let (path, file_key) = encrypted_file();
+        let before = fs::read(&path).unwrap();
+        let header_len = read_header(&path).unwrap().to_bytes().len();
+
+        add_keyslot(
+            &path,
+            Secret::Key(&file_key),
+            NewKeySlot::Key(&Key::generate()),
+        )
+        .unwrap();
+        let after = fs::read(&path).unwrap();
+        assert_eq!(after.len(), before.len());
+        assert_ne!(after[..header_len], before[..header_len]);
+        assert_eq!(after[header_len..], before[header_len..]);
+        remove_scratch(&path);
+    }
+
+    #[test]
+    fn keeps_the_last_keyslot_unless_the_file_key_is_shown() {
+        let (path, file_key) = encrypted_file();
+        let slot_key = Key::generate();
+        let index = add_keyslot(&path, Secret::Key(&file_key), NewKeySlot::Key(&slot_key)).unwrap();
+
+        let error = remove_keyslot(&path, Secret::Key(&slot_key), index).unwrap_err();
+        assert!(matches!(error, EncoraError::InvalidInput(_)));
+        assert!(read_header(&path).unwrap().keyslots[index].is_some());
+
+        remove_keyslot(&path, Secret::Key(&file_key), index).unwrap();
+        assert!(read_header(&path)
+            .unwrap()
+            .keyslots
+            .iter()
+            .all(Option::is_none));
+        assert_eq!(
+            open(&path, Secret::Key(&file_key)).unwrap(),
+            b"keyslot test"
+        );
+        remove_scratch(&path);
+    }
+
+    #[test]
+    fn refuses_changes_without_a_key_that_opens_the_file() {
+        let (path, _) = encrypted_file();
+        let before = fs::read(&path).unwrap();
+        let error = add_keyslot(
+            &path,
+            Secret::Key(&Key::generate()),
+            NewKeySlot::Key(&Key::generate()),
+        )
+        .unwrap_err();
+        assert!(matches!(error, EncoraError::WrongKey));
+        assert_eq!(fs::read(&path).unwrap(), before);
+        remove_scratch(&path);
+    }
+}
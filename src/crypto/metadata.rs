@@ -0,0 +1,291 @@
+//! Facts about the original file, stored encrypted at the start of the payload.
+//!
+//! When the header has the metadata flag set, the plaintext begins with this
+//! block, so the name, size, modification time and permissions of the
+//! original file are only visible to someone who can decrypt it:
+//!
+//! ```text
+//! u16  length of the rest of the block
+//! u8   fields present (0x01 modification time, 0x02 permissions)
+//! u64  size in bytes
+//! i64  modification time, seconds since the Unix epoch
+//! u32  modification time, nanoseconds
+//! u32  Unix permission bits
+//! u16  name length, followed by the UTF-8 file name
+//! ```
+//!
+//! All integers are big-endian. Fields that are not present are zero. Later
+//! versions may append fields, which older readers skip.
+
+use super::error::{EncoraError, Result};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const HAS_MODIFIED: u8 = 0x01;
+const HAS_MODE: u8 = 0x02;
+
+/// Bytes of the block after its length, without the name.
+const FIXED_LEN: usize = 1 + 8 + 8 + 4 + 4 + 2;
+
+/// The original file as it was when it was encrypted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMetadata {
+    /// File name without any directory; lossy if it was not valid UTF-8.
+    pub name: String,
+    /// Size in bytes; zero for a directory.
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    /// Unix permission bits.
+    pub mode: Option<u32>,
+}
+
+impl FileMetadata {
+    /// Reads the metadata of the file at `path`.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Ok(Self::from_metadata(name, &std::fs::metadata(path)?))
+    }
+
+    /// Takes the size, modification time and permissions from `metadata`.
+    pub fn from_metadata(name: String, metadata: &std::fs::Metadata) -> Self {
+        #[cfg(unix)]
+        let mode = {
+            use std::os::unix::fs::PermissionsExt;
+            Some(metadata.permissions().mode() & 0o7777)
+        };
+        #[cfg(not(unix))]
+        let mode = None;
+        Self {
+            name,
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+            mode,
+        }
+    }
+
+    /// The stored name if it is a plain file name, so it is safe to create
+    /// in a directory of the user's choice.
+    pub fn file_name(&self) -> Option<&str> {
+        let name = self.name.as_str();
+        let plain = !name.is_empty()
+            && !name.contains(['/', '\\', '\0'])
+            && Path::new(name).file_name() == Some(name.as_ref());
+        plain.then_some(name)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let name = &self.name.as_bytes()[..self.name.len().min(u16::MAX as usize - FIXED_LEN)];
+        let (seconds, nanos) = self.modified.map_or((0, 0), unix_time);
+        let mut fields = 0;
+        if self.modified.is_some() {
+            fields |= HAS_MODIFIED;
+        }
+        if self.mode.is_some() {
+            fields |= HAS_MODE;
+        }
+
+        let mut bytes = Vec::with_capacity(2 + FIXED_LEN + name.len());
+        bytes.extend_from_slice(&((FIXED_LEN + name.len()) as u16).to_be_bytes());
+        bytes.push(fields);
+        bytes.extend_from_slice(&self.size.to_be_bytes());
+        bytes.extend_from_slice(&seconds.to_be_bytes());
+        bytes.extend_from_slice(&nanos.to_be_bytes());
+        bytes.extend_from_slice(&self.mode.unwrap_or(0).to_be_bytes());
+        bytes.extend_from_slice(&(name.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(name);
+        bytes
+    }
+
+    /// Reads the block from the start of the decrypted payload.
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut len = [0u8; 2];
+        read_block(reader, &mut len)?;
+        let mut block = vec![0u8; u16::from_be_bytes(len) as usize];
+        read_block(reader, &mut block)?;
+        if block.len() < FIXED_LEN {
+            return Err(invalid("block is too short"));
+        }
+
+        let mut fields = block.as_slice();
+        let [present] = take(&mut fields);
+        let size = u64::from_be_bytes(take(&mut fields));
+        let seconds = i64::from_be_bytes(take(&mut fields));
+        let nanos = u32::from_be_bytes(take(&mut fields));
+        let mode = u32::from_be_bytes(take(&mut fields));
+        let name_len = u16::from_be_bytes(take(&mut fields)) as usize;
+        let name = fields
+            .get(..name_len)
+            .ok_or_else(|| invalid("name is longer than the block"))?;
+
+        Ok(Self {
+            name: String::from_utf8_lossy(name).into_owned(),
+            size,
+            modified: (present & HAS_MODIFIED != 0)
+                .then(|| from_unix_time(seconds, nanos))
+                .flatten(),
+            mode: (present & HAS_MODE != 0).then_some(mode),
+        })
+    }
+
+    /// Gives the file or directory at `path` the stored modification time
+    /// and permissions. Set-user-ID, set-group-ID and sticky bits are not
+    /// restored.
+    pub fn restore<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let file = if path.is_dir() {
+            File::open(path)?
+        } else {
+            File::options().write(true).open(path)?
+        };
+        if let Some(modified) = self.modified {
+            file.set_modified(modified)?;
+        }
+        #[cfg(unix)]
+        if let Some(mode) = self.mode {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(std::fs::Permissions::from_mode(mode & 0o777))?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads part of the block; an authenticated payload that ends inside it was
+/// written wrongly rather than cut short.
+fn read_block<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<()> {
+    reader
+        .read_exact(buf)
+        .map_err(|e| match EncoraError::from(e) {
+            EncoraError::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                invalid("payload ends inside the block")
+            }
+            e => e,
+        })
+}
+
+pub(crate) fn unix_time(time: SystemTime) -> (i64, u32) {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(after) => (after.as_secs() as i64, after.subsec_nanos()),
+        Err(e) => {
+            let before = e.duration();
+            match before.subsec_nanos() {
+                0 => (-(before.as_secs() as i64), 0),
+                nanos => (-(before.as_secs() as i64) - 1, 1_000_000_000 - nanos),
+            }
+        }
+    }
+}
+
+pub(crate) fn from_unix_time(seconds: i64, nanos: u32) -> Option<SystemTime> {
+    if nanos >= 1_000_000_000 {
+        return None;
+    }
+    let time = if seconds >= 0 {
+        UNIX_EPOCH.checked_add(Duration::from_secs(seconds as u64))?
+    } else {
+        UNIX_EPOCH.checked_sub(Duration::from_secs(seconds.unsigned_abs()))?
+    };
+    time.checked_add(Duration::from_nanos(nanos as u64))
+}
+
+/// Splits the next `N` bytes off `fields`; the caller checked there are enough.
+fn take<const N: usize>(fields: &mut &[u8]) -> [u8; N] {
+    let (head, rest) = fields.split_at(N);
+    *fields = rest;
+    head.try_into().expect("split at N")
+}
+
+fn invalid(reason: &str) -> EncoraError {
+    EncoraError::CorruptHeader(format!("invalid file metadata: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata() -> FileMetadata {
+        FileMetadata {
+            name: "notes.txt".into(),
+            size: 1234,
+            modified: Some(UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789)),
+            mode: Some(0o640),
+        }
+    }
+
+    #[test]
+    fn round_trips_the_block() {
+        let before_epoch = FileMetadata {
+            modified: Some(UNIX_EPOCH - Duration::new(10, 500)),
+            ..metadata()
+        };
+        let without_fields = FileMetadata {
+            modified: None,
+            mode: None,
+            ..metadata()
+        };
+        for metadata in [metadata(), before_epoch, without_fields] {
+            let bytes = metadata.to_bytes();
+            assert_eq!(FileMetadata::read_from(&mut &bytes[..]).unwrap(), metadata);
+        }
+    }
+
+    #[test]
+    fn skips_fields_appended_by_later_versions() {
+        let mut bytes = metadata().to_bytes();
+        let len = u16::from_be_bytes([bytes[0], bytes[1]]) + 3;
+        bytes[..2].copy_from_slice(&len.to_be_bytes());
+        bytes.extend_from_slice(b"new");
+        bytes.extend_from_slice(b"payload");
+        let mut reader = &bytes[..];
+        assert_eq!(FileMetadata::read_from(&mut reader).unwrap(), metadata());
+        assert_eq!(reader, b"payload");
+    }
+
+    #[test]
+    fn refuses_a_cut_or_short_block() {
+        let bytes = metadata().to_bytes();
+        let cut = FileMetadata::read_from(&mut &bytes[..bytes.len() - 1]);
+        assert!(matches!(cut, Err(EncoraError::CorruptHeader(_))));
+        let short = [0u8, 2, 0, 0];
+        let short = FileMetadata::read_from(&mut &short[..]);
+        assert!(matches!(short, Err(EncoraError::CorruptHeader(_))));
+    }
+
+    #[test]
+    fn only_plain_names_are_used() {
+        for name in [
+            "",
+            ".",
+            "..",
+            "../notes.txt",
+            "dir/notes.txt",
+            "dir\\notes.txt",
+            "/notes.txt",
+        ] {
+            let metadata = FileMetadata {
+                name: name.into(),
+                ..metadata()
+            };
+            assert_eq!(metadata.file_name(), None, "{:?} was accepted", name);
+        }
+        assert_eq!(metadata().file_name(), Some("notes.txt"));
+    }
+
+    #[test]
+    fn restores_the_modification_time_and_permissions() {
+        let path =
+            std::env::temp_dir().join(format!("encora-metadata-{:016x}", rand::random::<u64>()));
+        std::fs::write(&path, b"contents").unwrap();
+        metadata().restore(&path).unwrap();
+        let restored = FileMetadata::from_path(&path).unwrap();
+        assert_eq!(restored.modified, metadata().modified);
+        #[cfg(unix)]
+        assert_eq!(restored.mode, Some(0o640));
+        std::fs::remove_file(path).unwrap();
+    }
+}
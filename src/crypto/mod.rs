@@ -1,2 +1,786 @@
+pub mod aes;
+pub mod archive;
+pub(crate) mod atomic;
 pub mod chacha20;
-pub mod aes;
\ No newline at end of file
+pub mod cipher;
+pub mod compress;
+pub mod error;
+pub mod header;
+pub mod key;
+pub mod keyfile;
+pub mod keyslot;
+pub mod metadata;
+pub mod progress;
+pub mod recipient;
+pub mod shamir;
+pub mod shred;
+pub mod stream;
+pub mod vault;
+
+use archive::Archive;
+use atomic::AtomicFile;
+use error::{EncoraError, Result};
+use header::{Algorithm, Header};
+use key::{Key, Secret};
+use keyslot::{unlock, KeySlot, NewKeySlot};
+use metadata::FileMetadata;
+use progress::Progress;
+use rand::Rng;
+use recipient::Recipient;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use stream::DEFAULT_CHUNK_SIZE;
+use zeroize::Zeroizing;
+
+/// Settings for encrypting a new file. Their `Debug` output leaves out the
+/// passphrase and the key.
+#[derive(Clone)]
+pub struct EncryptOptions {
+    pub algorithm: Algorithm,
+    /// Add a keyslot that opens the file with this passphrase.
+    pub passphrase: Option<Zeroizing<String>>,
+    /// Use this file key instead of generating a random one.
+    pub key: Option<Key>,
+    /// Add a keyslot for each of these public keys so their identities can decrypt.
+    pub recipients: Vec<Recipient>,
+    /// Plaintext bytes per chunk.
+    pub chunk_size: u32,
+    /// Compress the data with zstd at this level (see [`compress::LEVELS`])
+    /// before encrypting it, unless a trial shows it does not compress.
+    /// Encrypted folders compress each file on its own.
+    pub compression: Option<i32>,
+    /// Store this, encrypted, in front of the data. [`encrypt_file`] fills it
+    /// in from the input file when it is `None`.
+    pub metadata: Option<FileMetadata>,
+    /// Count the input read here, to show how far encryption has got.
+    pub progress: Option<Progress>,
+}
+
+impl EncryptOptions {
+    pub fn new(algorithm: Algorithm) -> Self {
+        Self {
+            algorithm,
+            passphrase: None,
+            key: None,
+            recipients: Vec::new(),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            compression: None,
+            metadata: None,
+            progress: None,
+        }
+    }
+}
+
+impl std::fmt::Debug for EncryptOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let redacted = |set: bool| if set { "Some(..)" } else { "None" };
+        f.debug_struct("EncryptOptions")
+            .field("algorithm", &self.algorithm)
+            .field(
+                "passphrase",
+                &format_args!("{}", redacted(self.passphrase.is_some())),
+            )
+            .field("key", &format_args!("{}", redacted(self.key.is_some())))
+            .field("recipients", &self.recipients)
+            .field("chunk_size", &self.chunk_size)
+            .field("compression", &self.compression)
+            .field("metadata", &self.metadata)
+            .field("progress", &self.progress)
+            .finish()
+    }
+}
+
+impl Default for EncryptOptions {
+    fn default() -> Self {
+        Self::new(Algorithm::XCHACHA20_POLY1305)
+    }
+}
+
+/// Encrypts everything in `reader` into `writer` as an Encora file and
+/// returns the file key.
+pub fn encrypt<R: Read, W: Write>(
+    reader: &mut R,
+    writer: W,
+    options: &EncryptOptions,
+) -> Result<Key> {
+    encrypt_payload(reader, writer, options, false)
+}
+
+/// Encrypts the tree under the directory `dir` into `writer` as an Encora
+/// archive, storing the directory's name, modification time and permissions
+/// unless `options` already has metadata, and returns the file key. The
+/// entries can then be listed and read one at a time with [`Archive`].
+///
+/// The archive is packed on a second thread while this one encrypts it.
+pub fn encrypt_archive<W: Write>(dir: &Path, writer: W, options: &EncryptOptions) -> Result<Key> {
+    let mut options = options.clone();
+    if options.metadata.is_none() {
+        options.metadata = Some(FileMetadata {
+            size: 0,
+            ..FileMetadata::from_path(dir)?
+        });
+    }
+    // Files are compressed one by one so each can be read on its own, and
+    // counted as they are read rather than as the archive is encrypted.
+    let compression = options.compression.take();
+    if let Some(level) = compression {
+        compress::validate_level(level)?;
+    }
+    let progress = options.progress.take();
+    if let Some(progress) = &progress {
+        progress.set_total(archive::tree_size(dir)?);
+    }
+    let (pipe_reader, pipe_writer) = io::pipe()?;
+    std::thread::scope(|scope| {
+        let packer = scope.spawn(move || -> Result<()> {
+            let writer = BufWriter::new(pipe_writer);
+            archive::write_archive(dir, writer, compression, progress.as_ref())?.flush()?;
+            Ok(())
+        });
+        let mut reader = BufReader::new(pipe_reader);
+        let encrypted = encrypt_payload(&mut reader, writer, &options, true);
+        // Lets the packer stop if encryption failed before reading everything.
+        drop(reader);
+        match packer.join() {
+            Ok(packed) => encrypted.and_then(|key| packed.map(|()| key)),
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    })
+}
+
+fn encrypt_payload<R: Read, W: Write>(
+    reader: &mut R,
+    mut writer: W,
+    options: &EncryptOptions,
+    archive: bool,
+) -> Result<Key> {
+    let key = options.key.clone().unwrap_or_else(Key::generate);
+    let mut header = Header::generate(options.algorithm);
+    header.chunk_size = options.chunk_size;
+    header.metadata = options.metadata.is_some();
+    header.archive = archive;
+
+    let new_slots = options
+        .passphrase
+        .as_ref()
+        .map(|passphrase| NewKeySlot::Passphrase(passphrase))
+        .into_iter()
+        .chain(options.recipients.iter().map(NewKeySlot::Recipient));
+    let keyslots = new_slots
+        .map(|new_slot| KeySlot::new(&key, new_slot, &header).map(Some))
+        .collect::<Result<Vec<_>>>()?;
+    // Keep the reserved empty slots so more can be added later.
+    let capacity = header.keyslots.len().max(keyslots.len());
+    header.keyslots = keyslots;
+    header.keyslots.resize(capacity, None);
+    header.validate()?;
+
+    let mut data: Box<dyn Read + '_> = Box::new(progress::track(reader, options.progress.as_ref()));
+    if let Some(level) = options.compression {
+        compress::validate_level(level)?;
+        let (compressible, sample) = compress::sample(&mut data, level)?;
+        data = Box::new(Cursor::new(sample).chain(data));
+        if compressible {
+            header.compressed = true;
+            data = compress::compressor(data, level)?;
+        }
+    }
+
+    let metadata = options.metadata.as_ref().map(FileMetadata::to_bytes);
+    let mut plaintext = Cursor::new(metadata.unwrap_or_default()).chain(data);
+    options
+        .algorithm
+        .cipher()
+        .encrypt_stream(&key, &header, &mut plaintext, &mut writer)?;
+    Ok(key)
+}
+
+/// Decrypts the Encora file in `reader` into `writer` and returns its header.
+///
+/// Plaintext is written chunk by chunk as it is authenticated, so on error
+/// `writer` may hold a verified prefix of the data that should be discarded.
+/// Stored metadata is checked and skipped; use [`decrypt_with`] to get it.
+/// The data of an encrypted folder is written as the archive described in
+/// [`archive`]; use [`Archive`] to read it.
+pub fn decrypt<R: Read, W: Write>(reader: R, writer: &mut W, secret: Secret) -> Result<Header> {
+    decrypt_with(reader, secret, |_| Ok(writer)).map(|(header, _, _)| header)
+}
+
+/// Decrypts the Encora file in `reader` into the writer returned by `open`,
+/// which is only called once the key has been found to be right and gets the
+/// stored metadata, if any, e.g. to name the output after the original file.
+/// Returns the header, the metadata and the writer.
+pub fn decrypt_with<R, W, F>(
+    reader: R,
+    secret: Secret,
+    open: F,
+) -> Result<(Header, Option<FileMetadata>, W)>
+where
+    R: Read,
+    W: Write,
+    F: FnOnce(Option<&FileMetadata>) -> Result<W>,
+{
+    let (header, metadata, mut plaintext) = open_decrypted(reader, secret)?;
+    let mut writer = open(metadata.as_ref())?;
+    io::copy(&mut plaintext, &mut writer)?;
+    writer.flush()?;
+    Ok((header, metadata, writer))
+}
+
+/// Reads the header of the Encora file in `reader` and unlocks it. Returns
+/// the header, the stored metadata and a reader over the decrypted and
+/// decompressed data. The first chunk has been authenticated by then, so a
+/// wrong key is reported here rather than by the reader.
+pub fn open_decrypted<'a, R: Read + 'a>(
+    mut reader: R,
+    secret: Secret,
+) -> Result<(Header, Option<FileMetadata>, Box<dyn Read + 'a>)> {
+    let header = Header::read_from(&mut reader)?;
+    let (key, unlocked_by) = unlock(&header, secret)?;
+    let mut plaintext = header.algorithm.cipher().decrypt_reader(
+        &key,
+        &header,
+        Box::new(reader),
+        unlocked_by.is_some(),
+    )?;
+
+    // Reading the metadata opens the first chunk, which proves the key.
+    let metadata = if header.metadata {
+        Some(FileMetadata::read_from(&mut plaintext)?)
+    } else {
+        let mut first = [0u8; 1];
+        let n = plaintext.read(&mut first)?;
+        plaintext = Box::new(Cursor::new(first[..n].to_vec()).chain(plaintext));
+        None
+    };
+    if header.compressed {
+        plaintext = compress::decompressor(plaintext)?;
+    }
+    Ok((header, metadata, plaintext))
+}
+
+/// Decrypts just enough of the file at `path` to read its stored metadata.
+pub fn read_metadata<P: AsRef<Path>>(path: P, secret: Secret) -> Result<Option<FileMetadata>> {
+    let mut reader = BufReader::new(File::open(path.as_ref())?);
+    let header = Header::read_from(&mut reader)?;
+    if !header.metadata {
+        return Ok(None);
+    }
+    let (key, unlocked_by) = unlock(&header, secret)?;
+    let mut plaintext = header.algorithm.cipher().decrypt_reader(
+        &key,
+        &header,
+        Box::new(reader),
+        unlocked_by.is_some(),
+    )?;
+    FileMetadata::read_from(&mut plaintext).map(Some)
+}
+
+/// Encrypts the file at `input` into a new file at `output`, storing the
+/// input's name, size, modification time and permissions with the data unless
+/// `options` already has metadata. Nothing is left at `output` if encryption
+/// fails.
+pub fn encrypt_file<P: AsRef<Path>, Q: AsRef<Path>>(
+    input: P,
+    output: Q,
+    options: &EncryptOptions,
+) -> Result<Key> {
+    let mut reader = BufReader::new(File::open(input.as_ref())?);
+    progress::set_total(
+        options.progress.as_ref(),
+        reader.get_ref().metadata()?.len(),
+    );
+    let options = match options.metadata {
+        Some(_) => options.clone(),
+        None => EncryptOptions {
+            metadata: Some(FileMetadata::from_path(input.as_ref())?),
+            ..options.clone()
+        },
+    };
+    let writer = BufWriter::new(create_output(output.as_ref(), input.as_ref())?);
+    let result = encrypt(&mut reader, writer, &options);
+    if result.is_err() {
+        let _ = std::fs::remove_file(output.as_ref());
+    }
+    result
+}
+
+/// Encrypts the directory tree at `input` into a new file at `output`.
+/// Nothing is left at `output` if encryption fails.
+pub fn encrypt_dir<P: AsRef<Path>, Q: AsRef<Path>>(
+    input: P,
+    output: Q,
+    options: &EncryptOptions,
+) -> Result<Key> {
+    let writer = BufWriter::new(create_output(output.as_ref(), input.as_ref())?);
+    let result = encrypt_archive(input.as_ref(), writer, options);
+    if result.is_err() {
+        let _ = fs::remove_file(output.as_ref());
+    }
+    result
+}
+
+/// Decrypts the file at `input` into a new file at `output`, giving it the
+/// stored modification time and permissions. Nothing is left at `output` if
+/// decryption fails.
+pub fn decrypt_file<P: AsRef<Path>, Q: AsRef<Path>>(
+    input: P,
+    output: Q,
+    secret: Secret,
+) -> Result<Header> {
+    let reader = BufReader::new(File::open(input.as_ref())?);
+    let (header, metadata, plaintext) = open_decrypted(reader, secret)?;
+    if header.archive {
+        return Err(EncoraError::InvalidInput(
+            "This is an encrypted folder; decrypt it with decrypt_dir".into(),
+        ));
+    }
+    write_file(
+        plaintext,
+        metadata.as_ref(),
+        input.as_ref(),
+        output.as_ref(),
+    )?;
+    Ok(header)
+}
+
+/// Decrypts the encrypted folder at `input` into a new directory at
+/// `output`, which must not exist yet. Nothing is left at `output` if
+/// decryption fails.
+pub fn decrypt_dir<P: AsRef<Path>, Q: AsRef<Path>>(
+    input: P,
+    output: Q,
+    secret: Secret,
+) -> Result<Header> {
+    let mut archive = Archive::open(input, secret)?;
+    archive.extract_all(output)?;
+    Ok(archive.header().clone())
+}
+
+/// Encrypts the file at `path` and replaces it with the result, storing its
+/// name, size, modification time and permissions with the data unless
+/// `options` already has metadata. The encrypted file keeps the original's
+/// permissions.
+///
+/// The output is written to a temporary file beside the original, synced to
+/// disk and renamed over it, so a crash leaves either the plaintext or the
+/// complete ciphertext. If the file is changed by something else before the
+/// rename, it is left alone and [`EncoraError::SourceModified`] is returned.
+pub fn encrypt_in_place<P: AsRef<Path>>(path: P, options: &EncryptOptions) -> Result<Key> {
+    let path = path.as_ref();
+    let (mut reader, mut output) = AtomicFile::open(path)?;
+    progress::set_total(
+        options.progress.as_ref(),
+        reader.get_ref().metadata()?.len(),
+    );
+    let options = match options.metadata {
+        Some(_) => options.clone(),
+        None => EncryptOptions {
+            metadata: Some(FileMetadata::from_path(path)?),
+            ..options.clone()
+        },
+    };
+    let key = encrypt(&mut reader, output.writer(), &options)?;
+    drop(reader);
+    output.commit(None)?;
+    Ok(key)
+}
+
+/// Decrypts the file at `path` and replaces it with the result, giving it
+/// the stored modification time and permissions. Replacing works as in
+/// [`encrypt_in_place`]; encrypted folders cannot be decrypted in place.
+/// The encrypted file is counted in `progress` as it is read.
+pub fn decrypt_in_place<P: AsRef<Path>>(
+    path: P,
+    secret: Secret,
+    progress: Option<&Progress>,
+) -> Result<Header> {
+    let (reader, mut output) = AtomicFile::open(path.as_ref())?;
+    progress::set_total(progress, reader.get_ref().metadata()?.len());
+    let reader = progress::track(reader, progress);
+    let (header, metadata, mut plaintext) = open_decrypted(reader, secret)?;
+    if header.archive {
+        return Err(EncoraError::InvalidInput(
+            "This is an encrypted folder; it cannot be decrypted in place".into(),
+        ));
+    }
+    io::copy(&mut plaintext, output.writer())?;
+    drop(plaintext);
+    output.commit(metadata.as_ref())?;
+    Ok(header)
+}
+
+/// Writes `plaintext`, read from the file at `input`, to a new file at
+/// `path` and restores `metadata` on it. Nothing is left at `path` if that
+/// fails.
+fn write_file<R: Read>(
+    mut plaintext: R,
+    metadata: Option<&FileMetadata>,
+    input: &Path,
+    path: &Path,
+) -> Result<()> {
+    let mut writer = BufWriter::new(create_output(path, input)?);
+    let result = io::copy(&mut plaintext, &mut writer)
+        .and_then(|_| writer.flush())
+        .map_err(EncoraError::from);
+    drop(writer);
+    let result = result.and_then(|()| metadata.map_or(Ok(()), |metadata| metadata.restore(path)));
+    if result.is_err() {
+        let _ = fs::remove_file(path);
+    }
+    result
+}
+
+/// Creates or empties the file at `path`, unless it is the file at `input`
+/// that is about to be read, which emptying it would destroy.
+fn create_output(path: &Path, input: &Path) -> Result<File> {
+    if same_file(path, input) {
+        return Err(EncoraError::InvalidInput(format!(
+            "{} is the file being read and cannot also be the output; choose another \
+             path, or replace the file in place",
+            path.display()
+        )));
+    }
+    Ok(OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?)
+}
+
+/// Whether `first` and `second` are the same existing file, through links
+/// or different spellings of its path.
+pub fn same_file(first: &Path, second: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        matches!(
+            (fs::metadata(first), fs::metadata(second)),
+            (Ok(first), Ok(second)) if first.dev() == second.dev() && first.ino() == second.ino()
+        )
+    }
+    // Without inode numbers, compare where both paths lead.
+    #[cfg(not(unix))]
+    matches!(
+        (fs::canonicalize(first), fs::canonicalize(second)),
+        (Ok(first), Ok(second)) if first == second
+    )
+}
+
+/// Where the per-algorithm helpers write their output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputLocation {
+    /// Exactly this file.
+    File(PathBuf),
+    /// Next to the source file, named after it.
+    AlongsideSource,
+    /// In this directory (created if missing), named after the source file.
+    Directory(PathBuf),
+}
+
+impl OutputLocation {
+    /// Output path for encrypting `source`; generated names are `<name>_encrypted.<ext>`.
+    pub fn encrypted_path(&self, source: &Path) -> Result<PathBuf> {
+        self.resolve(source, encrypted_file_name(source))
+    }
+
+    /// Output path for decrypting `source`; generated names are `<stem>_decrypted.<ext>`.
+    pub fn decrypted_path(&self, source: &Path) -> Result<PathBuf> {
+        self.resolve(source, decrypted_file_name(source, 1))
+    }
+
+    /// Output path for encrypting `source` under a random name that says
+    /// nothing about it, `<32 hex digits>.encora`.
+    pub fn opaque_encrypted_path(&self, source: &Path) -> Result<PathBuf> {
+        let random: [u8; 16] = rand::thread_rng().gen();
+        let name: String = random.iter().map(|b| format!("{:02x}", b)).collect();
+        self.resolve(source, format!("{}.encora", name))
+    }
+
+    /// Output path for decrypting `source` whose stored metadata is
+    /// `metadata`. A generated name is the first that does not exist yet of
+    /// the original file name, `<stem>_decrypted.<ext>` of it, then
+    /// `<stem>_decrypted (2).<ext>` and so on, so no existing file is chosen.
+    /// Without a usable original name they are made from `source`'s name, as
+    /// in [`Self::decrypted_path`].
+    pub fn restored_path(&self, source: &Path, metadata: Option<&FileMetadata>) -> Result<PathBuf> {
+        if let OutputLocation::File(path) = self {
+            return Ok(path.clone());
+        }
+        let original = metadata.and_then(FileMetadata::file_name);
+        let named_after = original.map_or(source, Path::new);
+        let names = original
+            .map(str::to_string)
+            .into_iter()
+            .chain((1..=MAX_NAME_TRIES).map(|number| decrypted_file_name(named_after, number)));
+        for name in names {
+            let path = self.resolve(source, name)?;
+            // A dangling link counts as taken, as writing would follow it.
+            if fs::symlink_metadata(&path).is_err() {
+                return Ok(path);
+            }
+        }
+        Err(EncoraError::InvalidInput(format!(
+            "No unused name for the decrypted {} after {} tries; choose an output path",
+            named_after.display(),
+            MAX_NAME_TRIES
+        )))
+    }
+
+    fn resolve(&self, source: &Path, file_name: String) -> Result<PathBuf> {
+        match self {
+            OutputLocation::File(path) => Ok(path.clone()),
+            OutputLocation::AlongsideSource => Ok(source.with_file_name(file_name)),
+            OutputLocation::Directory(dir) => {
+                std::fs::create_dir_all(dir)?;
+                Ok(dir.join(file_name))
+            }
+        }
+    }
+}
+
+fn encrypted_file_name(file_path: &Path) -> String {
+    let original_name = file_path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("encrypted_file");
+    let extension = file_path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("");
+    if extension.is_empty() {
+        format!("{}_encrypted", original_name)
+    } else {
+        format!("{}_encrypted.{}", original_name, extension)
+    }
+}
+
+/// How many numbered names [`OutputLocation::restored_path`] tries.
+const MAX_NAME_TRIES: u32 = 1000;
+
+/// `<stem>_decrypted.<ext>` of `file_path`, with ` (<number>)` after
+/// `_decrypted` from the second number on.
+fn decrypted_file_name(file_path: &Path, number: u32) -> String {
+    let original_name = file_path
+        .file_stem()
+        .and_then(|name| name.to_str())
+        .unwrap_or("decrypted_file");
+    let extension = file_path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("");
+    let suffix = match number {
+        1 => "_decrypted".to_string(),
+        number => format!("_decrypted ({})", number),
+    };
+    if extension.is_empty() {
+        format!("{}{}", original_name, suffix)
+    } else {
+        format!("{}{}.{}", original_name, suffix, extension)
+    }
+}
+
+/// Encrypts the file at `file_path` with `algorithm` into `output` under a
+/// random key, adding a keyslot for `passphrase` when given. Returns the hex
+/// key and the output path.
+pub fn encrypt_to_location(
+    file_path: &Path,
+    passphrase: Option<&str>,
+    output: &OutputLocation,
+    algorithm: Algorithm,
+) -> Result<(Zeroizing<String>, PathBuf)> {
+    let options = EncryptOptions {
+        passphrase: passphrase.map(|passphrase| Zeroizing::new(passphrase.to_string())),
+        ..EncryptOptions::new(algorithm)
+    };
+    let output_path = output.encrypted_path(file_path)?;
+    let key = encrypt_file(file_path, &output_path, &options)?;
+    Ok((key.to_hex(), output_path))
+}
+
+/// Decrypts the file at `file_path` into `output` with whichever algorithm its
+/// header names, naming generated outputs after the original file when its
+/// name was stored. Encrypted folders are extracted into a new directory.
+/// The encrypted file is counted in `progress` as it is read. Returns the
+/// header and the output path.
+pub fn decrypt_to_location(
+    file_path: &Path,
+    secret: Secret,
+    output: &OutputLocation,
+    progress: Option<&Progress>,
+) -> Result<(Header, PathBuf)> {
+    decrypt_to_chosen_path(
+        file_path,
+        secret,
+        |metadata| output.restored_path(file_path, metadata),
+        progress,
+    )
+}
+
+/// Decrypts the file at `file_path` like [`decrypt_to_location`], into the
+/// path `choose` returns. `choose` is called once the key has opened the file,
+/// with the metadata stored in it, so the output can be named after the
+/// original without unlocking the file a second time; an error from it stops
+/// the decryption before anything is written.
+pub fn decrypt_to_chosen_path(
+    file_path: &Path,
+    secret: Secret,
+    choose: impl FnOnce(Option<&FileMetadata>) -> Result<PathBuf>,
+    progress: Option<&Progress>,
+) -> Result<(Header, PathBuf)> {
+    let file = File::open(file_path)?;
+    progress::set_total(progress, file.metadata()?.len());
+    let file = progress::track(file, progress);
+    if header::read_header(file_path)?.archive {
+        let mut archive = Archive::new(file, secret)?;
+        let output_path = choose(archive.metadata())?;
+        archive.extract_all(&output_path)?;
+        return Ok((archive.header().clone(), output_path));
+    }
+    let reader = BufReader::new(file);
+    let (header, metadata, plaintext) = open_decrypted(reader, secret)?;
+    let output_path = choose(metadata.as_ref())?;
+    write_file(plaintext, metadata.as_ref(), file_path, &output_path)?;
+    Ok((header, output_path))
+}
+
+/// Shared body of the per-algorithm decryption helpers, which only accept
+/// files encrypted with their own algorithm.
+fn decrypt_expecting(
+    file_path: &Path,
+    secret: Secret,
+    output: &OutputLocation,
+    algorithm: Algorithm,
+) -> Result<PathBuf> {
+    let header = header::read_header(file_path)?;
+    if header.algorithm != algorithm {
+        return Err(EncoraError::InvalidInput(format!(
+            "File was encrypted with {}, not {}",
+            header.algorithm, algorithm
+        )));
+    }
+    decrypt_to_location(file_path, secret, output, None).map(|(_, output_path)| output_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_output_over_input() {
+        let dir = std::env::temp_dir().join(format!("encora-same-{:016x}", rand::random::<u64>()));
+        fs::create_dir(&dir).unwrap();
+        let input = dir.join("same.bin");
+        fs::write(&input, b"plaintext").unwrap();
+
+        let error = encrypt_file(
+            &input,
+            dir.join(".").join("same.bin"),
+            &EncryptOptions::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(error, EncoraError::InvalidInput(_)));
+        assert_eq!(fs::read(&input).unwrap(), b"plaintext");
+
+        let encrypted = dir.join("same.bin.encora");
+        let key = encrypt_file(&input, &encrypted, &EncryptOptions::default()).unwrap();
+        let error = decrypt_file(&encrypted, &encrypted, Secret::Key(&key)).unwrap_err();
+        assert!(matches!(error, EncoraError::InvalidInput(_)));
+        decrypt_file(&encrypted, dir.join("out.bin"), Secret::Key(&key)).unwrap();
+        assert_eq!(fs::read(dir.join("out.bin")).unwrap(), b"plaintext");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn debug_output_leaves_out_secrets() {
+        let key = Key::generate();
+        let options = EncryptOptions {
+            passphrase: Some(Zeroizing::new("correct horse".into())),
+            key: Some(key.clone()),
+            ..EncryptOptions::default()
+        };
+        let debug = format!("{:?}", options);
+        assert!(debug.contains("passphrase: Some(..)"));
+        assert!(!debug.contains("correct horse"));
+        assert!(!debug.contains(&*key.to_hex()));
+    }
+
+    /// A new, empty directory under the system's temporary directory.
+    fn scratch_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("encora-mod-{:016x}", rand::random::<u64>()));
+        fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn restores_the_stored_metadata() {
+        let dir = scratch_dir();
+        let input = dir.join("notes.txt");
+        fs::write(&input, b"some notes").unwrap();
+        let stored = FileMetadata {
+            modified: Some(std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000)),
+            mode: Some(0o640),
+            ..FileMetadata::from_path(&input).unwrap()
+        };
+        stored.restore(&input).unwrap();
+        let encrypted = dir.join("opaque.encora");
+        let key = encrypt_file(&input, &encrypted, &EncryptOptions::default()).unwrap();
+
+        let metadata = read_metadata(&encrypted, Secret::Key(&key))
+            .unwrap()
+            .unwrap();
+        assert_eq!(metadata.name, "notes.txt");
+        assert_eq!(metadata.size, 10);
+        let wrong = read_metadata(&encrypted, Secret::Key(&Key::generate()));
+        assert!(matches!(wrong, Err(EncoraError::WrongKey)));
+
+        let output = dir.join("restored");
+        decrypt_file(&encrypted, &output, Secret::Key(&key)).unwrap();
+        let restored = FileMetadata::from_path(&output).unwrap();
+        assert_eq!(restored.modified, stored.modified);
+        #[cfg(unix)]
+        assert_eq!(restored.mode, Some(0o640));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn never_overwrites_a_file_with_the_restored_name() {
+        let dir = scratch_dir();
+        let output = dir.join("out");
+        fs::create_dir(&output).unwrap();
+        fs::write(output.join("notes.txt"), b"already there").unwrap();
+        let mut written = Vec::new();
+        for contents in ["first", "second"] {
+            let source = dir.join(contents);
+            fs::create_dir(&source).unwrap();
+            fs::write(source.join("notes.txt"), contents).unwrap();
+            let encrypted = source.join("notes.txt.encora");
+            let key = encrypt_file(
+                source.join("notes.txt"),
+                &encrypted,
+                &EncryptOptions::default(),
+            )
+            .unwrap();
+            let location = OutputLocation::Directory(output.clone());
+            let (_, path) =
+                decrypt_to_location(&encrypted, Secret::Key(&key), &location, None).unwrap();
+            written.push(path);
+        }
+
+        assert_eq!(
+            written,
+            [
+                output.join("notes_decrypted.txt"),
+                output.join("notes_decrypted (2).txt")
+            ]
+        );
+        assert_eq!(
+            fs::read(output.join("notes.txt")).unwrap(),
+            b"already there"
+        );
+        assert_eq!(fs::read(&written[0]).unwrap(), b"first");
+        assert_eq!(fs::read(&written[1]).unwrap(), b"second");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
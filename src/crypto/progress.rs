@@ -0,0 +1,233 @@
+//! Reporting how far a long-running encryption or decryption has got, and
+//! stopping it early.
+//!
+//! A [`Progress`] is a handle shared between the thread doing the work and
+//! whoever displays it, such as the GUI. The work counts the input bytes it
+//! has read and, when it knows it, the total it will read; the display polls
+//! both whenever it likes. Encryption counts the plaintext read from the
+//! files, decryption the encrypted file read, header included.
+//!
+//! The handle is also the operation's cancellation token: after
+//! [`Progress::cancel`], the next read fails with [`EncoraError::Cancelled`]
+//! and the operation removes what it had written, as it does on any error.
+
+use super::error::EncoraError;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Bytes processed so far and the total expected, updated by one thread and
+/// read by others, and whether the operation should stop. Clones share the
+/// same state.
+#[derive(Debug, Clone, Default)]
+pub struct Progress {
+    processed: Arc<AtomicU64>,
+    total: Arc<AtomicU64>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Input bytes read so far.
+    pub fn processed(&self) -> u64 {
+        self.processed.load(Ordering::Relaxed)
+    }
+
+    /// Input bytes the operation will read in all, or 0 while unknown.
+    pub fn total(&self) -> u64 {
+        self.total.load(Ordering::Relaxed)
+    }
+
+    /// How much of the total has been processed, from 0.0 to 1.0, if the
+    /// total is known.
+    pub fn fraction(&self) -> Option<f64> {
+        let total = self.total();
+        (total > 0).then(|| (self.processed() as f64 / total as f64).min(1.0))
+    }
+
+    /// Asks the operation to stop as soon as it next reads its input.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_total(&self, total: u64) {
+        self.total.store(total, Ordering::Relaxed);
+    }
+
+    /// Adds `bytes` to the total, for an operation that carries on with more
+    /// work once the first is done.
+    pub(crate) fn add_total(&self, bytes: u64) {
+        self.total.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add(&self, bytes: u64) {
+        self.processed.fetch_add(bytes, Ordering::Relaxed);
+    }
+}
+
+/// Sets the total of `progress`, if there is one.
+pub(crate) fn set_total(progress: Option<&Progress>, total: u64) {
+    if let Some(progress) = progress {
+        progress.set_total(total);
+    }
+}
+
+/// Wraps `inner` so that everything read through it is added to `progress`,
+/// and reading fails once `progress` is cancelled.
+pub(crate) fn track<R>(inner: R, progress: Option<&Progress>) -> Tracked<R> {
+    Tracked {
+        inner,
+        progress: progress.cloned(),
+    }
+}
+
+/// A reader that counts what is read through it; see [`track`].
+pub(crate) struct Tracked<R> {
+    inner: R,
+    progress: Option<Progress>,
+}
+
+impl<R: Read> Read for Tracked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(progress) = &self.progress else {
+            return self.inner.read(buf);
+        };
+        if progress.is_cancelled() {
+            return Err(EncoraError::Cancelled.into());
+        }
+        let n = self.inner.read(buf)?;
+        progress.add(n as u64);
+        Ok(n)
+    }
+}
+
+impl<R: Seek> Seek for Tracked<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::key::Secret;
+    use crate::crypto::{
+        decrypt_in_place, decrypt_to_location, encrypt, encrypt_file, encrypt_in_place,
+        EncryptOptions, OutputLocation,
+    };
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// A new, empty directory under the system's temporary directory.
+    fn scratch_dir() -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("encora-progress-{:016x}", rand::random::<u64>()));
+        fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    /// Options that report to a new [`Progress`], returned beside them.
+    fn tracked() -> (EncryptOptions, Progress) {
+        let progress = Progress::new();
+        let options = EncryptOptions {
+            progress: Some(progress.clone()),
+            ..EncryptOptions::default()
+        };
+        (options, progress)
+    }
+
+    /// A reader of zeros that cancels `progress` once `after` bytes are read.
+    struct CancelAfter {
+        progress: Progress,
+        after: u64,
+        read: u64,
+    }
+
+    impl Read for CancelAfter {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.read >= self.after {
+                self.progress.cancel();
+            }
+            buf.fill(0);
+            self.read += buf.len() as u64;
+            Ok(buf.len())
+        }
+    }
+
+    #[test]
+    fn counts_what_encryption_reads() {
+        let dir = scratch_dir();
+        let input = dir.join("plain.bin");
+        fs::write(&input, vec![1u8; 300_000]).unwrap();
+        let (options, progress) = tracked();
+
+        encrypt_file(&input, dir.join("plain.bin.encora"), &options).unwrap();
+        assert_eq!((progress.processed(), progress.total()), (300_000, 300_000));
+        assert_eq!(progress.fraction(), Some(1.0));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn cancel_stops_encryption_midway() {
+        let (options, progress) = tracked();
+        let mut reader = CancelAfter {
+            progress: progress.clone(),
+            after: 1 << 20,
+            read: 0,
+        };
+        let error = encrypt(&mut reader, io::sink(), &options).unwrap_err();
+        assert!(matches!(error, EncoraError::Cancelled));
+        assert!(progress.processed() >= 1 << 20);
+    }
+
+    #[test]
+    fn cancel_removes_the_partial_output() {
+        let dir = scratch_dir();
+        let input = dir.join("plain.bin");
+        fs::write(&input, vec![1u8; 300_000]).unwrap();
+        let (options, progress) = tracked();
+        progress.cancel();
+
+        let output = dir.join("plain.bin.encora");
+        let error = encrypt_file(&input, &output, &options).unwrap_err();
+        assert!(matches!(error, EncoraError::Cancelled));
+        assert!(!output.exists());
+
+        let key = encrypt_file(&input, &output, &EncryptOptions::default()).unwrap();
+        let location = OutputLocation::Directory(dir.join("out"));
+        let error = decrypt_to_location(&output, Secret::Key(&key), &location, Some(&progress))
+            .unwrap_err();
+        assert!(matches!(error, EncoraError::Cancelled));
+        assert_eq!(fs::read_dir(dir.join("out")).map_or(0, Iterator::count), 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn cancel_leaves_the_file_replaced_in_place_intact() {
+        let dir = scratch_dir();
+        let path = dir.join("plain.bin");
+        fs::write(&path, vec![1u8; 300_000]).unwrap();
+        let (options, progress) = tracked();
+        progress.cancel();
+
+        let error = encrypt_in_place(&path, &options).unwrap_err();
+        assert!(matches!(error, EncoraError::Cancelled));
+        assert_eq!(fs::read(&path).unwrap(), vec![1u8; 300_000]);
+
+        let key = encrypt_in_place(&path, &EncryptOptions::default()).unwrap();
+        let encrypted = fs::read(&path).unwrap();
+        let error = decrypt_in_place(&path, Secret::Key(&key), Some(&progress)).unwrap_err();
+        assert!(matches!(error, EncoraError::Cancelled));
+        assert_eq!(fs::read(&path).unwrap(), encrypted);
+        // Neither left its temporary file behind
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
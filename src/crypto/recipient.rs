@@ -0,0 +1,327 @@
+//! Public-key recipients, in the style of age.
+//!
+//! A file encrypted to recipients still has a random file key, but instead of
+//! being handed to the user it is wrapped once per recipient's X25519 public
+//! key and the wrapped copies ("stanzas") are stored in the header's keyslots.
+//! Each recipient unwraps it with their own identity (private key), so no
+//! shared secret has to be sent around.
+//!
+//! Every stanza uses a fresh ephemeral key pair:
+//!
+//! ```text
+//! shared      = X25519(ephemeral secret, recipient public key)
+//! wrap key    = HKDF-SHA256(ikm = shared, salt = ephemeral public || recipient public,
+//!                           info = "encora x25519 v1")
+//! wrapped key = ChaCha20-Poly1305(wrap key, nonce = 0, file key)
+//! ```
+//!
+//! A wrap key is used exactly once, so the all-zero nonce is safe. Stanzas
+//! carry a short id of the recipient's public key, so decryption only tries
+//! the ones made for the identity and a departing recipient's slot can be
+//! found and removed.
+
+use super::error::{EncoraError, Result};
+use super::key::{Key, KEY_LEN};
+use super::keyslot::{derive_kek, key_id, unwrap_key, wrap_key, KeyId, WrappedKey};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+/// Text form of a recipient: this prefix followed by the hex public key.
+pub const PUBLIC_KEY_PREFIX: &str = "encora-pk-";
+
+/// Text form of an identity: this prefix followed by the hex private key.
+pub const SECRET_KEY_PREFIX: &str = "ENCORA-SK-";
+
+/// Bytes of an X25519 public or private key.
+pub const X25519_KEY_LEN: usize = 32;
+
+const WRAP_INFO: &[u8] = b"encora x25519 v1";
+
+/// A public key files can be encrypted to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Recipient(PublicKey);
+
+impl Recipient {
+    pub fn from_bytes(bytes: [u8; X25519_KEY_LEN]) -> Self {
+        Self(PublicKey::from(bytes))
+    }
+
+    pub fn as_bytes(&self) -> &[u8; X25519_KEY_LEN] {
+        self.0.as_bytes()
+    }
+
+    /// Short id stored in the keyslots made for this recipient.
+    pub fn id(&self) -> KeyId {
+        key_id(self.as_bytes())
+    }
+
+    /// Parses the `encora-pk-...` form produced by `to_string`.
+    pub fn parse(text: &str) -> Result<Self> {
+        let hex_key = text
+            .trim()
+            .strip_prefix(PUBLIC_KEY_PREFIX)
+            .ok_or_else(|| invalid_key(format!("public keys start with {}", PUBLIC_KEY_PREFIX)))?;
+        Ok(Self::from_bytes(*decode_x25519(hex_key)?))
+    }
+}
+
+impl std::fmt::Display for Recipient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", PUBLIC_KEY_PREFIX, hex::encode(self.as_bytes()))
+    }
+}
+
+impl std::str::FromStr for Recipient {
+    type Err = EncoraError;
+
+    fn from_str(text: &str) -> Result<Self> {
+        Self::parse(text)
+    }
+}
+
+/// A private key that opens files encrypted to its [`Recipient`].
+///
+/// Identity files hold the private key on its own line, after `#` comment
+/// lines with the creation time and the public key.
+#[derive(Clone)]
+pub struct Identity(StaticSecret);
+
+impl Identity {
+    pub fn generate() -> Self {
+        Self(StaticSecret::random_from_rng(rand::thread_rng()))
+    }
+
+    pub fn recipient(&self) -> Recipient {
+        Recipient(PublicKey::from(&self.0))
+    }
+
+    /// Parses an identity file, or just its `ENCORA-SK-...` line.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut lines = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+        let hex_key = lines
+            .next()
+            .and_then(|line| line.strip_prefix(SECRET_KEY_PREFIX))
+            .ok_or_else(|| invalid_key(format!("private keys start with {}", SECRET_KEY_PREFIX)))?;
+        if lines.next().is_some() {
+            return Err(invalid_key("an identity file holds a single private key"));
+        }
+        Ok(Self(StaticSecret::from(*decode_x25519(hex_key)?)))
+    }
+
+    pub fn to_text(&self) -> Zeroizing<String> {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let secret_hex = Zeroizing::new(hex::encode(self.0.as_bytes()));
+        Zeroizing::new(format!(
+            "# created: {}\n# public key: {}\n{}{}\n",
+            created,
+            self.recipient(),
+            SECRET_KEY_PREFIX,
+            *secret_hex
+        ))
+    }
+
+    /// Writes the identity file, readable only by the current user.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        std::io::Write::write_all(&mut options.open(path)?, self.to_text().as_bytes())?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::parse(&Zeroizing::new(std::fs::read_to_string(path)?))
+    }
+
+    /// Whether `text` looks like an identity file.
+    pub fn is_identity_file(text: &str) -> bool {
+        text.lines()
+            .map(str::trim)
+            .any(|line| line.starts_with(SECRET_KEY_PREFIX))
+    }
+}
+
+impl std::fmt::Debug for Identity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Identity(..)")
+    }
+}
+
+/// The file key wrapped to one recipient.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stanza {
+    /// [`Recipient::id`] of the recipient.
+    pub id: KeyId,
+    pub ephemeral: [u8; X25519_KEY_LEN],
+    pub wrapped_key: WrappedKey,
+}
+
+impl Stanza {
+    pub fn wrap(file_key: &Key, recipient: &Recipient) -> Result<Self> {
+        let secret = EphemeralSecret::random_from_rng(rand::thread_rng());
+        let ephemeral = PublicKey::from(&secret);
+        let shared = secret.diffie_hellman(&recipient.0);
+        if !shared.was_contributory() {
+            return Err(invalid_key(format!(
+                "{} is not a usable public key",
+                recipient
+            )));
+        }
+
+        let kek = stanza_kek(shared.as_bytes(), &ephemeral, &recipient.0);
+        Ok(Self {
+            id: recipient.id(),
+            ephemeral: *ephemeral.as_bytes(),
+            wrapped_key: wrap_key(&kek, file_key),
+        })
+    }
+
+    /// The file key, if this stanza was made for `identity`.
+    pub fn unwrap(&self, identity: &Identity) -> Option<Key> {
+        let recipient = identity.recipient();
+        if self.id != recipient.id() {
+            return None;
+        }
+        let ephemeral = PublicKey::from(self.ephemeral);
+        let shared = identity.0.diffie_hellman(&ephemeral);
+        if !shared.was_contributory() {
+            return None;
+        }
+
+        let kek = stanza_kek(shared.as_bytes(), &ephemeral, &recipient.0);
+        unwrap_key(&kek, &self.wrapped_key)
+    }
+}
+
+fn stanza_kek(
+    shared: &[u8],
+    ephemeral: &PublicKey,
+    recipient: &PublicKey,
+) -> Zeroizing<[u8; KEY_LEN]> {
+    let mut salt = [0u8; 2 * X25519_KEY_LEN];
+    salt[..X25519_KEY_LEN].copy_from_slice(ephemeral.as_bytes());
+    salt[X25519_KEY_LEN..].copy_from_slice(recipient.as_bytes());
+    derive_kek(shared, &salt, WRAP_INFO)
+}
+
+/// Decodes a hex X25519 key, wiping every copy when it is a private one.
+fn decode_x25519(hex_key: &str) -> Result<Zeroizing<[u8; X25519_KEY_LEN]>> {
+    let bytes = Zeroizing::new(hex::decode(hex_key).map_err(|e| invalid_key(e.to_string()))?);
+    if bytes.len() != X25519_KEY_LEN {
+        return Err(invalid_key(format!(
+            "X25519 keys are {} bytes",
+            X25519_KEY_LEN
+        )));
+    }
+    let mut key = Zeroizing::new([0u8; X25519_KEY_LEN]);
+    key.copy_from_slice(&bytes);
+    Ok(key)
+}
+
+fn invalid_key<S: Into<String>>(reason: S) -> EncoraError {
+    EncoraError::InvalidKey(reason.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{decrypt, encrypt, EncryptOptions, Secret};
+
+    #[test]
+    fn stanza_opens_only_for_its_recipient() {
+        let (identity, stranger) = (Identity::generate(), Identity::generate());
+        let file_key = Key::generate();
+        let stanza = Stanza::wrap(&file_key, &identity.recipient()).unwrap();
+        assert_eq!(stanza.id, identity.recipient().id());
+        assert_eq!(stanza.unwrap(&identity), Some(file_key.clone()));
+        assert_eq!(stanza.unwrap(&stranger), None);
+
+        let mut tampered = stanza.clone();
+        tampered.ephemeral[0] ^= 1;
+        assert_eq!(tampered.unwrap(&identity), None);
+        // Every wrap has an ephemeral key of its own
+        assert_ne!(
+            Stanza::wrap(&file_key, &identity.recipient()).unwrap(),
+            stanza
+        );
+    }
+
+    #[test]
+    fn file_encrypted_to_a_recipient_refuses_other_identities() {
+        let identity = Identity::generate();
+        let options = EncryptOptions {
+            recipients: vec![identity.recipient()],
+            ..EncryptOptions::default()
+        };
+        let mut encrypted = Vec::new();
+        encrypt(&mut &b"for one reader"[..], &mut encrypted, &options).unwrap();
+
+        let error = decrypt(
+            &encrypted[..],
+            &mut Vec::new(),
+            Secret::Identity(&Identity::generate()),
+        )
+        .unwrap_err();
+        assert!(matches!(error, EncoraError::NotARecipient));
+        let mut plaintext = Vec::new();
+        decrypt(&encrypted[..], &mut plaintext, Secret::Identity(&identity)).unwrap();
+        assert_eq!(plaintext, b"for one reader");
+    }
+
+    #[test]
+    fn parses_what_it_prints() {
+        let identity = Identity::generate();
+        let recipient = identity.recipient();
+        assert_eq!(Recipient::parse(&recipient.to_string()).unwrap(), recipient);
+        assert_eq!(
+            Identity::parse(&identity.to_text()).unwrap().recipient(),
+            recipient
+        );
+        assert!(Identity::is_identity_file(&identity.to_text()));
+    }
+
+    #[test]
+    fn rejects_malformed_keys() {
+        let hex_key = hex::encode(Identity::generate().recipient().as_bytes());
+        for text in [
+            hex_key.clone(),
+            format!("{}{}", SECRET_KEY_PREFIX, hex_key),
+            format!("{}{}", PUBLIC_KEY_PREFIX, &hex_key[..62]),
+            format!("{}{}00", PUBLIC_KEY_PREFIX, hex_key),
+            format!("{}{}zz", PUBLIC_KEY_PREFIX, &hex_key[..62]),
+        ] {
+            assert!(
+                matches!(Recipient::parse(&text), Err(EncoraError::InvalidKey(_))),
+                "{}",
+                text
+            );
+        }
+
+        let twice = format!(
+            "{}{}",
+            *Identity::generate().to_text(),
+            *Identity::generate().to_text()
+        );
+        assert!(matches!(
+            Identity::parse(&twice),
+            Err(EncoraError::InvalidKey(_))
+        ));
+        // The all-zero point would make every shared secret zero
+        let zero = Recipient::from_bytes([0; X25519_KEY_LEN]);
+        assert!(matches!(
+            Stanza::wrap(&Key::generate(), &zero),
+            Err(EncoraError::InvalidKey(_))
+        ));
+    }
+}
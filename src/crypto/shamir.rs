@@ -0,0 +1,295 @@
+//! Splitting a file key into shares so that no single person can decrypt.
+//!
+//! [`split_key`] turns a 32-byte key into `count` shares of which any
+//! `threshold` rebuild it with [`combine_shares`], while fewer reveal nothing
+//! about it. This is Shamir's secret sharing over GF(256), byte by byte: each
+//! key byte is the constant term of a random polynomial of degree
+//! `threshold - 1`, and share `x` holds the polynomials evaluated at `x`.
+//!
+//! A share is written as one line of text,
+//!
+//! ```text
+//! encora-share-<16 hex digits of split id>-<threshold>-<share number>-<64 hex digits>
+//! ```
+//!
+//! or saved as a `.encora-share` file holding that line. The split id is
+//! random and the same for every share of one split, so shares of different
+//! keys are not mixed by mistake.
+
+use super::error::{EncoraError, Result};
+use super::key::{Key, KEY_LEN};
+use super::keyfile::save_private;
+use rand::Rng;
+use std::fmt;
+use std::path::Path;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+/// Extension of share files.
+pub const SHARE_FILE_EXTENSION: &str = "encora-share";
+
+/// Start of every share in text form.
+const SHARE_PREFIX: &str = "encora-share-";
+
+/// Bytes of the random id shared by the shares of one split.
+const SPLIT_ID_LEN: usize = 8;
+
+/// One share of a key, wiped from memory when dropped.
+#[derive(Clone, PartialEq, Eq, Zeroize, ZeroizeOnDrop)]
+pub struct Share {
+    /// Shared by every share of the same split.
+    pub split_id: [u8; SPLIT_ID_LEN],
+    /// How many shares rebuild the key.
+    pub threshold: u8,
+    /// The point the share was taken at, from 1 to 255.
+    pub number: u8,
+    value: [u8; KEY_LEN],
+}
+
+impl Share {
+    /// The share in its one-line text form.
+    pub fn to_text(&self) -> Zeroizing<String> {
+        Zeroizing::new(format!(
+            "{}{}-{}-{}-{}",
+            SHARE_PREFIX,
+            hex::encode(self.split_id),
+            self.threshold,
+            self.number,
+            *Zeroizing::new(hex::encode(self.value))
+        ))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let fields = text
+            .trim()
+            .strip_prefix(SHARE_PREFIX)
+            .ok_or_else(|| invalid_share("missing `encora-share-`"))?;
+        let mut fields = fields.split('-');
+        let (Some(split_id), Some(threshold), Some(number), Some(value), None) = (
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+        ) else {
+            return Err(invalid_share("expected four fields"));
+        };
+
+        let mut share = Self {
+            split_id: [0u8; SPLIT_ID_LEN],
+            threshold: threshold
+                .parse()
+                .map_err(|_| invalid_share("bad threshold"))?,
+            number: number
+                .parse()
+                .map_err(|_| invalid_share("bad share number"))?,
+            value: [0u8; KEY_LEN],
+        };
+        hex::decode_to_slice(split_id, &mut share.split_id)
+            .map_err(|_| invalid_share("bad split id"))?;
+        hex::decode_to_slice(value, &mut share.value).map_err(|_| invalid_share("bad value"))?;
+        if share.threshold < 2 || share.number == 0 {
+            return Err(invalid_share("threshold or share number out of range"));
+        }
+        Ok(share)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut text = self.to_text();
+        text.push('\n');
+        save_private(path.as_ref(), &text)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::parse(&Zeroizing::new(std::fs::read_to_string(path)?))
+    }
+
+    /// Whether `text` looks like a share.
+    pub fn is_share(text: &str) -> bool {
+        text.trim_start().starts_with(SHARE_PREFIX)
+    }
+}
+
+impl fmt::Debug for Share {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Share")
+            .field("split_id", &hex::encode(self.split_id))
+            .field("threshold", &self.threshold)
+            .field("number", &self.number)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Splits `key` into `count` shares, any `threshold` of which rebuild it.
+/// The threshold must be at least 2 and at most `count`, which is at most
+/// 255.
+pub fn split_key(key: &Key, threshold: u8, count: u8) -> Result<Vec<Share>> {
+    if threshold < 2 || threshold > count {
+        return Err(EncoraError::InvalidInput(format!(
+            "Cannot split a key into {} share(s) with {} needed; at least 2 must be \
+             needed and no more than there are shares",
+            count, threshold
+        )));
+    }
+    let mut rng = rand::thread_rng();
+    let split_id: [u8; SPLIT_ID_LEN] = rng.gen();
+
+    let mut shares: Vec<Share> = (1..=count)
+        .map(|number| Share {
+            split_id,
+            threshold,
+            number,
+            value: [0u8; KEY_LEN],
+        })
+        .collect();
+    // One polynomial per key byte: the byte, then `threshold - 1` random coefficients
+    let mut coefficients = Zeroizing::new(vec![0u8; threshold as usize]);
+    for (byte, &secret) in key.as_bytes().iter().enumerate() {
+        coefficients[0] = secret;
+        rng.fill(&mut coefficients[1..]);
+        for share in &mut shares {
+            share.value[byte] = evaluate(&coefficients, share.number);
+        }
+    }
+    Ok(shares)
+}
+
+/// Rebuilds the key from shares of one split, at least as many as its
+/// threshold. Extra shares are ignored.
+pub fn combine_shares(shares: &[Share]) -> Result<Key> {
+    let first = shares
+        .first()
+        .ok_or_else(|| EncoraError::InvalidKey("no key shares given".into()))?;
+    if shares
+        .iter()
+        .any(|share| share.split_id != first.split_id || share.threshold != first.threshold)
+    {
+        return Err(EncoraError::InvalidKey(
+            "the shares come from splitting different keys".into(),
+        ));
+    }
+    let mut chosen: Vec<&Share> = Vec::new();
+    for share in shares {
+        if !chosen.iter().any(|other| other.number == share.number) {
+            chosen.push(share);
+        }
+    }
+    let threshold = first.threshold as usize;
+    if chosen.len() < threshold {
+        return Err(EncoraError::InvalidKey(format!(
+            "{} different share(s) given, but {} are needed",
+            chosen.len(),
+            threshold
+        )));
+    }
+    chosen.truncate(threshold);
+
+    // Lagrange interpolation at x = 0; in GF(256) subtracting is XOR
+    let mut key = Zeroizing::new([0u8; KEY_LEN]);
+    for (i, share) in chosen.iter().enumerate() {
+        let mut basis = 1u8;
+        for (j, other) in chosen.iter().enumerate() {
+            if i != j {
+                basis = mul(basis, div(other.number, other.number ^ share.number));
+            }
+        }
+        for (byte, &value) in key.iter_mut().zip(&share.value) {
+            *byte ^= mul(value, basis);
+        }
+    }
+    Ok(Key::from_bytes(*key))
+}
+
+/// The polynomial with `coefficients`, lowest first, at `x`.
+fn evaluate(coefficients: &[u8], x: u8) -> u8 {
+    coefficients
+        .iter()
+        .rev()
+        .fold(0, |result, &coefficient| mul(result, x) ^ coefficient)
+}
+
+/// Multiplication in GF(256) modulo the AES polynomial x^8 + x^4 + x^3 + x + 1,
+/// without branching on the operands.
+fn mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    for _ in 0..8 {
+        product ^= a & (b & 1).wrapping_neg();
+        let carry = (a >> 7).wrapping_neg();
+        a = (a << 1) ^ (0x1b & carry);
+        b >>= 1;
+    }
+    product
+}
+
+/// `a / b` in GF(256); `b` is never 0, as share numbers differ.
+fn div(a: u8, b: u8) -> u8 {
+    // b^254 is the inverse of b, as every non-zero b has b^255 = 1
+    let mut inverse = 1;
+    let mut power = b;
+    for bit in 0..8 {
+        if (254 >> bit) & 1 == 1 {
+            inverse = mul(inverse, power);
+        }
+        power = mul(power, power);
+    }
+    mul(a, inverse)
+}
+
+fn invalid_share(reason: &str) -> EncoraError {
+    EncoraError::InvalidKey(format!("not a valid key share: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn any_threshold_of_the_shares_rebuild_the_key() {
+        let key = Key::generate();
+        let shares = split_key(&key, 3, 5).unwrap();
+        assert_eq!(shares.len(), 5);
+        for chosen in [[0, 1, 2], [0, 2, 4], [4, 3, 1], [1, 2, 3]] {
+            let subset: Vec<Share> = chosen.iter().map(|&i| shares[i].clone()).collect();
+            assert_eq!(combine_shares(&subset).unwrap().as_bytes(), key.as_bytes());
+        }
+        assert_eq!(combine_shares(&shares).unwrap().as_bytes(), key.as_bytes());
+    }
+
+    #[test]
+    fn fewer_than_the_threshold_are_refused() {
+        let key = Key::generate();
+        let shares = split_key(&key, 3, 5).unwrap();
+        let error = combine_shares(&shares[..2]).unwrap_err();
+        assert!(matches!(error, EncoraError::InvalidKey(_)));
+        // A repeated share does not count twice
+        let repeated = [shares[0].clone(), shares[1].clone(), shares[1].clone()];
+        assert!(matches!(
+            combine_shares(&repeated),
+            Err(EncoraError::InvalidKey(_))
+        ));
+    }
+
+    #[test]
+    fn shares_of_different_splits_are_refused() {
+        let key = Key::generate();
+        let (first, second) = (
+            split_key(&key, 2, 3).unwrap(),
+            split_key(&key, 2, 3).unwrap(),
+        );
+        let mixed = [first[0].clone(), second[1].clone()];
+        assert!(matches!(
+            combine_shares(&mixed),
+            Err(EncoraError::InvalidKey(_))
+        ));
+    }
+
+    #[test]
+    fn shares_survive_their_text_form() {
+        let key = Key::generate();
+        let shares: Vec<Share> = split_key(&key, 2, 2)
+            .unwrap()
+            .iter()
+            .map(|share| Share::parse(&share.to_text()).unwrap())
+            .collect();
+        assert_eq!(combine_shares(&shares).unwrap().as_bytes(), key.as_bytes());
+    }
+}
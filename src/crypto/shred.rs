@@ -0,0 +1,327 @@
+//! Best-effort secure deletion of the plaintext once it has been encrypted.
+//!
+//! [`shred`] overwrites every byte of a file several times, syncing each
+//! pass to disk, truncates it, renames it to a random name so the directory
+//! no longer records the original one, and unlinks it. Folders are shredded
+//! file by file; links and the directories themselves are only removed.
+//!
+//! Overwriting only destroys data where the file system writes blocks in
+//! place. Copy-on-write and log-structured file systems (Btrfs, ZFS, APFS,
+//! F2FS), SSDs and flash drives that remap writes, snapshots, backups, swap
+//! and editor temporary files can all keep earlier copies, so the report
+//! lists these limits along with what was done. Full-disk encryption is the
+//! only reliable protection on such storage.
+
+use super::error::{EncoraError, Result};
+use super::progress::Progress;
+use rand::Rng;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Overwrite passes unless the caller asks for a different number.
+pub const DEFAULT_PASSES: u32 = 3;
+
+/// Bytes written at a time while overwriting.
+const BLOCK_LEN: usize = 64 * 1024;
+
+/// Warning included in every report.
+const GENERAL_WARNING: &str = "SSDs, flash drives, snapshots and backups may still hold \
+     copies of the original data; only full-disk encryption protects against that";
+
+/// What [`shred`] did, and why it may not have been enough.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShredReport {
+    /// The file or folder that was shredded.
+    pub path: PathBuf,
+    /// Files overwritten, renamed and removed.
+    pub files: u64,
+    /// Bytes overwritten on each pass, over all files.
+    pub bytes: u64,
+    pub passes: u32,
+    /// Directories and links removed without overwriting.
+    pub other_entries: u64,
+    /// Limits of overwriting that apply to this file.
+    pub warnings: Vec<String>,
+}
+
+impl fmt::Display for ShredReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Shredded {}: overwrote {} file(s), {} bytes, {} time(s), truncated, \
+             renamed and removed them",
+            self.path.display(),
+            self.files,
+            self.bytes,
+            self.passes
+        )?;
+        if self.other_entries > 0 {
+            write!(
+                f,
+                ", and removed {} folder(s) and link(s)",
+                self.other_entries
+            )?;
+        }
+        for warning in &self.warnings {
+            write!(f, "\nWarning: {}", warning)?;
+        }
+        Ok(())
+    }
+}
+
+/// Overwrites the file at `path` `passes` times, the last time with zeros
+/// and before that with random data, then truncates, renames and removes
+/// it. A folder has each of its files shredded and is then removed.
+/// Symbolic links are removed, never followed.
+pub fn shred<P: AsRef<Path>>(path: P, passes: u32) -> Result<ShredReport> {
+    shred_with_progress(path, passes, None)
+}
+
+/// [`shred`], adding the bytes it will overwrite to the total of `progress`
+/// and counting them as they are written. Once `progress` is cancelled it
+/// stops before the next block with [`EncoraError::Cancelled`], leaving the
+/// file it was overwriting partly overwritten under its own name.
+pub fn shred_with_progress<P: AsRef<Path>>(
+    path: P,
+    passes: u32,
+    progress: Option<&Progress>,
+) -> Result<ShredReport> {
+    let path = path.as_ref();
+    if passes == 0 {
+        return Err(EncoraError::InvalidInput(
+            "Shredding needs at least one overwrite pass".into(),
+        ));
+    }
+    let mut report = ShredReport {
+        path: path.to_path_buf(),
+        files: 0,
+        bytes: 0,
+        passes,
+        other_entries: 0,
+        warnings: warnings(path),
+    };
+    if let Some(progress) = progress {
+        progress.add_total(size(path)?.saturating_mul(passes as u64));
+    }
+    shred_entry(path, &mut report, progress)?;
+    Ok(report)
+}
+
+/// Bytes in the regular files at or under `path`, not following links.
+fn size(path: &Path) -> Result<u64> {
+    let metadata = fs::symlink_metadata(path)?;
+    if metadata.is_dir() {
+        let mut total = 0;
+        for child in fs::read_dir(path)? {
+            total += size(&child?.path())?;
+        }
+        Ok(total)
+    } else if metadata.is_file() {
+        Ok(metadata.len())
+    } else {
+        Ok(0)
+    }
+}
+
+fn shred_entry(path: &Path, report: &mut ShredReport, progress: Option<&Progress>) -> Result<()> {
+    let metadata = fs::symlink_metadata(path)?;
+    if metadata.is_dir() {
+        for child in fs::read_dir(path)? {
+            shred_entry(&child?.path(), report, progress)?;
+        }
+        fs::remove_dir(path)?;
+        report.other_entries += 1;
+    } else if metadata.is_file() {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            if metadata.nlink() > 1 {
+                return Err(EncoraError::InvalidInput(format!(
+                    "{} has other hard links, which would still reach the data; \
+                     remove them first",
+                    path.display()
+                )));
+            }
+        }
+        report.bytes += overwrite(path, metadata.len(), report.passes, progress)?;
+        unlink(path)?;
+        report.files += 1;
+    } else {
+        fs::remove_file(path)?;
+        report.other_entries += 1;
+    }
+    Ok(())
+}
+
+/// Overwrites the first `len` bytes of the file at `path` `passes` times,
+/// syncing after each pass, and truncates it. Returns `len`.
+fn overwrite(path: &Path, len: u64, passes: u32, progress: Option<&Progress>) -> Result<u64> {
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    let mut block = vec![0u8; BLOCK_LEN];
+    for pass in 1..=passes {
+        let last = pass == passes;
+        file.seek(SeekFrom::Start(0))?;
+        let mut remaining = len;
+        while remaining > 0 {
+            if progress.is_some_and(Progress::is_cancelled) {
+                return Err(EncoraError::Cancelled);
+            }
+            let n = remaining.min(BLOCK_LEN as u64) as usize;
+            if last {
+                block[..n].fill(0);
+            } else {
+                rand::thread_rng().fill(&mut block[..n]);
+            }
+            file.write_all(&block[..n])?;
+            remaining -= n as u64;
+            if let Some(progress) = progress {
+                progress.add(n as u64);
+            }
+        }
+        file.sync_data()?;
+    }
+    file.set_len(0)?;
+    file.sync_all()?;
+    Ok(len)
+}
+
+/// Renames the file at `path` to a random name beside it and removes it.
+fn unlink(path: &Path) -> Result<()> {
+    let random: [u8; 8] = rand::thread_rng().gen();
+    let name: String = random.iter().map(|b| format!("{:02x}", b)).collect();
+    let renamed = path.with_file_name(name);
+    fs::rename(path, &renamed)?;
+    fs::remove_file(&renamed)?;
+    #[cfg(unix)]
+    if let Some(dir) = renamed.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// The limits of overwriting that apply to `path`.
+fn warnings(path: &Path) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if let Some(fs_type) = copy_on_write_file_system(path) {
+        warnings.push(format!(
+            "{} is on a copy-on-write file system ({}), which writes the new data \
+             elsewhere; the original blocks are probably still on disk",
+            path.display(),
+            fs_type
+        ));
+    }
+    warnings.push(GENERAL_WARNING.to_string());
+    warnings
+}
+
+/// The name of the copy-on-write or log-structured file system `path` is
+/// on, if it is on one Encora can recognise.
+#[cfg(target_os = "linux")]
+fn copy_on_write_file_system(path: &Path) -> Option<String> {
+    const COPY_ON_WRITE: &[&str] = &["btrfs", "zfs", "bcachefs", "f2fs", "nilfs2", "overlay"];
+    let path = fs::canonicalize(path).ok()?;
+    let mounts = fs::read_to_string("/proc/self/mounts").ok()?;
+    // The mount point that is the longest prefix of the path holds it.
+    let fs_type = mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(' ');
+            let mount_point = fields.nth(1)?.replace("\\040", " ");
+            let fs_type = fields.next()?;
+            path.starts_with(&mount_point)
+                .then_some((mount_point.len(), fs_type))
+        })
+        .max_by_key(|(len, _)| *len)?
+        .1;
+    COPY_ON_WRITE
+        .contains(&fs_type)
+        .then(|| fs_type.to_string())
+}
+
+#[cfg(target_os = "macos")]
+fn copy_on_write_file_system(_path: &Path) -> Option<String> {
+    // Every volume macOS boots from is APFS.
+    Some("APFS".to_string())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn copy_on_write_file_system(_path: &Path) -> Option<String> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A new, empty directory under the system's temporary directory.
+    fn scratch_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("encora-shred-{:016x}", rand::random::<u64>()));
+        fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn reports_what_it_removed() {
+        let dir = scratch_dir();
+        let path = dir.join("plain.txt");
+        fs::write(&path, vec![7u8; BLOCK_LEN + 10]).unwrap();
+
+        let report = shred(&path, 2).unwrap();
+        assert!(!path.exists());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        assert_eq!(report.path, path);
+        assert_eq!(
+            (
+                report.files,
+                report.bytes,
+                report.passes,
+                report.other_entries
+            ),
+            (1, BLOCK_LEN as u64 + 10, 2, 0)
+        );
+        assert_eq!(report.warnings.last().unwrap(), GENERAL_WARNING);
+        assert!(report.to_string().contains("overwrote 1 file(s)"));
+        assert!(matches!(
+            shred(dir.join("other.txt"), 0),
+            Err(EncoraError::InvalidInput(_))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn removes_a_folder_without_following_links() {
+        let dir = scratch_dir();
+        let (folder, outside) = (dir.join("folder"), dir.join("outside.txt"));
+        fs::create_dir_all(folder.join("sub")).unwrap();
+        fs::write(folder.join("a.txt"), b"abc").unwrap();
+        fs::write(folder.join("sub").join("b.txt"), b"defg").unwrap();
+        fs::write(&outside, b"keep me").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&outside, folder.join("link")).unwrap();
+
+        let report = shred(&folder, 1).unwrap();
+        assert!(!folder.exists());
+        assert_eq!(fs::read(&outside).unwrap(), b"keep me");
+        assert_eq!((report.files, report.bytes), (2, 7));
+        let links = if cfg!(unix) { 1 } else { 0 };
+        assert_eq!(report.other_entries, 2 + links);
+        assert!(report.to_string().contains("folder(s) and link(s)"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_a_file_with_other_hard_links() {
+        let dir = scratch_dir();
+        let (path, other) = (dir.join("plain.txt"), dir.join("other name.txt"));
+        fs::write(&path, b"still reachable").unwrap();
+        fs::hard_link(&path, &other).unwrap();
+
+        assert!(matches!(shred(&path, 1), Err(EncoraError::InvalidInput(_))));
+        assert_eq!(fs::read(&path).unwrap(), b"still reachable");
+        assert_eq!(fs::read(&other).unwrap(), b"still reachable");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
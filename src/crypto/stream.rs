@@ -0,0 +1,430 @@
+//! Chunked ("STREAM") authenticated encryption, so files of any size are
+//! processed in bounded memory.
+//!
+//! The plaintext is cut into chunks of `chunk_size` bytes and every chunk is
+//! sealed on its own. The nonce of a chunk is the random prefix from the
+//! header followed by a 32-bit big-endian chunk counter and a one-byte flag
+//! that is `1` only for the final chunk:
+//!
+//! ```text
+//! nonce = prefix || counter (u32 BE) || last (u8)
+//! ```
+//!
+//! Reordering chunks breaks the counter, and cutting the file short leaves no
+//! chunk sealed with the final flag, so both are detected on decryption. The
+//! fixed part of the header ([`Header::authenticated_bytes`]) is passed as
+//! associated data to every chunk; the keyslots are not, so they can change.
+//!
+//! The chunk buffers hold plaintext, so they are wiped when dropped.
+
+use super::error::EncoraError;
+use super::header::Header;
+use aes_gcm::aead::{AeadInPlace, Nonce};
+use std::io::{self, Read, Write};
+use zeroize::Zeroizing;
+
+/// Plaintext bytes per chunk unless the header says otherwise.
+pub const DEFAULT_CHUNK_SIZE: u32 = 64 * 1024;
+
+/// Bounds accepted for the chunk size recorded in a header.
+pub const MIN_CHUNK_SIZE: u32 = 1024;
+pub const MAX_CHUNK_SIZE: u32 = 16 * 1024 * 1024;
+
+/// Counter and final-chunk flag appended to the nonce prefix.
+pub const NONCE_SUFFIX_LEN: usize = 5;
+
+/// Authentication tag appended to every chunk by all supported AEADs.
+pub const TAG_LEN: usize = 16;
+
+fn chunk_nonce<A: AeadInPlace>(prefix: &[u8], counter: u32, last: bool) -> Nonce<A> {
+    let mut nonce = Nonce::<A>::default();
+    let split = nonce.len() - NONCE_SUFFIX_LEN;
+    nonce[..split].copy_from_slice(prefix);
+    nonce[split..split + 4].copy_from_slice(&counter.to_be_bytes());
+    nonce[split + 4] = last as u8;
+    nonce
+}
+
+fn next_counter(counter: u32) -> io::Result<u32> {
+    counter
+        .checked_add(1)
+        .ok_or_else(|| io::Error::other("File is too large for the chunk counter"))
+}
+
+/// Encrypts everything written to it and writes sealed chunks to `inner`.
+///
+/// [`EncryptWriter::finish`] must be called to seal the final chunk; dropping
+/// the writer without it produces a file that fails to decrypt as truncated.
+pub struct EncryptWriter<A: AeadInPlace, W: Write> {
+    cipher: A,
+    nonce_prefix: Vec<u8>,
+    aad: Vec<u8>,
+    chunk_size: usize,
+    counter: u32,
+    buffer: Zeroizing<Vec<u8>>,
+    inner: W,
+}
+
+impl<A: AeadInPlace, W: Write> EncryptWriter<A, W> {
+    /// Writes `header` to `inner` and prepares to encrypt the payload.
+    pub fn new(cipher: A, header: &Header, mut inner: W) -> io::Result<Self> {
+        inner.write_all(&header.to_bytes())?;
+        let aad = header.authenticated_bytes();
+
+        let chunk_size = header.chunk_size as usize;
+        Ok(Self {
+            cipher,
+            nonce_prefix: header.nonce_prefix.clone(),
+            aad,
+            chunk_size,
+            counter: 0,
+            buffer: Zeroizing::new(Vec::with_capacity(chunk_size + TAG_LEN)),
+            inner,
+        })
+    }
+
+    fn seal_chunk(&mut self, last: bool) -> io::Result<()> {
+        let nonce = chunk_nonce::<A>(&self.nonce_prefix, self.counter, last);
+        self.cipher
+            .encrypt_in_place(&nonce, &self.aad, &mut *self.buffer)
+            .map_err(|e| io::Error::other(format!("Encryption error: {}", e)))?;
+        self.inner.write_all(&self.buffer)?;
+        self.buffer.clear();
+        self.counter = next_counter(self.counter)?;
+        Ok(())
+    }
+
+    /// Seals the final chunk and returns the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.seal_chunk(true)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<A: AeadInPlace, W: Write> Write for EncryptWriter<A, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // A full chunk is only sealed once more data arrives, because until
+        // then it might still turn out to be the final one.
+        if self.buffer.len() == self.chunk_size {
+            self.seal_chunk(false)?;
+        }
+        let take = buf.len().min(self.chunk_size - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..take]);
+        Ok(take)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reads sealed chunks from `inner` and yields the authenticated plaintext.
+///
+/// Every chunk is verified before any of its bytes are returned. A stream that
+/// ends without a final chunk results in an error rather than a silent EOF.
+pub struct DecryptReader<A: AeadInPlace, R: Read> {
+    cipher: A,
+    nonce_prefix: Vec<u8>,
+    aad: Vec<u8>,
+    sealed_chunk_len: usize,
+    counter: u32,
+    buffer: Zeroizing<Vec<u8>>,
+    pos: usize,
+    lookahead: Option<u8>,
+    finished: bool,
+    key_proven: bool,
+    inner: R,
+}
+
+impl<A: AeadInPlace, R: Read> DecryptReader<A, R> {
+    /// `inner` must be positioned right after `header`. `key_proven` says
+    /// whether a keyslot has already shown the key to be right, as
+    /// [`open_chunk`] takes it.
+    pub fn new(cipher: A, header: &Header, inner: R, key_proven: bool) -> Self {
+        let sealed_chunk_len = header.chunk_size as usize + TAG_LEN;
+        Self {
+            cipher,
+            nonce_prefix: header.nonce_prefix.clone(),
+            aad: header.authenticated_bytes(),
+            sealed_chunk_len,
+            counter: 0,
+            buffer: Zeroizing::new(Vec::with_capacity(sealed_chunk_len)),
+            pos: 0,
+            lookahead: None,
+            finished: false,
+            key_proven,
+            inner,
+        }
+    }
+
+    fn open_next_chunk(&mut self) -> io::Result<()> {
+        self.buffer.clear();
+        self.buffer.resize(self.sealed_chunk_len, 0);
+        self.pos = 0;
+
+        let mut filled = 0;
+        if let Some(byte) = self.lookahead.take() {
+            self.buffer[0] = byte;
+            filled = 1;
+        }
+        filled += read_full(&mut self.inner, &mut self.buffer[filled..])?;
+
+        // A full chunk is the final one only if nothing follows it.
+        let last = if filled < self.sealed_chunk_len {
+            true
+        } else {
+            let mut next = [0u8; 1];
+            if read_full(&mut self.inner, &mut next)? == 0 {
+                true
+            } else {
+                self.lookahead = Some(next[0]);
+                false
+            }
+        };
+
+        if filled < TAG_LEN {
+            self.buffer.clear();
+            return Err(EncoraError::Truncated.into());
+        }
+        self.buffer.truncate(filled);
+
+        let opened = open_chunk(
+            &self.cipher,
+            &self.nonce_prefix,
+            &self.aad,
+            self.counter,
+            last,
+            self.key_proven,
+            &mut self.buffer,
+        );
+        if let Err(error) = opened {
+            self.buffer.clear();
+            return Err(error.into());
+        }
+        self.counter = next_counter(self.counter)?;
+        self.finished = last;
+        Ok(())
+    }
+}
+
+/// Authenticates and decrypts the sealed chunk number `counter` in `chunk`
+/// in place; `last` says whether the file ends with it. Chunks can be opened
+/// in any order, which is how single entries are read from an archive.
+///
+/// A first chunk that fails to open is reported as the wrong key unless
+/// `key_proven` says a keyslot or an earlier chunk already showed the key to
+/// be right, in which case the chunk was tampered with.
+pub fn open_chunk<A: AeadInPlace>(
+    cipher: &A,
+    nonce_prefix: &[u8],
+    aad: &[u8],
+    counter: u32,
+    last: bool,
+    key_proven: bool,
+    chunk: &mut Vec<u8>,
+) -> Result<(), EncoraError> {
+    let nonce = chunk_nonce::<A>(nonce_prefix, counter, last);
+    if cipher.decrypt_in_place(&nonce, aad, chunk).is_ok() {
+        return Ok(());
+    }
+    // The AEADs verify before decrypting, so `chunk` still holds the sealed
+    // chunk. One that opens as a non-final chunk where the file ends means
+    // the rest of the file was cut off at a chunk boundary.
+    if last {
+        let nonce = chunk_nonce::<A>(nonce_prefix, counter, false);
+        if cipher.decrypt_in_place(&nonce, aad, chunk).is_ok() {
+            return Err(EncoraError::Truncated);
+        }
+    }
+    // Once a chunk has opened the key is known to be right.
+    if counter == 0 && !key_proven {
+        Err(EncoraError::WrongKey)
+    } else {
+        Err(EncoraError::Tampered)
+    }
+}
+
+impl<A: AeadInPlace, R: Read> Read for DecryptReader<A, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buffer.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            self.open_next_chunk()?;
+        }
+        let n = buf.len().min(self.buffer.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buffer[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Reads until `buf` is full or the reader is exhausted, returning the count.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Writes `header` and the encryption of everything in `reader` to `writer`.
+pub fn encrypt_stream<A: AeadInPlace, R: Read + ?Sized, W: Write>(
+    cipher: A,
+    header: &Header,
+    reader: &mut R,
+    writer: W,
+) -> io::Result<W> {
+    let mut encryptor = EncryptWriter::new(cipher, header, writer)?;
+    io::copy(reader, &mut encryptor)?;
+    encryptor.finish()
+}
+
+/// Decrypts the chunks that follow `header` in `reader` into `writer` and
+/// returns the number of plaintext bytes written.
+pub fn decrypt_stream<A: AeadInPlace, R: Read, W: Write + ?Sized>(
+    cipher: A,
+    header: &Header,
+    reader: R,
+    writer: &mut W,
+    key_proven: bool,
+) -> io::Result<u64> {
+    let mut decryptor = DecryptReader::new(cipher, header, reader, key_proven);
+    let written = io::copy(&mut decryptor, writer)?;
+    writer.flush()?;
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::cipher::Algorithm;
+    use aes_gcm::aead::KeyInit;
+    use aes_gcm::Aes256Gcm;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    /// A header for small chunks, so a few KiB make several of them.
+    fn header() -> Header {
+        let mut header = Header::generate(Algorithm::AES_256_GCM);
+        header.chunk_size = MIN_CHUNK_SIZE;
+        header
+    }
+
+    fn cipher() -> Aes256Gcm {
+        Aes256Gcm::new_from_slice(&KEY).unwrap()
+    }
+
+    /// The sealed chunks of `plaintext`, without the header.
+    fn seal(header: &Header, plaintext: &[u8]) -> Vec<Vec<u8>> {
+        let sealed = encrypt_stream(cipher(), header, &mut &plaintext[..], Vec::new()).unwrap();
+        sealed[header.to_bytes().len()..]
+            .chunks(MIN_CHUNK_SIZE as usize + TAG_LEN)
+            .map(<[u8]>::to_vec)
+            .collect()
+    }
+
+    fn open(header: &Header, chunks: &[Vec<u8>], key_proven: bool) -> Result<Vec<u8>, EncoraError> {
+        let mut plaintext = Vec::new();
+        decrypt_stream(
+            cipher(),
+            header,
+            &chunks.concat()[..],
+            &mut plaintext,
+            key_proven,
+        )?;
+        Ok(plaintext)
+    }
+
+    fn plaintext() -> Vec<u8> {
+        (0..4000u32).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn round_trips_over_several_chunks() {
+        let header = header();
+        let chunks = seal(&header, &plaintext());
+        assert_eq!(chunks.len(), 4);
+        assert_eq!(open(&header, &chunks, false).unwrap(), plaintext());
+    }
+
+    #[test]
+    fn detects_reordered_chunks() {
+        let header = header();
+        let mut chunks = seal(&header, &plaintext());
+        chunks.swap(1, 2);
+        assert!(matches!(
+            open(&header, &chunks, false),
+            Err(EncoraError::Tampered)
+        ));
+    }
+
+    #[test]
+    fn detects_truncation_at_a_chunk_boundary() {
+        let header = header();
+        let mut chunks = seal(&header, &plaintext());
+        chunks.pop();
+        assert!(matches!(
+            open(&header, &chunks, false),
+            Err(EncoraError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn detects_truncation_inside_a_chunk() {
+        let header = header();
+        let mut chunks = seal(&header, &plaintext());
+        chunks.last_mut().unwrap().truncate(TAG_LEN - 1);
+        assert!(matches!(
+            open(&header, &chunks, false),
+            Err(EncoraError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn detects_tampered_chunks() {
+        let header = header();
+        let mut chunks = seal(&header, &plaintext());
+        chunks[2][10] ^= 1;
+        assert!(matches!(
+            open(&header, &chunks, false),
+            Err(EncoraError::Tampered)
+        ));
+    }
+
+    #[test]
+    fn reports_a_bad_first_chunk_as_the_wrong_key_unless_proven() {
+        let header = header();
+        let mut chunks = seal(&header, &plaintext());
+        chunks[0][10] ^= 1;
+        assert!(matches!(
+            open(&header, &chunks, false),
+            Err(EncoraError::WrongKey)
+        ));
+        assert!(matches!(
+            open(&header, &chunks, true),
+            Err(EncoraError::Tampered)
+        ));
+    }
+
+    #[test]
+    fn rejects_a_changed_header() {
+        let header = header();
+        let chunks = seal(&header, &plaintext());
+        let mut changed = header.clone();
+        changed.compressed = true;
+        assert!(matches!(
+            open(&changed, &chunks, true),
+            Err(EncoraError::Tampered)
+        ));
+    }
+}
@@ -0,0 +1,309 @@
+//! A vault file keeping the keys of encrypted files under one master
+//! password, so they do not have to be copied anywhere else.
+//!
+//! The vault is itself an Encora file with a single passphrase keyslot, so
+//! the master password goes through Argon2id like any passphrase. Its
+//! plaintext is a [`KeyBundle`]: one key file per encrypted file, named after
+//! the path it was written to. Entries are indexed by the random file id in
+//! an encrypted file's header, which keyslot changes leave alone, so files
+//! encrypted before they were recorded are found the same way.
+//!
+//! Every change rewrites the whole vault through a synced temporary file that
+//! is renamed over it, so a crash leaves either the old vault or the new one.
+
+use super::atomic::AtomicFile;
+use super::error::{EncoraError, Result};
+use super::header::Header;
+use super::key::Secret;
+use super::keyfile::{BundleEntry, KeyBundle, KeyFile};
+use super::EncryptOptions;
+use std::fs::{self, File, OpenOptions};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+/// Name of the vault file in its default folder.
+pub const VAULT_FILE_NAME: &str = "vault.encora";
+
+/// An unlocked vault, holding its master password to write changes back.
+pub struct Vault {
+    path: PathBuf,
+    password: Zeroizing<String>,
+    keys: KeyBundle,
+}
+
+impl Vault {
+    /// `.encora/vault.encora` in the user's home directory, if it is known.
+    pub fn default_path() -> Option<PathBuf> {
+        let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"))?;
+        Some(PathBuf::from(home).join(".encora").join(VAULT_FILE_NAME))
+    }
+
+    /// Creates an empty vault at `path`, which must not exist yet, locked by
+    /// `password`. Missing parent folders are created.
+    pub fn create<P: AsRef<Path>>(path: P, password: &str) -> Result<Self> {
+        let path = path.as_ref();
+        if password.is_empty() {
+            return Err(EncoraError::InvalidInput(
+                "The vault needs a master password".into(),
+            ));
+        }
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        // The empty file only fixes the permissions the vault is then written with
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options.open(path)?;
+
+        let vault = Self {
+            path: path.to_path_buf(),
+            password: Zeroizing::new(password.to_string()),
+            keys: KeyBundle::new(),
+        };
+        if let Err(e) = vault.write(&vault.keys) {
+            let _ = fs::remove_file(path);
+            return Err(e);
+        }
+        Ok(vault)
+    }
+
+    /// Unlocks the vault at `path` with `password`. A wrong password fails
+    /// with [`EncoraError::WrongKey`].
+    pub fn open<P: AsRef<Path>>(path: P, password: &str) -> Result<Self> {
+        let path = path.as_ref();
+        let mut plaintext = Zeroizing::new(Vec::new());
+        super::decrypt(
+            BufReader::new(File::open(path)?),
+            &mut *plaintext,
+            Secret::Passphrase(password),
+        )?;
+        let text = std::str::from_utf8(&plaintext)
+            .map_err(|_| EncoraError::InvalidInput("The vault does not hold text".into()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            password: Zeroizing::new(password.to_string()),
+            keys: KeyBundle::parse(text)?,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Every key in the vault, oldest first.
+    pub fn keys(&self) -> &KeyBundle {
+        &self.keys
+    }
+
+    /// The key recorded for the encrypted file with `header`, if any.
+    pub fn find(&self, header: &Header) -> Option<&KeyFile> {
+        self.keys.find(header)
+    }
+
+    /// Indexes of the entries whose file name or algorithm contains `query`,
+    /// or whose hex file id starts with it, ignoring case. An empty query
+    /// matches every entry.
+    pub fn search(&self, query: &str) -> Vec<usize> {
+        let query = query.trim().to_lowercase();
+        self.keys
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| {
+                entry.file_name.to_lowercase().contains(&query)
+                    || entry
+                        .key_file
+                        .algorithm
+                        .name()
+                        .to_lowercase()
+                        .contains(&query)
+                    || hex::encode(entry.key_file.file_id).starts_with(&query)
+            })
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// Records `key_file` for the encrypted file named `file_name`, replacing
+    /// any key recorded for the same file, and saves the vault.
+    pub fn record(&mut self, file_name: &str, key_file: KeyFile) -> Result<()> {
+        let mut keys = self.keys.clone();
+        keys.entries
+            .retain(|entry| entry.key_file.file_id != key_file.file_id);
+        keys.push(file_name, key_file);
+        self.write(&keys)?;
+        self.keys = keys;
+        Ok(())
+    }
+
+    /// Removes the entry at `index` and saves the vault.
+    pub fn remove(&mut self, index: usize) -> Result<BundleEntry> {
+        if index >= self.keys.len() {
+            return Err(EncoraError::InvalidInput(format!(
+                "The vault has no entry {}",
+                index
+            )));
+        }
+        let mut keys = self.keys.clone();
+        let entry = keys.entries.remove(index);
+        self.write(&keys)?;
+        self.keys = keys;
+        Ok(entry)
+    }
+
+    /// A key bundle with the entries at `indexes`, to hand on or keep
+    /// elsewhere.
+    pub fn export(&self, indexes: &[usize]) -> KeyBundle {
+        let mut bundle = KeyBundle::new();
+        for entry in indexes
+            .iter()
+            .filter_map(|&index| self.keys.entries.get(index))
+        {
+            bundle.push(&entry.file_name, entry.key_file.clone());
+        }
+        bundle
+    }
+
+    /// Encrypts `keys` under the master password over the vault file.
+    fn write(&self, keys: &KeyBundle) -> Result<()> {
+        let options = EncryptOptions {
+            passphrase: Some(self.password.clone()),
+            ..EncryptOptions::default()
+        };
+        let (source, mut file) = AtomicFile::open(&self.path)?;
+        drop(source);
+        super::encrypt(&mut keys.to_text().as_bytes(), file.writer(), &options)?;
+        file.commit(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::cipher::Algorithm;
+    use crate::crypto::key::Key;
+
+    /// A path for a vault in a new, empty directory.
+    fn vault_path() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("encora-vault-{:016x}", rand::random::<u64>()));
+        fs::create_dir(&dir).unwrap();
+        dir.join(VAULT_FILE_NAME)
+    }
+
+    fn remove(path: &Path) {
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn records_and_finds_keys_after_reopening() {
+        let path = vault_path();
+        let mut vault = Vault::create(&path, "master").unwrap();
+        assert!(vault.keys().is_empty());
+        let (first, second) = (
+            Header::generate(Algorithm::XCHACHA20_POLY1305),
+            Header::generate(Algorithm::AES_256_GCM),
+        );
+        let key = Key::generate();
+        vault
+            .record("/a/first.encora", KeyFile::new(&first, key.clone()))
+            .unwrap();
+        vault
+            .record("/a/second.encora", KeyFile::new(&second, Key::generate()))
+            .unwrap();
+
+        let vault = Vault::open(&path, "master").unwrap();
+        assert_eq!(vault.keys().len(), 2);
+        assert_eq!(vault.find(&first).unwrap().key, key);
+        assert!(vault
+            .find(&Header::generate(Algorithm::XCHACHA20_POLY1305))
+            .is_none());
+        assert_eq!(vault.search("SECOND"), vec![1]);
+        assert_eq!(vault.search(&hex::encode(first.file_id)[..8]), vec![0]);
+        assert_eq!(vault.search(""), vec![0, 1]);
+        remove(&path);
+    }
+
+    #[test]
+    fn recording_a_file_again_replaces_its_key() {
+        let path = vault_path();
+        let mut vault = Vault::create(&path, "master").unwrap();
+        let header = Header::generate(Algorithm::XCHACHA20_POLY1305);
+        let key = Key::generate();
+        vault
+            .record("old name", KeyFile::new(&header, Key::generate()))
+            .unwrap();
+        vault
+            .record("new name", KeyFile::new(&header, key.clone()))
+            .unwrap();
+        assert_eq!(vault.keys().len(), 1);
+        assert_eq!(vault.keys().entries[0].file_name, "new name");
+        assert_eq!(vault.find(&header).unwrap().key, key);
+        remove(&path);
+    }
+
+    #[test]
+    fn exports_and_removes_entries() {
+        let path = vault_path();
+        let mut vault = Vault::create(&path, "master").unwrap();
+        let headers: Vec<_> = (0..3)
+            .map(|_| Header::generate(Algorithm::XCHACHA20_POLY1305))
+            .collect();
+        for (number, header) in headers.iter().enumerate() {
+            vault
+                .record(
+                    &format!("file {}", number),
+                    KeyFile::new(header, Key::generate()),
+                )
+                .unwrap();
+        }
+
+        let exported = vault.export(&[2, 0, 7]);
+        assert_eq!(exported.len(), 2);
+        assert_eq!(exported.entries[0].file_name, "file 2");
+        assert_eq!(exported.find(&headers[0]), vault.find(&headers[0]));
+        assert!(exported.find(&headers[1]).is_none());
+
+        assert_eq!(vault.remove(1).unwrap().file_name, "file 1");
+        assert!(matches!(vault.remove(5), Err(EncoraError::InvalidInput(_))));
+        let vault = Vault::open(&path, "master").unwrap();
+        assert_eq!(vault.keys().len(), 2);
+        assert!(vault.find(&headers[1]).is_none());
+        remove(&path);
+    }
+
+    #[test]
+    fn refuses_a_wrong_password() {
+        let path = vault_path();
+        Vault::create(&path, "master").unwrap();
+        assert!(matches!(
+            Vault::open(&path, "not the master"),
+            Err(EncoraError::WrongKey)
+        ));
+        remove(&path);
+    }
+
+    #[test]
+    fn never_creates_over_an_existing_vault() {
+        let path = vault_path();
+        let mut vault = Vault::create(&path, "master").unwrap();
+        let header = Header::generate(Algorithm::XCHACHA20_POLY1305);
+        vault
+            .record("kept", KeyFile::new(&header, Key::generate()))
+            .unwrap();
+        assert!(Vault::create(&path, "other").is_err());
+        assert!(Vault::open(&path, "master")
+            .unwrap()
+            .find(&header)
+            .is_some());
+        assert!(matches!(
+            Vault::create(path.with_file_name("other.encora"), ""),
+            Err(EncoraError::InvalidInput(_))
+        ));
+        remove(&path);
+    }
+}
@@ -1,233 +1,1084 @@
-use crate::crypto::chacha20::{decrypt_file, encrypt_file};
-use crate::crypto::aes::{aes_decrypt_file, aes_encrypt_file};
+use encora::crypto::{decrypt_in_place, decrypt_to_chosen_path, decrypt_to_location, encrypt_dir, encrypt_file, encrypt_in_place, same_file, EncryptOptions};
+use encora::crypto::archive::{Archive, Entry, EntryKind};
+use encora::crypto::cipher::Algorithm;
+use encora::crypto::compress::DEFAULT_LEVEL;
+use encora::crypto::header::{read_header, Header};
+use encora::crypto::key::{Key, Secret};
+use encora::crypto::keyfile::{KeyBundle, KeyFile, KEY_BUNDLE_EXTENSION, KEY_FILE_EXTENSION};
+use encora::crypto::keyslot::KeySlotKind;
+use encora::crypto::metadata::FileMetadata;
+use encora::crypto::error::EncoraError;
+use encora::crypto::OutputLocation;
+use encora::crypto::progress::Progress;
+use encora::crypto::recipient::{Identity, Recipient};
+use encora::crypto::shamir::{split_key, Share, SHARE_FILE_EXTENSION};
+use encora::crypto::shred::{shred_with_progress, DEFAULT_PASSES};
+use encora::crypto::vault::Vault;
 use iced::{
     alignment::{Horizontal, Vertical},
-    widget::{button, column, container, pick_list, row, text, text_input, Space},
-    Element, Length, Sandbox, theme
+    executor,
+    futures::channel::{mpsc, oneshot},
+    futures::{SinkExt, StreamExt},
+    event, subscription, window,
+    widget::{button, checkbox, column, container, pick_list, progress_bar, row, scrollable, text, text_input, Space},
+    Application, Command, Element, Length, Subscription, theme
 };
-use rfd::FileDialog;
+use rfd::{FileDialog, MessageDialog, MessageLevel};
 use copypasta::{ClipboardContext, ClipboardProvider};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use zeroize::{Zeroize, Zeroizing};
 
 #[derive(Debug, Clone)]
 pub enum MyAppMessage {
-    AlgorithmSelected(Algorithms),
+    AlgorithmSelected(Algorithm),
     StartEncryption,
     StartDecryption,
     FileSelected(Option<std::path::PathBuf>),
+    FilesSelected(Vec<PathBuf>),
     OpenFileDialog,
+    OpenFolderDialog,
+    FileDropped(PathBuf),
+    FilesHovered,
+    FilesHoveredLeft,
     KeyInputChanged(String),
-    NonceInputChanged(String),
+    PassphraseInputChanged(String),
+    RecipientsInputChanged(String),
+    HideFileNameToggled(bool),
+    CompressToggled(bool),
+    InPlaceToggled(bool),
+    ShredToggled(bool),
+    DistinctKeysToggled(bool),
+    GenerateIdentity,
     Decrypt,
+    BrowseContents,
+    FolderToggled(String),
+    ExtractEntry(String),
     BackToMain,
     CopyKey,
-    CopyNonce,
-    DownloadFile
+    SaveKeyFile,
+    SaveKeyBundle,
+    ShareThresholdChanged(String),
+    ShareCountChanged(String),
+    SplitKey,
+    CopyShare(usize),
+    SaveShareFiles,
+    SharesInputChanged(String),
+    LoadShareFiles,
+    LoadKeyFile,
+    OpenKeys,
+    CloseKeys,
+    VaultPasswordChanged(String),
+    VaultPasswordConfirmChanged(String),
+    UnlockVault,
+    CreateVault,
+    LockVault,
+    VaultSearchChanged(String),
+    ExportVaultKey(usize),
+    ExportVaultKeys,
+    DeleteVaultKey(usize),
+    ProgressTick,
+    CancelJob,
+    JobFinished(JobOutcome),
+    OutputNeeded(Option<OutputRequest>),
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum Algorithms {
-    AES,
-    ChaCha20,
+/// What a background encryption or decryption ended with.
+#[derive(Debug, Clone)]
+pub enum JobOutcome {
+    /// The file key, where it was written and what shredding the original reported, if asked to.
+    Encrypted { key: Key, output: PathBuf, shred_status: String },
+    Decrypted(PathBuf),
+    Opened(OpenFolder),
+    /// Where the entry was extracted.
+    Extracted(PathBuf),
+    Unlocked(UnlockedVault),
+    /// Stopped by the user; nothing was written or replaced.
+    Cancelled,
+    /// The reason, ready to show.
+    Failed(String),
 }
 
-impl Algorithms {
-    const ALL: [Algorithms; 2] = [Algorithms::AES, Algorithms::ChaCha20];
+impl JobOutcome {
+    /// The outcome of a job that failed with `error`.
+    fn failed(error: EncoraError) -> Self {
+        match error {
+            EncoraError::Cancelled => JobOutcome::Cancelled,
+            error => JobOutcome::Failed(describe(&error)),
+        }
+    }
 }
 
-impl std::fmt::Display for Algorithms {
+/// An encrypted folder opened for browsing. The entries are copied out so
+/// the view can list them while a job extracts one from the shared archive.
+#[derive(Clone)]
+pub struct OpenFolder {
+    entries: Vec<Entry>,
+    algorithm: Algorithm,
+    archive: Arc<Mutex<Archive<std::fs::File>>>,
+}
+
+impl std::fmt::Debug for OpenFolder {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Algorithms::AES => write!(f, "AES"),
-            Algorithms::ChaCha20 => write!(f, "ChaCha20"),
-        }
+        f.debug_struct("OpenFolder").field("entries", &self.entries.len()).finish_non_exhaustive()
     }
 }
 
+/// A vault unlocked or created in the background, taken out once its job
+/// has finished.
+#[derive(Clone)]
+pub struct UnlockedVault(Arc<Mutex<Option<Vault>>>);
+
+impl std::fmt::Debug for UnlockedVault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UnlockedVault").finish_non_exhaustive()
+    }
+}
+
+/// A background decryption asking where to write `source` decrypted, once
+/// the key has opened it and its stored `metadata` has been read. `None` as
+/// the reply cancels the decryption.
+#[derive(Debug, Clone)]
+pub struct OutputRequest {
+    source: PathBuf,
+    metadata: Option<FileMetadata>,
+    reply: std::sync::mpsc::Sender<Option<OutputLocation>>,
+}
+
+/// An encryption or decryption running on a background thread.
+pub struct Job {
+    kind: JobKind,
+    algorithm: Algorithm,
+    progress: Progress,
+    started: Instant,
+}
+
+enum JobKind {
+    Encrypt { recipients: usize, passphrase: bool },
+    Decrypt { in_place: bool },
+    Browse,
+    Extract { path: String },
+    /// Unlocking the vault, or creating it if `create` is set.
+    Vault { create: bool },
+}
+
+/// A file of a batch and how far it has got.
+pub struct BatchItem {
+    path: PathBuf,
+    status: BatchStatus,
+}
+
+enum BatchStatus {
+    Queued,
+    Running,
+    /// Where the output was written, and a note such as what shredding did.
+    Done(PathBuf, String),
+    Failed(String),
+    Cancelled,
+}
+
+/// What is done to every file of a running batch, with the settings copied
+/// when it started.
+pub enum BatchTask {
+    /// `output_dir` is `None` to replace each file in place.
+    Encrypt { options: EncryptOptions, output_dir: Option<PathBuf>, hide_file_name: bool, shred: bool },
+    Decrypt {
+        identity: Option<Identity>,
+        passphrase: Zeroizing<String>,
+        key: Zeroizing<String>,
+        key_bundle: Option<KeyBundle>,
+        shares: Vec<Share>,
+        /// The keys in the vault, if it is unlocked.
+        vault_keys: Option<KeyBundle>,
+        output_dir: Option<PathBuf>,
+    },
+}
+
+/// How often the progress bar is redrawn while a job runs.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Files dropped within this time of each other were dropped together; the
+/// window reports them one at a time.
+const DROP_GROUPING: Duration = Duration::from_millis(500);
+
 pub struct MyApp {
-    pub selected_algorithm: Option<Algorithms>,
+    pub selected_algorithm: Option<Algorithm>,
     pub encryption_status: String,
     pub decryption_status: String,
     pub selected_file: Option<std::path::PathBuf>,
-    pub key: String,
-    pub nonce: String,
-    pub show_key_nonce_input: bool,
+    pub key: Zeroizing<String>,
+    pub passphrase: Zeroizing<String>,
+    pub show_key_input: bool,
     pub copy_status: String,
     pub processed_file: Option<std::path::PathBuf>,
+    pub key_file: Option<KeyFile>,
+    pub recipients: String,
+    pub identity: Option<Identity>,
+    pub hide_file_name: bool,
+    pub compress: bool,
+    pub in_place: bool,
+    pub shred: bool,
+    pub shred_status: String,
+    pub archive: Option<OpenFolder>,
+    pub expanded: HashSet<String>,
+    pub job: Option<Job>,
+    pub batch: Vec<BatchItem>,
+    pub batch_task: Option<BatchTask>,
+    pub distinct_keys: bool,
+    pub key_bundle: Option<KeyBundle>,
+    pub hovering_files: bool,
+    pub last_drop: Option<Instant>,
+    pub show_keys: bool,
+    pub vault_path: Option<PathBuf>,
+    pub vault: Option<Vault>,
+    pub vault_password: Zeroizing<String>,
+    pub vault_password_confirm: Zeroizing<String>,
+    pub vault_search: String,
+    pub vault_status: String,
+    pub pending_delete: Option<usize>,
+    pub share_threshold: String,
+    pub share_count: String,
+    pub shares: Vec<Share>,
+    pub shares_input: Zeroizing<String>,
 }
 
-impl Sandbox for MyApp {
+impl Application for MyApp {
+    type Executor = executor::Default;
     type Message = MyAppMessage;
+    type Theme = iced::Theme;
+    type Flags = ();
 
-    fn new() -> Self {
-        Self {
+    fn new(_flags: ()) -> (Self, Command<Self::Message>) {
+        (Self {
             selected_algorithm: None,
             encryption_status: "".into(),
             decryption_status: "".into(),
             copy_status:"".into(),
             selected_file: None,
-            key: "".into(),
-            nonce: "".into(),
-            show_key_nonce_input: false,
+            key: Zeroizing::default(),
+            passphrase: Zeroizing::default(),
+            show_key_input: false,
             processed_file: None,
-        }
+            key_file: None,
+            recipients: "".into(),
+            identity: None,
+            hide_file_name: false,
+            compress: false,
+            in_place: false,
+            shred: false,
+            shred_status: "".into(),
+            archive: None,
+            expanded: HashSet::new(),
+            job: None,
+            batch: Vec::new(),
+            batch_task: None,
+            distinct_keys: false,
+            key_bundle: None,
+            hovering_files: false,
+            last_drop: None,
+            show_keys: false,
+            vault_path: Vault::default_path(),
+            vault: None,
+            vault_password: Zeroizing::default(),
+            vault_password_confirm: Zeroizing::default(),
+            vault_search: String::new(),
+            vault_status: String::new(),
+            pending_delete: None,
+            share_threshold: "".into(),
+            share_count: "".into(),
+            shares: Vec::new(),
+            shares_input: Zeroizing::default(),
+        }, Command::none())
     }
 
     fn title(&self) -> String {
         String::from("File Encryption Tool")
     }
 
-    fn update(&mut self, message: Self::Message) {
+    fn update(&mut self, message: Self::Message) -> Command<Self::Message> {
         match message {
             MyAppMessage::AlgorithmSelected(algorithm) => {
                 self.selected_algorithm = Some(algorithm);
             }
             MyAppMessage::FileSelected(file_path) => {
                 self.selected_file = file_path;
-                self.encryption_status = String::new();
-                self.decryption_status = String::new();
+                self.batch.clear();
+                self.clear_results();
+            }
+            MyAppMessage::FilesSelected(files) => {
+                // Several files are queued and processed one after another with the same settings
+                self.selected_file = None;
+                self.batch = files.into_iter().map(|path| BatchItem { path, status: BatchStatus::Queued }).collect();
+                self.clear_results();
             }
             MyAppMessage::OpenFileDialog => {
-                if let Some(path) = FileDialog::new().pick_file() {
-                    self.update(MyAppMessage::FileSelected(Some(path)));
-                } else {
-                    self.update(MyAppMessage::FileSelected(None));
+                let mut files = FileDialog::new().pick_files().unwrap_or_default();
+                if files.len() > 1 {
+                    return self.update(MyAppMessage::FilesSelected(files));
+                }
+                return self.update(MyAppMessage::FileSelected(files.pop()));
+            }
+            MyAppMessage::OpenFolderDialog => {
+                // A folder is packed into one archive and encrypted as a single file
+                if let Some(path) = FileDialog::new().pick_folder() {
+                    return self.update(MyAppMessage::FileSelected(Some(path)));
+                }
+            }
+            MyAppMessage::FileDropped(path) => {
+                self.hovering_files = false;
+                if self.job.is_some() {
+                    return Command::none();
+                }
+                let encrypted = has_encora_header(&path);
+                // Files dropped together join one batch, which takes the way of the first of them
+                if self.last_drop.is_some_and(|last| last.elapsed() < DROP_GROUPING) {
+                    self.last_drop = Some(Instant::now());
+                    let name = path.file_name().map_or_else(|| path.display().to_string(), |name| name.to_string_lossy().into_owned());
+                    let skipped = match (encrypted, self.show_key_input) {
+                        (true, false) => format!("Skipped {}: it is already encrypted", name),
+                        (false, true) => format!("Skipped {}: it is not an Encora file", name),
+                        _ => {
+                            if let Some(first) = self.selected_file.take() {
+                                self.batch.push(BatchItem { path: first, status: BatchStatus::Queued });
+                            }
+                            self.batch.push(BatchItem { path, status: BatchStatus::Queued });
+                            return Command::none();
+                        }
+                    };
+                    let status = if self.show_key_input { &mut self.decryption_status } else { &mut self.encryption_status };
+                    if !status.is_empty() {
+                        status.push_str("; ");
+                    }
+                    status.push_str(&skipped);
+                    return Command::none();
+                }
+                self.last_drop = Some(Instant::now());
+                // A file with an Encora header is decrypted, anything else, folders included, encrypted
+                if encrypted && !self.show_key_input {
+                    let _ = self.update(MyAppMessage::StartDecryption);
+                } else if !encrypted && self.show_key_input {
+                    let _ = self.update(MyAppMessage::BackToMain);
                 }
+                return self.update(MyAppMessage::FileSelected(Some(path)));
+            }
+            MyAppMessage::FilesHovered => {
+                self.hovering_files = self.job.is_none();
+            }
+            MyAppMessage::FilesHoveredLeft => {
+                self.hovering_files = false;
             }
             MyAppMessage::StartEncryption => {
                 self.encryption_status = "Encryption started".into();
+                self.shred_status = String::new();
+                self.key_bundle = None;
+                self.shares.clear();
+                if !self.batch.is_empty() {
+                    return self.start_batch_encryption();
+                }
                 if let Some(selected_file) = &self.selected_file {
                     if let Some(algorithm) = self.selected_algorithm {
-                        match algorithm {
-                            Algorithms::ChaCha20 => match encrypt_file(selected_file) {
-                                Ok((key, nonce, output_path)) => {
-                                    self.key = format!("{:x?}", key);
-                                    self.nonce = format!("{:?}", nonce);
-                                    self.processed_file = Some(output_path);
-                                    self.encryption_status = format!("File encrypted successfully");
-                                }
-                                Err(e) => {
-                                    self.encryption_status =
-                                        format!("Error encrypting file: {}", e);
-                                }
-                            },
-                            Algorithms::AES => match aes_encrypt_file(selected_file) {
-                                Ok((key, nonce, encrypted_file_path)) => {
-                                    self.encryption_status = format!("File encrypted successfully");
-                  
-                                    self.key = format!("{:x?}", key);
-                                    self.nonce = format!("{:?}", nonce);
-                                    self.processed_file = Some(encrypted_file_path);
-                                    
-                                }
-                                Err(e) => {
-                                    self.encryption_status = format!("Error encrypting file with AES: {}", e);
-                                }
+                        // A random name keeps the original one only inside the encrypted metadata
+                        let naming = if self.hide_file_name { OutputLocation::opaque_encrypted_path } else { OutputLocation::encrypted_path };
+                        // In place, the encrypted file atomically replaces the original instead
+                        let output = if self.in_place {
+                            OutputLocation::File(selected_file.clone())
+                        } else if let Some(output) = ask_output_path(selected_file, naming) {
+                            output
+                        } else {
+                            self.encryption_status = "Encryption cancelled: no output file chosen".into();
+                            return Command::none();
+                        };
+                        let mut options = match encryption_options(&self.recipients, &self.passphrase, self.compress, algorithm) {
+                            Ok(options) => options,
+                            Err(e) => {
+                                self.encryption_status = format!("Error encrypting file with {}: {}", algorithm, describe(&e));
+                                return Command::none();
                             }
-                        }
+                        };
+                        let progress = Progress::new();
+                        options.progress = Some(progress.clone());
+                        self.job = Some(Job {
+                            kind: JobKind::Encrypt { recipients: options.recipients.len(), passphrase: options.passphrase.is_some() },
+                            algorithm,
+                            progress,
+                            started: Instant::now(),
+                        });
+                        let (source, in_place, shred_original) = (selected_file.clone(), self.in_place, self.shred);
+                        return run_job(move || encrypt_job(&source, &output, &options, in_place, shred_original));
                     }
                 }
             }
             MyAppMessage::CopyKey => {
-                let mut clipboard = ClipboardContext::new().unwrap();
-                clipboard.set_contents(self.key.clone()).unwrap();
-                self.copy_status = "Key copied to clipboard!".into();
+                self.copy_status = match copy_to_clipboard(&self.key) {
+                    Ok(()) => "Key copied to clipboard!".into(),
+                    Err(e) => format!("Could not copy the key: {}", e),
+                };
             }
-            MyAppMessage::CopyNonce => {
-                let mut clipboard = ClipboardContext::new().unwrap();
-                clipboard.set_contents(self.nonce.clone()).unwrap();
-                self.copy_status = "Nonce copied to clipboard!".into();
+            MyAppMessage::SaveKeyFile => {
+                if let Some(encrypted_file) = &self.processed_file {
+                    let key_file = match (read_header(encrypted_file), Key::from_hex(&self.key)) {
+                        (Ok(header), Ok(key)) => KeyFile::new(&header, key),
+                        (Err(e), _) | (_, Err(e)) => {
+                            self.copy_status = format!("Error creating key file: {}", describe(&e));
+                            return Command::none();
+                        }
+                    };
+                    let file_name = encrypted_file.file_name().and_then(|name| name.to_str()).unwrap_or("key");
+                    if let Some(save_path) = FileDialog::new()
+                        .set_file_name(format!("{}.{}", file_name, KEY_FILE_EXTENSION))
+                        .add_filter("Encora key file", &[KEY_FILE_EXTENSION])
+                        .save_file() {
+                        self.copy_status = match key_file.save(save_path) {
+                            Ok(()) => "Key file saved successfully".into(),
+                            Err(e) => format!("Error saving key file: {}", describe(&e)),
+                        };
+                    }
+                }
+            }
+            MyAppMessage::SaveKeyBundle => {
+                if let Some(key_bundle) = &self.key_bundle {
+                    if let Some(save_path) = FileDialog::new()
+                        .set_file_name(format!("keys.{}", KEY_BUNDLE_EXTENSION))
+                        .add_filter("Encora key bundle", &[KEY_BUNDLE_EXTENSION])
+                        .save_file() {
+                        self.copy_status = match key_bundle.save(save_path) {
+                            Ok(()) => format!("Key bundle saved with {} key(s)", key_bundle.len()),
+                            Err(e) => format!("Error saving key bundle: {}", describe(&e)),
+                        };
+                    }
+                }
+            }
+            MyAppMessage::ShareThresholdChanged(threshold) => {
+                self.share_threshold = threshold;
+            }
+            MyAppMessage::ShareCountChanged(count) => {
+                self.share_count = count;
+            }
+            MyAppMessage::SplitKey => {
+                let (Ok(threshold), Ok(count)) = (self.share_threshold.trim().parse(), self.share_count.trim().parse()) else {
+                    self.copy_status = "Enter how many shares to make and how many of them are needed, from 2 to 255".into();
+                    return Command::none();
+                };
+                match Key::from_hex(&self.key).and_then(|key| split_key(&key, threshold, count)) {
+                    Ok(shares) => {
+                        self.copy_status = format!("Key split into {} shares; any {} of them decrypt", count, threshold);
+                        self.shares = shares;
+                    }
+                    Err(e) => {
+                        self.copy_status = format!("Error splitting the key: {}", describe(&e));
+                    }
+                }
+            }
+            MyAppMessage::CopyShare(index) => {
+                if let Some(share) = self.shares.get(index) {
+                    self.copy_status = match copy_to_clipboard(&share.to_text()) {
+                        Ok(()) => format!("Share {} copied to clipboard!", share.number),
+                        Err(e) => format!("Could not copy share {}: {}", share.number, e),
+                    };
+                }
+            }
+            MyAppMessage::SaveShareFiles => {
+                // One file per share, named after the encrypted file, to hand to different people
+                let mut dialog = FileDialog::new();
+                if let Some(dir) = self.processed_file.as_ref().and_then(|file| file.parent()) {
+                    dialog = dialog.set_directory(dir);
+                }
+                let Some(dir) = dialog.pick_folder() else {
+                    return Command::none();
+                };
+                let file_name = self.processed_file.as_ref().and_then(|file| file.file_name()).and_then(|name| name.to_str()).unwrap_or("key");
+                let result = self.shares.iter().try_for_each(|share| {
+                    share.save(dir.join(format!("{}.{}.{}", file_name, share.number, SHARE_FILE_EXTENSION)))
+                });
+                self.copy_status = match result {
+                    Ok(()) => format!("{} share files saved in {}", self.shares.len(), dir.display()),
+                    Err(e) => format!("Error saving share files: {}", describe(&e)),
+                };
+            }
+            MyAppMessage::SharesInputChanged(shares) => {
+                self.shares_input = Zeroizing::new(shares);
+            }
+            MyAppMessage::LoadShareFiles => {
+                let Some(paths) = FileDialog::new()
+                    .add_filter("Encora key share", &[SHARE_FILE_EXTENSION])
+                    .pick_files() else {
+                    return Command::none();
+                };
+                // The shares join any already pasted, one per line
+                for path in paths {
+                    match std::fs::read_to_string(&path) {
+                        Ok(contents) => {
+                            let contents = Zeroizing::new(contents);
+                            if !self.shares_input.trim().is_empty() {
+                                self.shares_input.push('\n');
+                            }
+                            self.shares_input.push_str(contents.trim());
+                        }
+                        Err(e) => {
+                            self.decryption_status = format!("Error loading {}: {}", path.display(), e);
+                            return Command::none();
+                        }
+                    }
+                }
+                self.decryption_status = format!("{} key share(s) entered", self.shares_input.split_whitespace().count());
+            }
+            MyAppMessage::LoadKeyFile => {
+                if let Some(path) = FileDialog::new()
+                    .add_filter("Encora key file", &[KEY_FILE_EXTENSION])
+                    .add_filter("Encora key bundle", &[KEY_BUNDLE_EXTENSION])
+                    .add_filter("Identity file", &["*"])
+                    .pick_file() {
+                    let contents = match std::fs::read_to_string(path) {
+                        Ok(contents) => Zeroizing::new(contents),
+                        Err(e) => {
+                            self.decryption_status = format!("Error loading key file: {}", e);
+                            return Command::none();
+                        }
+                    };
+                    // Identity files open files encrypted to their public key
+                    if Identity::is_identity_file(&contents) {
+                        match Identity::parse(&contents) {
+                            Ok(identity) => {
+                                self.key.zeroize();
+                                self.key_file = None;
+                                self.key_bundle = None;
+                                self.identity = Some(identity);
+                                self.decryption_status = "Identity loaded".into();
+                            }
+                            Err(e) => {
+                                self.decryption_status = format!("Error loading identity: {}", describe(&e));
+                            }
+                        }
+                        return Command::none();
+                    }
+                    // A key bundle holds the keys of many files, each found by the file's header
+                    if KeyBundle::is_key_bundle(&contents) {
+                        match KeyBundle::parse(&contents) {
+                            Ok(key_bundle) => {
+                                self.key.zeroize();
+                                self.key_file = None;
+                                self.identity = None;
+                                self.decryption_status = format!("Key bundle loaded with {} key(s)", key_bundle.len());
+                                self.key_bundle = Some(key_bundle);
+                            }
+                            Err(e) => {
+                                self.decryption_status = format!("Error loading key bundle: {}", describe(&e));
+                            }
+                        }
+                        return Command::none();
+                    }
+                    match KeyFile::parse(&contents) {
+                        Ok(key_file) => {
+                            self.key = key_file.key.to_hex();
+                            self.key_file = Some(key_file);
+                            self.key_bundle = None;
+                            self.identity = None;
+                            self.decryption_status = "Key file loaded".into();
+                        }
+                        Err(e) => {
+                            self.decryption_status = format!("Error loading key file: {}", describe(&e));
+                        }
+                    }
+                }
+            }
+            MyAppMessage::GenerateIdentity => {
+                if let Some(save_path) = FileDialog::new()
+                    .set_file_name("identity.txt")
+                    .save_file() {
+                    let identity = Identity::generate();
+                    self.copy_status = match identity.save(save_path) {
+                        Ok(()) => {
+                            // Share the public key; keep the identity file private
+                            self.key = Zeroizing::new(identity.recipient().to_string());
+                            format!("Identity saved. Your public key: {}", *self.key)
+                        }
+                        Err(e) => format!("Error saving identity: {}", describe(&e)),
+                    };
+                }
             }
             MyAppMessage::BackToMain => {
                 // show the main page
-                self.show_key_nonce_input = false;
+                self.show_key_input = false;
                 self.encryption_status = String::new();
                 self.decryption_status = String::new();
-                self.key = String::new();
-                self.nonce = String::new();
+                // Wipe the key and passphrase rather than just dropping them
+                self.key.zeroize();
+                self.passphrase.zeroize();
+                self.shares.clear();
+                self.shares_input.zeroize();
                 self.selected_file = None;
+                self.batch.clear();
+                self.processed_file = None;
+                self.key_file = None;
+                self.key_bundle = None;
+                self.identity = None;
+                self.recipients = String::new();
+                self.distinct_keys = false;
+                self.hide_file_name = false;
+                self.compress = false;
+                self.in_place = false;
+                self.shred = false;
+                self.shred_status = String::new();
+                self.archive = None;
                 self.copy_status = String::new();
             }
             MyAppMessage::StartDecryption => {
-                // Show input fields for key and nonce
-                self.show_key_nonce_input = true;
+                // Show input fields for the key or passphrase
+                self.show_key_input = true;
                 self.encryption_status = String::new();
-                self.key = String::new();
-                self.nonce = String::new();
+                self.key.zeroize();
+                self.passphrase.zeroize();
+                self.shares.clear();
+                self.shares_input.zeroize();
                 self.selected_file = None;
+                self.batch.clear();
+                self.processed_file = None;
+                self.key_file = None;
+                self.key_bundle = None;
+                self.identity = None;
+                self.archive = None;
                 self.copy_status = String::new();
             }
             MyAppMessage::KeyInputChanged(key) => {
-                self.key = key;
+                // A hand-edited key no longer comes from the loaded key file
+                self.key = Zeroizing::new(key);
+                self.key_file = None;
+                self.key_bundle = None;
+                self.identity = None;
             }
-            MyAppMessage::NonceInputChanged(nonce) => {
-                self.nonce = nonce;
+            MyAppMessage::PassphraseInputChanged(passphrase) => {
+                self.passphrase = Zeroizing::new(passphrase);
             }
-            MyAppMessage::DownloadFile => {
-                if let Some(file_path) = &self.processed_file {
-                    if let Some(save_path) = FileDialog::new()
-                        .set_file_name(file_path.file_name().unwrap().to_str().unwrap())
-                        .save_file() {
-                        if let Err(e) = std::fs::copy(file_path, save_path) {
-                            self.copy_status = format!("Error saving file: {}", e);
-                        } else {
-                            self.copy_status = "File saved successfully".into();
-                            self.processed_file = None; // Reset processed_file
-                        }
-                    }
+            MyAppMessage::RecipientsInputChanged(recipients) => {
+                self.recipients = recipients;
+            }
+            MyAppMessage::HideFileNameToggled(hide) => {
+                self.hide_file_name = hide;
+            }
+            MyAppMessage::CompressToggled(compress) => {
+                self.compress = compress;
+            }
+            MyAppMessage::InPlaceToggled(in_place) => {
+                self.in_place = in_place;
+                // The replaced original is renamed away rather than overwritten, so it cannot be shredded
+                if in_place {
+                    self.shred = false;
                 }
             }
+            MyAppMessage::ShredToggled(shred) => {
+                self.shred = shred;
+            }
+            MyAppMessage::DistinctKeysToggled(distinct_keys) => {
+                self.distinct_keys = distinct_keys;
+            }
             MyAppMessage::Decrypt => {
-                // Start decryption when both key and nonce are provided
-                if self.key.is_empty() || self.nonce.is_empty() {
-                    self.decryption_status = "Please provide both key and nonce to decrypt".into();
-                } else {
-                    if let Some(selected_file) = &self.selected_file {
-                        if let Some(algorithm) = self.selected_algorithm {
-                            match algorithm {
-                                Algorithms::ChaCha20 => {
-                                    match decrypt_file(selected_file, &self.key, &self.nonce) {
-                                        Ok(output_path) => {
-                                            self.processed_file = Some(output_path); // for download
-                                            self.decryption_status = format!(
-                                                "File decrypted successfully: {:?}",
-                                                selected_file
-                                            );
-                                            self.show_key_nonce_input = false; // Hide input fields after successful decryption
-                                        }
-                                        Err(e) => {
-                                            self.decryption_status = format!("Error decrypting file: {}", e);
-                                        }
-                                    }
-                                }
-                                Algorithms::AES => {
-                                    if self.key.is_empty() {
-                                        self.decryption_status = "Please provide a key for AES".into();
-                                        return;
-                                    }
-                                    match aes_decrypt_file(selected_file, &self.key, &self.nonce) {
-                                        Ok(decrypted_file_path) => {
-                                            self.decryption_status = format!("File decrypted successfully with AES. Saved to: {}", decrypted_file_path.display());
-                                            self.processed_file = Some(decrypted_file_path);
-                                            self.show_key_nonce_input = false;
-                                        }
-                                        Err(e) => {
-                                            self.decryption_status = format!("Error decrypting file with AES: {}", e);
-                                        }
-                                    }
-                                }
+                // Start decryption once a key or passphrase is provided; the algorithm comes from the file header
+                if self.no_secret_entered() && self.vault.is_none() {
+                    self.decryption_status = "Please provide the key, passphrase or identity to decrypt".into();
+                } else if !self.batch.is_empty() {
+                    return self.start_batch_decryption();
+                } else if let Some(selected_file) = &self.selected_file {
+                    let shares = match parse_shares(&self.shares_input) {
+                        Ok(shares) => shares,
+                        Err(e) => {
+                            self.decryption_status = format!("Cannot use the key shares: {}", describe(&e));
+                            return Command::none();
+                        }
+                    };
+                    let header = match read_header(selected_file) {
+                        Ok(header) => header,
+                        Err(e) => {
+                            self.decryption_status = format!("Cannot decrypt this file: {}", describe(&e));
+                            return Command::none();
+                        }
+                    };
+                    if self.key_file.as_ref().is_some_and(|key_file| !key_file.matches(&header)) {
+                        self.decryption_status = "This key file belongs to a different encrypted file".into();
+                        return Command::none();
+                    }
+                    if self.no_secret_entered() && self.vault.as_ref().is_none_or(|vault| vault.find(&header).is_none()) {
+                        self.decryption_status = "Your vault has no key for this file; please provide the key, passphrase or identity".into();
+                        return Command::none();
+                    }
+                    let algorithm = header.algorithm;
+                    self.selected_algorithm = Some(algorithm);
+                    let Some(unlock) = unlock_secret(&header, &shares, self.key_bundle.as_ref(), self.vault.as_ref().map(Vault::keys), self.identity.as_ref(), &self.passphrase, &self.key) else {
+                        self.decryption_status = "The key bundle has no key for this file".into();
+                        return Command::none();
+                    };
+                    let progress = Progress::new();
+                    let job = Job {
+                        kind: JobKind::Decrypt { in_place: self.in_place },
+                        algorithm,
+                        progress: progress.clone(),
+                        started: Instant::now(),
+                    };
+                    let source = selected_file.clone();
+                    if self.in_place {
+                        self.job = Some(job);
+                        return run_job(move || match decrypt_in_place(&source, unlock.secret(), Some(&progress)) {
+                            Ok(_) => JobOutcome::Decrypted(source),
+                            Err(e) => JobOutcome::failed(e),
+                        });
+                    }
+                    // The job asks where to save once the key has opened the file, so the original name can be suggested
+                    self.job = Some(job);
+                    let (request_sender, request_receiver) = oneshot::channel();
+                    let decryption = run_job(move || {
+                        let choose = |metadata: Option<&FileMetadata>| {
+                            if progress.is_cancelled() {
+                                return Err(EncoraError::Cancelled);
                             }
+                            let (reply, answer) = std::sync::mpsc::channel();
+                            let request = OutputRequest { source: source.clone(), metadata: metadata.cloned(), reply };
+                            if request_sender.send(request).is_err() {
+                                return Err(EncoraError::Cancelled);
+                            }
+                            match answer.recv() {
+                                Ok(Some(output)) => output.restored_path(&source, metadata),
+                                _ => Err(EncoraError::Cancelled),
+                            }
+                        };
+                        match decrypt_to_chosen_path(&source, unlock.secret(), choose, Some(&progress)) {
+                            Ok((_, decrypted_file_path)) => JobOutcome::Decrypted(decrypted_file_path),
+                            Err(e) => JobOutcome::failed(e),
+                        }
+                    });
+                    // The request is never sent if the file cannot be opened
+                    let request = Command::perform(request_receiver, |request| MyAppMessage::OutputNeeded(request.ok()));
+                    return Command::batch([decryption, request]);
+                }
+            }
+            MyAppMessage::BrowseContents => {
+                // Only the index is decrypted here; entries are decrypted when extracted
+                if self.no_secret_entered() && self.vault.is_none() {
+                    self.decryption_status = "Please provide the key, passphrase or identity to browse the folder".into();
+                } else if let Some(selected_file) = &self.selected_file {
+                    let shares = match parse_shares(&self.shares_input) {
+                        Ok(shares) => shares,
+                        Err(e) => {
+                            self.decryption_status = format!("Cannot use the key shares: {}", describe(&e));
+                            return Command::none();
+                        }
+                    };
+                    let header = match read_header(selected_file) {
+                        Ok(header) if header.archive => header,
+                        Ok(_) => {
+                            self.decryption_status = "Only encrypted folders can be browsed; decrypt this file instead".into();
+                            return Command::none();
+                        }
+                        Err(e) => {
+                            self.decryption_status = format!("Cannot open this file: {}", describe(&e));
+                            return Command::none();
                         }
+                    };
+                    if self.key_file.as_ref().is_some_and(|key_file| !key_file.matches(&header)) {
+                        self.decryption_status = "This key file belongs to a different encrypted file".into();
+                        return Command::none();
                     }
+                    if self.no_secret_entered() && self.vault.as_ref().is_none_or(|vault| vault.find(&header).is_none()) {
+                        self.decryption_status = "Your vault has no key for this file; please provide the key, passphrase or identity".into();
+                        return Command::none();
+                    }
+                    let Some(unlock) = unlock_secret(&header, &shares, self.key_bundle.as_ref(), self.vault.as_ref().map(Vault::keys), self.identity.as_ref(), &self.passphrase, &self.key) else {
+                        self.decryption_status = "The key bundle has no key for this file".into();
+                        return Command::none();
+                    };
+                    let progress = Progress::new();
+                    self.job = Some(Job {
+                        kind: JobKind::Browse,
+                        algorithm: header.algorithm,
+                        progress: progress.clone(),
+                        started: Instant::now(),
+                    });
+                    let source = selected_file.clone();
+                    // Unlocking can take seconds, so it runs in the background; a cancel takes effect once it returns
+                    return run_job(move || match Archive::open(&source, unlock.secret()) {
+                        Ok(_) if progress.is_cancelled() => JobOutcome::Cancelled,
+                        Ok(archive) => JobOutcome::Opened(OpenFolder {
+                            entries: archive.entries().to_vec(),
+                            algorithm: archive.header().algorithm,
+                            archive: Arc::new(Mutex::new(archive)),
+                        }),
+                        Err(e) => JobOutcome::failed(e),
+                    });
+                }
+            }
+            MyAppMessage::FolderToggled(path) => {
+                if !self.expanded.remove(&path) {
+                    self.expanded.insert(path);
                 }
-            }            
+            }
+            MyAppMessage::ExtractEntry(path) => {
+                let Some(folder) = &self.archive else {
+                    return Command::none();
+                };
+                let Some(name) = folder.entries.iter().find(|entry| entry.path == path).map(|entry| entry.name().to_string()) else {
+                    return Command::none();
+                };
+                // Extract into a chosen folder under the entry's own name, never over an existing file
+                let mut dialog = FileDialog::new();
+                if let Some(dir) = self.selected_file.as_ref().and_then(|file| file.parent()) {
+                    dialog = dialog.set_directory(dir);
+                }
+                let Some(dir) = dialog.pick_folder() else {
+                    self.decryption_status = "Extraction cancelled: no folder chosen".into();
+                    return Command::none();
+                };
+                let output = dir.join(name);
+                let progress = Progress::new();
+                self.job = Some(Job {
+                    kind: JobKind::Extract { path: path.clone() },
+                    algorithm: folder.algorithm,
+                    progress: progress.clone(),
+                    started: Instant::now(),
+                });
+                let archive = folder.archive.clone();
+                return run_job(move || {
+                    let mut archive = archive.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                    archive.set_progress(Some(&progress));
+                    let result = archive.extract(&path, &output);
+                    archive.set_progress(None);
+                    match result {
+                        Ok(()) => JobOutcome::Extracted(output),
+                        Err(e) => JobOutcome::failed(e),
+                    }
+                });
+            }
+            MyAppMessage::OpenKeys => {
+                self.show_keys = true;
+                self.vault_status = String::new();
+            }
+            MyAppMessage::CloseKeys => {
+                // The vault stays unlocked, so keys keep being recorded and looked up
+                self.show_keys = false;
+                self.vault_password.zeroize();
+                self.vault_password_confirm.zeroize();
+                self.pending_delete = None;
+            }
+            MyAppMessage::VaultPasswordChanged(password) => {
+                self.vault_password = Zeroizing::new(password);
+            }
+            MyAppMessage::VaultPasswordConfirmChanged(password) => {
+                self.vault_password_confirm = Zeroizing::new(password);
+            }
+            MyAppMessage::UnlockVault => {
+                let Some(path) = &self.vault_path else {
+                    return Command::none();
+                };
+                if self.job.is_some() {
+                    return Command::none();
+                }
+                self.vault_status = "Unlocking the vault...".into();
+                // Argon2 takes a moment, so the master password is checked in the background
+                let (path, password) = (path.clone(), self.vault_password.clone());
+                return self.run_vault_job(false, move || Vault::open(path, &password));
+            }
+            MyAppMessage::CreateVault => {
+                let Some(path) = &self.vault_path else {
+                    return Command::none();
+                };
+                // A mistyped master password would lock every key away for good
+                if *self.vault_password != *self.vault_password_confirm {
+                    self.vault_status = "The two master passwords do not match".into();
+                    return Command::none();
+                }
+                if self.job.is_some() {
+                    return Command::none();
+                }
+                self.vault_status = "Creating the vault...".into();
+                let (path, password) = (path.clone(), self.vault_password.clone());
+                return self.run_vault_job(true, move || Vault::create(path, &password));
+            }
+            MyAppMessage::LockVault => {
+                self.vault = None;
+                self.vault_search = String::new();
+                self.pending_delete = None;
+                self.vault_status = "Vault locked".into();
+            }
+            MyAppMessage::VaultSearchChanged(query) => {
+                self.vault_search = query;
+                self.pending_delete = None;
+            }
+            MyAppMessage::ExportVaultKey(index) => {
+                let Some(entry) = self.vault.as_ref().and_then(|vault| vault.keys().entries.get(index)) else {
+                    return Command::none();
+                };
+                let file_name = std::path::Path::new(&entry.file_name).file_name().and_then(|name| name.to_str()).unwrap_or("key");
+                if let Some(save_path) = FileDialog::new()
+                    .set_file_name(format!("{}.{}", file_name, KEY_FILE_EXTENSION))
+                    .add_filter("Encora key file", &[KEY_FILE_EXTENSION])
+                    .save_file() {
+                    self.vault_status = match entry.key_file.save(save_path) {
+                        Ok(()) => format!("Key of {} exported", entry.file_name),
+                        Err(e) => format!("Error exporting the key: {}", describe(&e)),
+                    };
+                }
+            }
+            MyAppMessage::ExportVaultKeys => {
+                let Some(vault) = &self.vault else {
+                    return Command::none();
+                };
+                let key_bundle = vault.export(&vault.search(&self.vault_search));
+                if let Some(save_path) = FileDialog::new()
+                    .set_file_name(format!("keys.{}", KEY_BUNDLE_EXTENSION))
+                    .add_filter("Encora key bundle", &[KEY_BUNDLE_EXTENSION])
+                    .save_file() {
+                    self.vault_status = match key_bundle.save(save_path) {
+                        Ok(()) => format!("{} key(s) exported as a key bundle", key_bundle.len()),
+                        Err(e) => format!("Error exporting the keys: {}", describe(&e)),
+                    };
+                }
+            }
+            MyAppMessage::DeleteVaultKey(index) => {
+                let Some(vault) = &mut self.vault else {
+                    return Command::none();
+                };
+                // Deleting takes a second click, as the file may have no other copy of its key
+                if self.pending_delete != Some(index) {
+                    self.pending_delete = Some(index);
+                    self.vault_status = "Click again to delete the key; without another copy of it, its file can no longer be decrypted".into();
+                    return Command::none();
+                }
+                self.pending_delete = None;
+                self.vault_status = match vault.remove(index) {
+                    Ok(entry) => format!("Deleted the key of {}", entry.file_name),
+                    Err(e) => format!("Error deleting the key: {}", describe(&e)),
+                };
+            }
+            MyAppMessage::ProgressTick => {
+                // Nothing changes here; the view reads the progress as it redraws
+            }
+            MyAppMessage::CancelJob => {
+                // The job stops at its next read, removes its partial output and reports back; a batch stops with it
+                if let Some(job) = &self.job {
+                    job.progress.cancel();
+                }
+            }
+            MyAppMessage::OutputNeeded(Some(request)) => {
+                let naming = |output: &OutputLocation, source: &std::path::Path| output.restored_path(source, request.metadata.as_ref());
+                // Dropping the reply unanswered also cancels, should the job be gone already
+                let _ = request.reply.send(ask_output_path(&request.source, naming));
+            }
+            MyAppMessage::OutputNeeded(None) => {
+                // The decryption failed before it could ask; its outcome reports why
+            }
+            MyAppMessage::JobFinished(outcome) => {
+                let Some(job) = self.job.take() else {
+                    return Command::none();
+                };
+                if self.batch_task.is_some() {
+                    return self.batch_job_finished(job, outcome);
+                }
+                match (job.kind, outcome) {
+                    (JobKind::Encrypt { recipients, passphrase }, JobOutcome::Encrypted { key, output, shred_status }) => {
+                        if let Some(Ok(note) | Err(note)) = self.record_in_vault(&output, &key) {
+                            self.copy_status = note;
+                        }
+                        self.processed_file = Some(output);
+                        self.shred_status = shred_status;
+                        if recipients > 0 {
+                            self.encryption_status = format!("File encrypted successfully for {} recipient(s)", recipients);
+                        } else if passphrase {
+                            self.encryption_status = "File encrypted successfully with your passphrase".into();
+                            self.passphrase.zeroize();
+                        } else {
+                            self.encryption_status = "File encrypted successfully".into();
+                            self.key = key.to_hex();
+                        }
+                    }
+                    (JobKind::Encrypt { .. }, JobOutcome::Cancelled) => {
+                        self.encryption_status = "Encryption cancelled; no encrypted file was kept and the original is untouched".into();
+                    }
+                    (JobKind::Encrypt { .. }, JobOutcome::Failed(reason)) => {
+                        self.encryption_status = format!("Error encrypting file with {}: {}", job.algorithm, reason);
+                    }
+                    (JobKind::Decrypt { in_place }, JobOutcome::Decrypted(output)) => {
+                        self.decryption_status = if in_place {
+                            format!("File decrypted in place with {}", job.algorithm)
+                        } else {
+                            format!("File decrypted successfully with {}", job.algorithm)
+                        };
+                        self.processed_file = Some(output);
+                        self.show_key_input = false; // Hide input fields after successful decryption
+                    }
+                    (JobKind::Decrypt { .. }, JobOutcome::Cancelled) => {
+                        self.decryption_status = "Decryption cancelled; no decrypted file was kept and the encrypted file is untouched".into();
+                    }
+                    (JobKind::Decrypt { .. }, JobOutcome::Failed(reason)) => {
+                        self.decryption_status = format!("Error decrypting file with {}: {}", job.algorithm, reason);
+                    }
+                    (JobKind::Browse, JobOutcome::Opened(folder)) => {
+                        self.decryption_status = format!("The folder holds {} item(s)", folder.entries.len());
+                        self.expanded.clear();
+                        self.archive = Some(folder);
+                    }
+                    (JobKind::Browse, JobOutcome::Cancelled) => {
+                        self.decryption_status = "Opening the folder cancelled".into();
+                    }
+                    (JobKind::Browse, JobOutcome::Failed(reason)) => {
+                        self.decryption_status = format!("Error opening folder with {}: {}", job.algorithm, reason);
+                    }
+                    (JobKind::Extract { path }, JobOutcome::Extracted(output)) => {
+                        self.decryption_status = format!("Extracted {}", path);
+                        self.processed_file = Some(output);
+                    }
+                    (JobKind::Extract { path }, JobOutcome::Cancelled) => {
+                        self.decryption_status = format!("Extracting {} cancelled; nothing extracted was kept", path);
+                    }
+                    (JobKind::Extract { path }, JobOutcome::Failed(reason)) => {
+                        self.decryption_status = format!("Error extracting {}: {}", path, reason);
+                    }
+                    (JobKind::Vault { create }, JobOutcome::Unlocked(UnlockedVault(vault))) => {
+                        let Some(vault) = vault.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take() else {
+                            return Command::none();
+                        };
+                        self.vault_status = if create {
+                            "Vault created; keys of the files you encrypt are now recorded in it".into()
+                        } else {
+                            format!("Vault unlocked with {} key(s)", vault.keys().len())
+                        };
+                        self.vault = Some(vault);
+                        self.vault_password.zeroize();
+                        self.vault_password_confirm.zeroize();
+                    }
+                    (JobKind::Vault { .. }, JobOutcome::Cancelled) => {
+                        self.vault_status = "Unlocking the vault cancelled".into();
+                    }
+                    (JobKind::Vault { create }, JobOutcome::Failed(reason)) => {
+                        self.vault_status = if create {
+                            format!("Error creating the vault: {}", reason)
+                        } else {
+                            format!("Error unlocking the vault: {}", reason)
+                        };
+                    }
+                    (_, outcome) => unreachable!("{:?} does not finish this kind of job", outcome),
+                }
+            }
         }
+        Command::none()
     }
-    
+
+    fn subscription(&self) -> Subscription<Self::Message> {
+        // Redraw regularly while a job runs, so its progress bar moves
+        let ticks = if self.job.is_some() {
+            progress_ticks()
+        } else {
+            Subscription::none()
+        };
+        Subscription::batch([file_drops(), ticks])
+    }
+
     fn theme(&self) -> iced::Theme {
         iced::Theme::Dark
     }
 
-    fn view(&self) -> Element<Self::Message> {
+    fn view(&self) -> Element<'_, Self::Message> {
+        if self.show_keys {
+            return self.keys_view();
+        }
         column![
             container(column![
                 text("Encora")
@@ -243,11 +1094,14 @@ impl Sandbox for MyApp {
             .width(Length::Fill),
             container(column![
                 row![
-                    text("Encora supports two encryption algorithms: ChaCha20 and AES.")
+                    text(format!(
+                        "Encora supports these authenticated algorithms: {}.",
+                        Algorithm::ALL.iter().copied().map(Algorithm::name).collect::<Vec<_>>().join(", ")
+                    ))
                         .width(Length::Shrink),
                     Space::with_width(Length::Fill),
                     pick_list(
-                        &Algorithms::ALL[..],
+                        Algorithm::ALL,
                         self.selected_algorithm, // current selected algorithm
                         MyAppMessage::AlgorithmSelected
                     )
@@ -269,73 +1123,167 @@ impl Sandbox for MyApp {
             Space::with_height(30),
             container(
                 column![
-                    if let Some(selected_file) = &self.selected_file {
-                        button(text(format!("Selected file: {}", selected_file.display())))
-                            .on_press(MyAppMessage::OpenFileDialog)
-                            .padding(15)
-                            .style(theme::Button::Secondary)
+                    if self.hovering_files {
+                        text("Drop files to encrypt them, or encrypted files to decrypt them")
+                            .size(18)
+                            .style(iced::theme::Text::Color(iced::Color::from_rgb(0.0,0.5,0.9)))
                     } else {
-                        button(text("Select a file..."))
-                            .on_press(MyAppMessage::OpenFileDialog)
+                        text("")
+                    },
+                    row![
+                        if let Some(selected_file) = &self.selected_file {
+                            let kind = if selected_file.is_dir() { "folder" } else { "file" };
+                            button(text(format!("Selected {}: {}", kind, selected_file.display())))
+                                .on_press_maybe(self.job.is_none().then_some(MyAppMessage::OpenFileDialog))
+                                .padding(15)
+                                .style(theme::Button::Secondary)
+                        } else if !self.batch.is_empty() {
+                            button(text(format!("Selected {} files", self.batch.len())))
+                                .on_press_maybe(self.job.is_none().then_some(MyAppMessage::OpenFileDialog))
+                                .padding(15)
+                                .style(theme::Button::Secondary)
+                        } else {
+                            button(text("Select files..."))
+                                .on_press_maybe(self.job.is_none().then_some(MyAppMessage::OpenFileDialog))
+                                .padding(15)
+                                .width(Length::Fixed(740.0))
+                                .style(theme::Button::Secondary)
+                        },
+                        Space::with_width(10),
+                        button(text("Select a folder..."))
+                            .on_press_maybe(self.job.is_none().then_some(MyAppMessage::OpenFolderDialog))
                             .padding(15)
-                            .width(Length::Fixed(900.0))
-                            .style(theme::Button::Secondary)
+                            .style(theme::Button::Secondary),
+                    ]
+                    .align_items(iced::Alignment::Center),
+                    Space::with_height(10),
+
+                    // The files of a batch, each with how far it has got
+                    if !self.batch.is_empty() {
+                        batch_queue(&self.batch, self.job.as_ref())
+                    } else {
+                        column![].into()
                     },
-                    Space::with_height(30),
+                    Space::with_height(20),
                     
-                    if !self.show_key_nonce_input {
-                        row![
-                            button(text("Encrypt"))
-                                .on_press(MyAppMessage::StartEncryption)
-                                .padding(10),
-                            Space::with_width(20),
-                            button(text("Decrypt"))
-                                .on_press(MyAppMessage::StartDecryption)
-                                .padding(10),
-                            Space::with_width(20),
-                            if self.processed_file.is_some() {
-
-                                button(text("Download File"))
-                                    .on_press(MyAppMessage::DownloadFile)
-                                    .padding(10)
+                    if !self.show_key_input {
+                        column![
+                            text_input("Passphrase (optional, leave empty to generate a random key)", &self.passphrase)
+                                .on_input(MyAppMessage::PassphraseInputChanged)
+                                .secure(true)
+                                .padding(10)
+                                .width(Length::Fixed(900.0)),
+                            Space::with_height(10),
+                            text_input("Recipients' public keys (optional, encora-pk-... separated by spaces or commas)", &self.recipients)
+                                .on_input(MyAppMessage::RecipientsInputChanged)
+                                .padding(10)
+                                .width(Length::Fixed(900.0)),
+                            Space::with_height(10),
+                            checkbox("Hide the file name (save under a random name)", self.hide_file_name)
+                                .on_toggle(MyAppMessage::HideFileNameToggled)
+                                .width(Length::Fixed(900.0)),
+                            Space::with_height(10),
+                            checkbox("Compress before encrypting (skipped for files that do not compress)", self.compress)
+                                .on_toggle(MyAppMessage::CompressToggled)
+                                .width(Length::Fixed(900.0)),
+                            Space::with_height(10),
+                            checkbox("Replace the original file instead of keeping a plaintext copy", self.in_place)
+                                .on_toggle(MyAppMessage::InPlaceToggled)
+                                .width(Length::Fixed(900.0)),
+                            Space::with_height(10),
+                            checkbox("Shred the original after encrypting (overwrite it and delete it)", self.shred)
+                                .on_toggle_maybe((!self.in_place).then_some(MyAppMessage::ShredToggled))
+                                .width(Length::Fixed(900.0)),
+                            if self.in_place {
+                                text("Shredding is not available when replacing the original: its old contents stay on disk until overwritten by other files. Untick “Replace the original” to shred it instead.")
+                                    .size(14)
+                                    .width(Length::Fixed(900.0))
+                                    .style(iced::theme::Text::Color(iced::Color::from_rgb(0.9, 0.6, 0.2)))
+                            } else if self.shred {
+                                text("Warning: on SSDs, copy-on-write file systems (Btrfs, ZFS, APFS), snapshots and backups, copies of the original may survive shredding.")
+                                    .size(14)
+                                    .width(Length::Fixed(900.0))
+                                    .style(iced::theme::Text::Color(iced::Color::from_rgb(0.9, 0.6, 0.2)))
                             } else {
-                                button(text("")).width(0)
-                            }
+                                text("")
+                            },
+                            if !self.batch.is_empty() {
+                                column![
+                                    Space::with_height(10),
+                                    checkbox("Use a different random key for each file (all saved together in a key bundle)", self.distinct_keys)
+                                        .on_toggle(MyAppMessage::DistinctKeysToggled)
+                                        .width(Length::Fixed(900.0)),
+                                ]
+                            } else {
+                                column![]
+                            },
+                            Space::with_height(20),
+                            row![
+                                button(text("Encrypt"))
+                                    .on_press_maybe(self.job.is_none().then_some(MyAppMessage::StartEncryption))
+                                    .padding(10),
+                                Space::with_width(20),
+                                button(text("Decrypt"))
+                                    .on_press(MyAppMessage::StartDecryption)
+                                    .padding(10),
+                                Space::with_width(20),
+                                button(text("Generate Key Pair"))
+                                    .on_press(MyAppMessage::GenerateIdentity)
+                                    .padding(10),
+                                Space::with_width(20),
+                                button(text("Keys"))
+                                    .on_press(MyAppMessage::OpenKeys)
+                                    .padding(10),
+                            ]
+                            .align_items(iced::Alignment::Center)
                         ]
                         .align_items(iced::Alignment::Center)
                     } else {
-                        row![]
+                        column![]
                     },
                     
-                        if self.encryption_status == "File encrypted successfully" {
+                        if self.encryption_status == "File encrypted successfully" || (self.key_bundle.is_some() && !self.show_key_input && self.batch_task.is_none()) {
+                            // A batch saves its keys as one key bundle instead of a key file
+                            let save_keys = if self.key_bundle.is_some() {
+                                button("Save Key Bundle").on_press(MyAppMessage::SaveKeyBundle).padding(10)
+                            } else {
+                                button("Save Key File").on_press(MyAppMessage::SaveKeyFile).padding(10)
+                            };
                             container(column![
                                 column![
                                     Space::with_height(20),
             
-                                    // Display Key and Nonce
+                                    // Display Key
                                     text("Encryption Details").size(22).style(iced::theme::Text::Color(iced::Color::from_rgb(0.0,0.5,0.9))),
                                     Space::with_height(10),
                                         
-                                    row![
-                                        text("Key:").width(Length::Shrink).style(iced::theme::Text::Color(iced::Color::from_rgb(0.0,0.5,0.9))),
-                                        text(&self.key)
-                                            .width(Length::Fill)
-                                            .horizontal_alignment(iced::alignment::Horizontal::Center),
-                                        button("Copy").on_press(MyAppMessage::CopyKey).padding(10) 
-                                    ]
+                                    if self.key.is_empty() {
+                                        // Every file of the batch got a key of its own
+                                        row![
+                                            text("Each file was encrypted with a key of its own.").width(Length::Fill),
+                                            save_keys,
+                                        ]
+                                    } else {
+                                        row![
+                                            text("Key:").width(Length::Shrink).style(iced::theme::Text::Color(iced::Color::from_rgb(0.0,0.5,0.9))),
+                                            text(&*self.key)
+                                                .width(Length::Fill)
+                                                .horizontal_alignment(iced::alignment::Horizontal::Center),
+                                            button("Copy").on_press(MyAppMessage::CopyKey).padding(10),
+                                            Space::with_width(10),
+                                            save_keys,
+                                        ]
+                                    }
                                     .align_items(iced::Alignment::Center),
-                                    Space::with_height(10),
-                                    row![
-                                        text("Nonce:").width(Length::Shrink).style(iced::theme::Text::Color(iced::Color::from_rgb(0.0,0.5,0.9))),
-                                        text(&self.nonce)
-                                            .width(Length::Fill)
-                                            .horizontal_alignment(iced::alignment::Horizontal::Center),
-                                        button("Copy").on_press(MyAppMessage::CopyNonce).padding(10)
-                                    ],
                                     Space::with_height(20),
+                                    if self.key.is_empty() { column![] } else { key_shares(&self.share_threshold, &self.share_count, &self.shares) },
                                 ]
                                 .align_items(iced::Alignment::Center),
-                                text("Please save the key and nonce somewhere safe in order to decrypt the file"),
+                                text(if self.key_bundle.is_some() {
+                                    "Please save the key bundle somewhere safe in order to decrypt the files"
+                                } else {
+                                    "Please save the key somewhere safe in order to decrypt the file"
+                                }),
                             ])
                             .width(Length::Fill)
                             .padding([50, 20])
@@ -344,26 +1292,56 @@ impl Sandbox for MyApp {
                             container(column![])
                         },
     
-                    // Show key and nonce input fields when decrypt button is clicked
-                    if self.show_key_nonce_input {
+                    // Show key input field when decrypt button is clicked
+                    if self.show_key_input {
                         column![
                             text("Key:").width(Length::Shrink).horizontal_alignment(Horizontal::Left),
                             Space::with_height(10),
-                            text_input("Enter Key", &self.key)
-                                .on_input(MyAppMessage::KeyInputChanged)
-                                .padding(10)
-                                .width(Length::Fill),
+                            row![
+                                text_input(if self.key_bundle.is_some() { "Using the loaded key bundle" } else { "Enter Key" }, &self.key)
+                                    .on_input(MyAppMessage::KeyInputChanged)
+                                    .padding(10)
+                                    .width(Length::Fill),
+                                Space::with_width(10),
+                                button(text("Load Key, Key Bundle or Identity File"))
+                                    .on_press(MyAppMessage::LoadKeyFile)
+                                    .padding(10),
+                            ]
+                            .align_items(iced::Alignment::Center),
                             Space::with_height(20),
-                            text("Nonce:").width(Length::Shrink).horizontal_alignment(Horizontal::Left),
+                            text("Or Passphrase:").width(Length::Shrink).horizontal_alignment(Horizontal::Left),
                             Space::with_height(10),
-                            text_input("Enter Nonce", &self.nonce)
-                                .on_input(MyAppMessage::NonceInputChanged)
+                            text_input("Enter Passphrase", &self.passphrase)
+                                .on_input(MyAppMessage::PassphraseInputChanged)
+                                .secure(true)
                                 .padding(10)
                                 .width(Length::Fill),
                             Space::with_height(20),
+                            text("Or Key Shares:").width(Length::Shrink).horizontal_alignment(Horizontal::Left),
+                            Space::with_height(10),
+                            row![
+                                text_input("Paste enough shares of the key, separated by spaces", &self.shares_input)
+                                    .on_input(MyAppMessage::SharesInputChanged)
+                                    .secure(true)
+                                    .padding(10)
+                                    .width(Length::Fill),
+                                Space::with_width(10),
+                                button(text("Load Share Files"))
+                                    .on_press(MyAppMessage::LoadShareFiles)
+                                    .padding(10),
+                            ]
+                            .align_items(iced::Alignment::Center),
+                            Space::with_height(10),
+                            checkbox("Replace the encrypted file with the decrypted one", self.in_place)
+                                .on_toggle(MyAppMessage::InPlaceToggled),
+                            Space::with_height(20),
                             row![
                                 button(text("Decrypt Now"))
-                                    .on_press(MyAppMessage::Decrypt)
+                                    .on_press_maybe(self.job.is_none().then_some(MyAppMessage::Decrypt))
+                                    .padding(10),
+                                Space::with_width(20),
+                                button(text("Browse Contents"))
+                                    .on_press_maybe(self.job.is_none().then_some(MyAppMessage::BrowseContents))
                                     .padding(10),
                                 Space::with_width(20),
                                 button(text("Back"))
@@ -378,7 +1356,40 @@ impl Sandbox for MyApp {
                     } else {
                         column![]
                     },
+
+                    // Browse an opened encrypted folder and extract single items
+                    if let Some(folder) = &self.archive {
+                        column![
+                            text("Folder contents").size(22).style(iced::theme::Text::Color(iced::Color::from_rgb(0.0,0.5,0.9))),
+                            Space::with_height(10),
+                            folder_tree(&folder.entries, &self.expanded, self.job.is_none()),
+                        ].padding([0, 50])
+                    } else {
+                        column![]
+                    },
     
+                    // Show how far a running encryption or decryption has got
+                    if let Some(job) = &self.job {
+                        column![
+                            Space::with_height(10),
+                            progress_bar(0.0..=1.0, job.progress.fraction().unwrap_or(0.0) as f32)
+                                .width(Length::Fixed(900.0)),
+                            Space::with_height(5),
+                            row![
+                                text(if job.progress.is_cancelled() { "Cancelling...".to_string() } else { job.status() }).size(15),
+                                Space::with_width(20),
+                                button(text("Cancel"))
+                                    .on_press_maybe((!job.progress.is_cancelled()).then_some(MyAppMessage::CancelJob))
+                                    .padding(5)
+                                    .style(theme::Button::Destructive),
+                            ]
+                            .align_items(iced::Alignment::Center),
+                        ]
+                        .align_items(iced::Alignment::Center)
+                    } else {
+                        column![]
+                    },
+
                     // Show encryption or decryption status
                     Space::with_height(10),
                     text(&self.encryption_status)
@@ -391,6 +1402,19 @@ impl Sandbox for MyApp {
                         .horizontal_alignment(Horizontal::Center)
                         .size(15)
                         .style(iced::theme::Text::Color(iced::Color::from_rgb(0.2, 0.8, 0.2))),
+                    text(
+                        self.processed_file
+                            .as_ref()
+                            .map_or(String::new(), |path| format!("Saved to: {}", path.display()))
+                    )
+                    .width(Length::Fill)
+                    .horizontal_alignment(Horizontal::Center)
+                    .size(15),
+                    text(&self.shred_status)
+                        .width(Length::Fill)
+                        .horizontal_alignment(Horizontal::Center)
+                        .size(15)
+                        .style(iced::theme::Text::Color(iced::Color::from_rgb(0.9, 0.6, 0.2))),
                     Space::with_height(10),
                     text(&self.copy_status)
                         .width(Length::Fill)
@@ -408,3 +1432,774 @@ impl Sandbox for MyApp {
         .into()
     }    
 }
+
+impl MyApp {
+    /// The Keys screen: unlocking or creating the vault, then browsing,
+    /// searching, exporting and deleting the keys in it.
+    fn keys_view(&self) -> Element<'_, MyAppMessage> {
+        let body: Element<'_, MyAppMessage> = if let Some(vault) = &self.vault {
+            let shown = vault.search(&self.vault_search);
+            let mut rows = column![].spacing(8);
+            for &index in &shown {
+                let entry = &vault.keys().entries[index];
+                rows = rows.push(
+                    row![
+                        column![
+                            text(&entry.file_name),
+                            text(format!("{}, id {}", entry.key_file.algorithm, &hex::encode(entry.key_file.file_id)[..16]))
+                                .size(14)
+                                .style(iced::theme::Text::Color(iced::Color::from_rgb(0.6, 0.6, 0.6))),
+                        ]
+                        .width(Length::Fill),
+                        button(text("Export"))
+                            .on_press(MyAppMessage::ExportVaultKey(index))
+                            .padding(5),
+                        button(text(if self.pending_delete == Some(index) { "Confirm Delete" } else { "Delete" }))
+                            .on_press(MyAppMessage::DeleteVaultKey(index))
+                            .padding(5)
+                            .style(theme::Button::Destructive),
+                    ]
+                    .spacing(10)
+                    .align_items(iced::Alignment::Center)
+                );
+            }
+            column![
+                text(format!("Vault: {} ({} key(s))", vault.path().display(), vault.keys().len())),
+                Space::with_height(10),
+                row![
+                    text_input("Search by file name, algorithm or id", &self.vault_search)
+                        .on_input(MyAppMessage::VaultSearchChanged)
+                        .padding(10)
+                        .width(Length::Fill),
+                    button(text("Export Shown"))
+                        .on_press_maybe((!shown.is_empty()).then_some(MyAppMessage::ExportVaultKeys))
+                        .padding(10),
+                    button(text("Lock Vault"))
+                        .on_press(MyAppMessage::LockVault)
+                        .padding(10),
+                ]
+                .spacing(10)
+                .align_items(iced::Alignment::Center),
+                Space::with_height(10),
+                if shown.is_empty() {
+                    text(if vault.keys().is_empty() { "No keys yet. Keys of the files you encrypt are added here." } else { "No key matches the search." })
+                } else {
+                    text("")
+                },
+                scrollable(rows).height(Length::Fixed(350.0)),
+            ]
+            .into()
+        } else if let Some(path) = &self.vault_path {
+            // A vault is created the first time, and unlocked every time after
+            let exists = path.exists();
+            column![
+                text(format!("Vault: {}", path.display())),
+                Space::with_height(10),
+                text(if exists {
+                    "Enter your master password to unlock your keys. While the vault is unlocked, the key of every file you encrypt is recorded in it and found again when you decrypt."
+                } else {
+                    "You have no vault yet. Choose a master password to create one; the key of every file you encrypt is then recorded in it and found again when you decrypt."
+                })
+                .width(Length::Fixed(900.0)),
+                Space::with_height(10),
+                text_input("Master password", &self.vault_password)
+                    .on_input(MyAppMessage::VaultPasswordChanged)
+                    .on_submit(if exists { MyAppMessage::UnlockVault } else { MyAppMessage::CreateVault })
+                    .secure(true)
+                    .padding(10)
+                    .width(Length::Fixed(900.0)),
+                Space::with_height(10),
+                if exists {
+                    column![
+                        button(text("Unlock Vault"))
+                            .on_press_maybe(self.job.is_none().then_some(MyAppMessage::UnlockVault))
+                            .padding(10),
+                    ]
+                } else {
+                    column![
+                        text_input("Repeat the master password", &self.vault_password_confirm)
+                            .on_input(MyAppMessage::VaultPasswordConfirmChanged)
+                            .on_submit(MyAppMessage::CreateVault)
+                            .secure(true)
+                            .padding(10)
+                            .width(Length::Fixed(900.0)),
+                        Space::with_height(10),
+                        button(text("Create Vault"))
+                            .on_press_maybe(self.job.is_none().then_some(MyAppMessage::CreateVault))
+                            .padding(10),
+                    ]
+                    .align_items(iced::Alignment::Center)
+                },
+            ]
+            .align_items(iced::Alignment::Center)
+            .into()
+        } else {
+            text("Your home folder could not be found, so there is nowhere to keep the vault.").into()
+        };
+        column![
+            container(column![
+                text("Keys")
+                    .width(Length::Fill)
+                    .horizontal_alignment(Horizontal::Left)
+                    .vertical_alignment(Vertical::Center)
+                    .size(28)
+                    .style(iced::theme::Text::Color(iced::Color::from_rgb(0.0,0.5,0.9))),
+                Space::with_height(10),
+                text("The keys of your encrypted files, kept under one master password").width(Length::Fill)
+            ])
+            .padding([50, 50])
+            .width(Length::Fill),
+            container(
+                column![
+                    body,
+                    Space::with_height(20),
+                    button(text("Back"))
+                        .on_press(MyAppMessage::CloseKeys)
+                        .padding(10),
+                    Space::with_height(10),
+                    text(&self.vault_status)
+                        .width(Length::Fill)
+                        .horizontal_alignment(Horizontal::Center)
+                        .size(15)
+                        .style(iced::theme::Text::Color(iced::Color::from_rgb(0.0,0.5,0.9))),
+                ]
+                .align_items(iced::Alignment::Center)
+            )
+            .padding([10, 50])
+            .width(Length::Fill)
+            .height(Length::Fill),
+        ]
+        .into()
+    }
+
+    /// Whether no key, passphrase, identity, key bundle or key share was
+    /// given to decrypt with, leaving only the vault.
+    fn no_secret_entered(&self) -> bool {
+        self.key.is_empty() && self.passphrase.is_empty() && self.identity.is_none() && self.key_bundle.is_none()
+            && self.shares_input.trim().is_empty()
+    }
+
+    /// Forgets what was shown about the previously selected files.
+    fn clear_results(&mut self) {
+        self.processed_file = None;
+        self.archive = None;
+        self.encryption_status = String::new();
+        self.decryption_status = String::new();
+        // A bundle loaded to decrypt stays; one made by encrypting belongs to the previous files
+        if !self.show_key_input {
+            self.key_bundle = None;
+        }
+    }
+
+    /// Encrypts every file of the batch with the same settings, one after
+    /// another, into a chosen folder or in place.
+    fn start_batch_encryption(&mut self) -> Command<MyAppMessage> {
+        let Some(algorithm) = self.selected_algorithm else {
+            return Command::none();
+        };
+        let mut options = match encryption_options(&self.recipients, &self.passphrase, self.compress, algorithm) {
+            Ok(options) => options,
+            Err(e) => {
+                self.encryption_status = format!("Error encrypting files with {}: {}", algorithm, describe(&e));
+                return Command::none();
+            }
+        };
+        let output_dir = if self.in_place {
+            None
+        } else if let Some(dir) = ask_output_dir(&self.batch[0].path) {
+            Some(dir)
+        } else {
+            self.encryption_status = "Encryption cancelled: no output folder chosen".into();
+            return Command::none();
+        };
+        // Without a passphrase or recipients the files get a random key, one for all or one each,
+        // recorded in a key bundle
+        if options.passphrase.is_none() && options.recipients.is_empty() {
+            if !self.distinct_keys {
+                options.key = Some(Key::generate());
+            }
+            self.key_bundle = Some(KeyBundle::new());
+        }
+        self.key.zeroize();
+        for item in &mut self.batch {
+            item.status = BatchStatus::Queued;
+        }
+        self.batch_task = Some(BatchTask::Encrypt { options, output_dir, hide_file_name: self.hide_file_name, shred: self.shred });
+        self.next_batch_job()
+    }
+
+    /// Decrypts every file of the batch with what was entered, one after
+    /// another, into a chosen folder or in place.
+    fn start_batch_decryption(&mut self) -> Command<MyAppMessage> {
+        let shares = match parse_shares(&self.shares_input) {
+            Ok(shares) => shares,
+            Err(e) => {
+                self.decryption_status = format!("Cannot use the key shares: {}", describe(&e));
+                return Command::none();
+            }
+        };
+        let output_dir = if self.in_place {
+            None
+        } else if let Some(dir) = ask_output_dir(&self.batch[0].path) {
+            Some(dir)
+        } else {
+            self.decryption_status = "Decryption cancelled: no output folder chosen".into();
+            return Command::none();
+        };
+        for item in &mut self.batch {
+            item.status = BatchStatus::Queued;
+        }
+        self.batch_task = Some(BatchTask::Decrypt {
+            identity: self.identity.clone(),
+            passphrase: self.passphrase.clone(),
+            key: self.key.clone(),
+            shares,
+            key_bundle: self.key_bundle.clone(),
+            vault_keys: self.vault.as_ref().map(|vault| vault.keys().clone()),
+            output_dir,
+        });
+        self.next_batch_job()
+    }
+
+    /// Starts the next queued file of the batch, or sums the batch up when
+    /// none is left.
+    fn next_batch_job(&mut self) -> Command<MyAppMessage> {
+        let Some(task) = &self.batch_task else {
+            return Command::none();
+        };
+        // Files that cannot even be started are marked failed and skipped
+        while let Some(item) = self.batch.iter_mut().find(|item| matches!(item.status, BatchStatus::Queued)) {
+            match start_batch_item(task, &item.path) {
+                Ok((job, command)) => {
+                    item.status = BatchStatus::Running;
+                    self.job = Some(job);
+                    return command;
+                }
+                Err(reason) => item.status = BatchStatus::Failed(reason),
+            }
+        }
+        self.finish_batch();
+        Command::none()
+    }
+
+    /// Records how the running file of the batch ended and goes on with the
+    /// next one.
+    fn batch_job_finished(&mut self, job: Job, outcome: JobOutcome) -> Command<MyAppMessage> {
+        let Some(index) = self.batch.iter().position(|item| matches!(item.status, BatchStatus::Running)) else {
+            // The batch was cleared by going back meanwhile
+            self.batch_task = None;
+            return Command::none();
+        };
+        self.batch[index].status = match outcome {
+            JobOutcome::Encrypted { key, output, shred_status } => {
+                let mut note = shred_status.lines().next().unwrap_or_default().to_string();
+                // Only a failure is worth a note on every file
+                if let Some(Err(reason)) = self.record_in_vault(&output, &key) {
+                    if !note.is_empty() {
+                        note.push_str(". ");
+                    }
+                    note.push_str(&reason);
+                }
+                match &mut self.key_bundle {
+                    // The file cannot be decrypted again unless its random key is recorded
+                    Some(key_bundle) => match read_header(&output) {
+                        Ok(header) => {
+                            let name = output.file_name().map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+                            key_bundle.push(&name, KeyFile::new(&header, key));
+                            BatchStatus::Done(output, note)
+                        }
+                        Err(e) => BatchStatus::Failed(format!("encrypted to {}, but its key could not be recorded: {}", output.display(), describe(&e))),
+                    },
+                    None => BatchStatus::Done(output, note),
+                }
+            }
+            JobOutcome::Decrypted(output) => BatchStatus::Done(output, String::new()),
+            JobOutcome::Cancelled => BatchStatus::Cancelled,
+            JobOutcome::Failed(reason) => BatchStatus::Failed(reason),
+            outcome @ (JobOutcome::Opened(_) | JobOutcome::Extracted(_) | JobOutcome::Unlocked(_)) => unreachable!("{:?} does not finish a batch job", outcome),
+        };
+        // Cancelling stops the whole batch, even if the running file got finished first
+        if job.progress.is_cancelled() {
+            for item in &mut self.batch {
+                if matches!(item.status, BatchStatus::Queued) {
+                    item.status = BatchStatus::Cancelled;
+                }
+            }
+        }
+        self.next_batch_job()
+    }
+
+    /// Runs `open`, which unlocks or creates the vault, as a job of its own.
+    /// A cancel takes effect once it returns, as Argon2 cannot be stopped.
+    fn run_vault_job(&mut self, create: bool, open: impl FnOnce() -> encora::Result<Vault> + Send + 'static) -> Command<MyAppMessage> {
+        let progress = Progress::new();
+        self.job = Some(Job {
+            kind: JobKind::Vault { create },
+            algorithm: EncryptOptions::default().algorithm,
+            progress: progress.clone(),
+            started: Instant::now(),
+        });
+        run_job(move || match open() {
+            Ok(_) if progress.is_cancelled() => JobOutcome::Cancelled,
+            Ok(vault) => JobOutcome::Unlocked(UnlockedVault(Arc::new(Mutex::new(Some(vault))))),
+            Err(e) => JobOutcome::failed(e),
+        })
+    }
+
+    /// Records `key` for the encrypted file at `output` in the vault. `None`
+    /// if no vault is unlocked, otherwise what to tell the user.
+    fn record_in_vault(&mut self, output: &std::path::Path, key: &Key) -> Option<Result<String, String>> {
+        let vault = self.vault.as_mut()?;
+        let result = read_header(output)
+            .and_then(|header| vault.record(&output.display().to_string(), KeyFile::new(&header, key.clone())));
+        Some(match result {
+            Ok(()) => Ok("The key was recorded in your vault".into()),
+            Err(e) => Err(format!("The key could not be recorded in your vault: {}", describe(&e))),
+        })
+    }
+
+    /// Shows how many files of the batch succeeded, failed or were cancelled.
+    fn finish_batch(&mut self) {
+        let Some(task) = self.batch_task.take() else {
+            return;
+        };
+        let count = |wanted: fn(&BatchStatus) -> bool| self.batch.iter().filter(|item| wanted(&item.status)).count();
+        let done = count(|status| matches!(status, BatchStatus::Done(..)));
+        let failed = count(|status| matches!(status, BatchStatus::Failed(_)));
+        let cancelled = count(|status| matches!(status, BatchStatus::Cancelled));
+        let summary = |verb: &str| format!("Batch finished: {} file(s) {}, {} failed, {} cancelled", done, verb, failed, cancelled);
+        match task {
+            BatchTask::Encrypt { options, .. } => {
+                self.encryption_status = summary("encrypted");
+                if done > 0 && options.passphrase.is_some() {
+                    self.passphrase.zeroize();
+                }
+                // A key shared by the whole batch is shown like the key of a single file
+                if let Some(key) = options.key.as_ref().filter(|_| done > 0) {
+                    self.key = key.to_hex();
+                }
+                if self.key_bundle.as_ref().is_some_and(KeyBundle::is_empty) {
+                    self.key_bundle = None;
+                }
+            }
+            BatchTask::Decrypt { .. } => {
+                self.decryption_status = summary("decrypted");
+            }
+        }
+    }
+}
+
+/// Starts the job `task` asks for on `source`, or says why it cannot start.
+fn start_batch_item(task: &BatchTask, source: &std::path::Path) -> Result<(Job, Command<MyAppMessage>), String> {
+    let source = source.to_path_buf();
+    let progress = Progress::new();
+    match task {
+        BatchTask::Encrypt { options, output_dir, hide_file_name, shred } => {
+            // Each file is named in the chosen folder as it would be on its own, and nothing there is overwritten
+            let output = match output_dir {
+                Some(dir) => {
+                    let location = OutputLocation::Directory(dir.clone());
+                    let naming = if *hide_file_name { OutputLocation::opaque_encrypted_path } else { OutputLocation::encrypted_path };
+                    let path = naming(&location, &source).map_err(|e| describe(&e))?;
+                    if path.exists() {
+                        return Err(format!("{} already exists", path.display()));
+                    }
+                    OutputLocation::File(path)
+                }
+                None => OutputLocation::File(source.clone()),
+            };
+            let mut options = options.clone();
+            options.progress = Some(progress.clone());
+            let job = Job {
+                kind: JobKind::Encrypt { recipients: options.recipients.len(), passphrase: options.passphrase.is_some() },
+                algorithm: options.algorithm,
+                progress,
+                started: Instant::now(),
+            };
+            let (in_place, shred_original) = (output_dir.is_none(), *shred);
+            Ok((job, run_job(move || encrypt_job(&source, &output, &options, in_place, shred_original))))
+        }
+        BatchTask::Decrypt { identity, passphrase, key, shares, key_bundle, vault_keys, output_dir } => {
+            let header = read_header(&source).map_err(|e| describe(&e))?;
+            let unlock = unlock_secret(&header, shares, key_bundle.as_ref(), vault_keys.as_ref(), identity.as_ref(), passphrase, key)
+                .ok_or_else(|| "the key bundle has no key for this file".to_string())?;
+            let job = Job {
+                kind: JobKind::Decrypt { in_place: output_dir.is_none() },
+                algorithm: header.algorithm,
+                progress: progress.clone(),
+                started: Instant::now(),
+            };
+            // Each file gets the first of its restored names not taken in the chosen folder, files of the batch
+            // before it included, so nothing there is overwritten; a folder whose names are all taken fails alone
+            let command = match output_dir.clone() {
+                None => run_job(move || match decrypt_in_place(&source, unlock.secret(), Some(&progress)) {
+                    Ok(_) => JobOutcome::Decrypted(source),
+                    Err(e) => JobOutcome::failed(e),
+                }),
+                Some(dir) => run_job(move || match decrypt_to_location(&source, unlock.secret(), &OutputLocation::Directory(dir), Some(&progress)) {
+                    Ok((_, decrypted_file_path)) => JobOutcome::Decrypted(decrypted_file_path),
+                    Err(e) => JobOutcome::failed(e),
+                }),
+            };
+            Ok((job, command))
+        }
+    }
+}
+
+/// Puts `text` on the clipboard, which not every session has, such as a
+/// headless one. `text` is borrowed from its wiped owner; the only copy made
+/// is the one handed to the clipboard, which keeps it anyway.
+fn copy_to_clipboard(text: &str) -> Result<(), String> {
+    let mut clipboard = ClipboardContext::new().map_err(|e| format!("no clipboard is available ({})", e))?;
+    clipboard.set_contents(text.to_owned()).map_err(|e| e.to_string())
+}
+
+/// Asks for the folder a batch is written to, starting next to `source`.
+/// `None` if the dialog is cancelled.
+fn ask_output_dir(source: &std::path::Path) -> Option<PathBuf> {
+    let mut dialog = FileDialog::new();
+    if let Some(dir) = source.parent() {
+        dialog = dialog.set_directory(dir);
+    }
+    dialog.pick_folder()
+}
+
+/// Asks where to save the output of processing `source`, suggesting the
+/// name `naming` would generate next to it, and asks again if `source` itself
+/// is chosen. `None` if the dialog is cancelled.
+fn ask_output_path(
+    source: &std::path::Path,
+    naming: impl Fn(&OutputLocation, &std::path::Path) -> encora::Result<std::path::PathBuf>,
+) -> Option<OutputLocation> {
+    let mut dialog = FileDialog::new();
+    if let Ok(suggested) = naming(&OutputLocation::AlongsideSource, source) {
+        if let Some(name) = suggested.file_name().and_then(|name| name.to_str()) {
+            dialog = dialog.set_file_name(name);
+        }
+    }
+    if let Some(dir) = source.parent() {
+        dialog = dialog.set_directory(dir);
+    }
+    loop {
+        let output = dialog.clone().save_file()?;
+        if !same_file(&output, source) {
+            return Some(OutputLocation::File(output));
+        }
+        MessageDialog::new()
+            .set_level(MessageLevel::Warning)
+            .set_title("Choose another file")
+            .set_description("The output cannot be the file being read. To replace it, tick the box to replace the file in place instead.")
+            .show();
+    }
+}
+
+/// Rows for the files of a batch with their status; the running one shows
+/// how much of it `job` has done.
+fn batch_queue<'a>(batch: &'a [BatchItem], job: Option<&Job>) -> Element<'a, MyAppMessage> {
+    let mut rows = column![].spacing(5);
+    for item in batch {
+        let (status, color) = match &item.status {
+            BatchStatus::Queued => ("Queued".to_string(), iced::Color::from_rgb(0.6, 0.6, 0.6)),
+            BatchStatus::Running => {
+                let percent = job.and_then(|job| job.progress.fraction()).unwrap_or(0.0) * 100.0;
+                (format!("Running, {:.0}%", percent), iced::Color::from_rgb(0.0, 0.5, 0.9))
+            }
+            BatchStatus::Done(output, note) if note.is_empty() => (format!("Done: {}", output.display()), iced::Color::from_rgb(0.2, 0.8, 0.2)),
+            BatchStatus::Done(output, note) => (format!("Done: {}. {}", output.display(), note), iced::Color::from_rgb(0.2, 0.8, 0.2)),
+            BatchStatus::Failed(reason) => (format!("Failed: {}", reason), iced::Color::from_rgb(0.9, 0.3, 0.3)),
+            BatchStatus::Cancelled => ("Cancelled".to_string(), iced::Color::from_rgb(0.9, 0.6, 0.2)),
+        };
+        let name = item.path.file_name().map_or_else(|| item.path.display().to_string(), |name| name.to_string_lossy().into_owned());
+        rows = rows.push(
+            row![
+                text(name).width(Length::FillPortion(1)),
+                text(status).width(Length::FillPortion(2)).style(iced::theme::Text::Color(color)),
+            ]
+            .spacing(10)
+        );
+    }
+    scrollable(rows).width(Length::Fixed(900.0)).height(Length::Fixed(150.0)).into()
+}
+
+/// Controls to split the key just shown into shares, any `threshold` of
+/// which decrypt, and the shares once made.
+fn key_shares<'a>(threshold: &str, count: &str, shares: &[Share]) -> iced::widget::Column<'a, MyAppMessage> {
+    let mut rows = column![
+        row![
+            text("Split the key so that"),
+            text_input("3", threshold).on_input(MyAppMessage::ShareThresholdChanged).padding(5).width(Length::Fixed(50.0)),
+            text("of"),
+            text_input("5", count).on_input(MyAppMessage::ShareCountChanged).padding(5).width(Length::Fixed(50.0)),
+            text("shares decrypt the file"),
+            button("Split Key").on_press(MyAppMessage::SplitKey).padding(10),
+        ]
+        .spacing(10)
+        .align_items(iced::Alignment::Center),
+    ]
+    .spacing(10)
+    .align_items(iced::Alignment::Center);
+    if !shares.is_empty() {
+        for (index, share) in shares.iter().enumerate() {
+            rows = rows.push(
+                row![
+                    text(format!("Share {}:", share.number)).style(iced::theme::Text::Color(iced::Color::from_rgb(0.0,0.5,0.9))),
+                    text(share.to_text().as_str()).size(12).width(Length::Fill),
+                    button("Copy").on_press(MyAppMessage::CopyShare(index)).padding(5),
+                ]
+                .spacing(10)
+                .align_items(iced::Alignment::Center)
+            );
+        }
+        rows = rows.push(button("Save Share Files").on_press(MyAppMessage::SaveShareFiles).padding(10));
+        rows = rows.push(text("Give each share to a different person; fewer than the threshold reveal nothing about the key"));
+    }
+    rows.push(Space::with_height(20))
+}
+
+/// Rows for the `entries` whose folders are all in `expanded`, indented by
+/// depth, each with a button to extract it, enabled if `can_extract`.
+fn folder_tree<'a>(entries: &'a [Entry], expanded: &HashSet<String>, can_extract: bool) -> Element<'a, MyAppMessage> {
+    // Entries come after their folder, so a folder is known to be shown before its contents
+    let mut shown = HashSet::new();
+    let mut rows = column![].spacing(5);
+    for entry in entries {
+        if entry.parent().is_some_and(|parent| !shown.contains(parent)) {
+            continue;
+        }
+        let label: Element<'a, MyAppMessage> = match entry.kind {
+            EntryKind::Directory => {
+                let open = expanded.contains(&entry.path);
+                if open {
+                    shown.insert(entry.path.as_str());
+                }
+                button(text(format!("[{}] {}/", if open { "-" } else { "+" }, entry.name())))
+                    .on_press(MyAppMessage::FolderToggled(entry.path.clone()))
+                    .style(theme::Button::Text)
+                    .into()
+            }
+            EntryKind::File => text(format!("{} ({} bytes)", entry.name(), entry.size)).into(),
+            EntryKind::Symlink => text(format!("{} -> {}", entry.name(), entry.link_target.as_deref().unwrap_or(""))).into(),
+        };
+        let depth = entry.path.matches('/').count();
+        rows = rows.push(
+            row![
+                Space::with_width(Length::Fixed(20.0 * depth as f32)),
+                label,
+                Space::with_width(Length::Fill),
+                button(text("Extract"))
+                    .on_press_maybe(can_extract.then(|| MyAppMessage::ExtractEntry(entry.path.clone())))
+                    .padding(5),
+            ]
+            .align_items(iced::Alignment::Center)
+        );
+    }
+    scrollable(rows).height(Length::Fixed(250.0)).into()
+}
+
+/// What unlocks a file with `header`: the key shares if any were entered,
+/// its key from the key bundle if one is loaded, the identity if one is loaded, the passphrase if the file has a
+/// passphrase keyslot, the key if one was entered, and otherwise the key
+/// recorded in the vault, whose keys are `vault_keys` when it is unlocked.
+/// `None` if the bundle has no key for the file.
+fn unlock_secret(
+    header: &Header,
+    shares: &[Share],
+    key_bundle: Option<&KeyBundle>,
+    vault_keys: Option<&KeyBundle>,
+    identity: Option<&Identity>,
+    passphrase: &str,
+    key: &str,
+) -> Option<Unlock> {
+    Some(if !shares.is_empty() {
+        Unlock::Shares(shares.to_vec())
+    } else if let Some(key_bundle) = key_bundle {
+        Unlock::KeyHex(key_bundle.find(header)?.key.to_hex())
+    } else if let Some(identity) = identity {
+        Unlock::Identity(identity.clone())
+    } else if header.has_keyslot(KeySlotKind::Passphrase) && !passphrase.is_empty() {
+        Unlock::Passphrase(Zeroizing::new(passphrase.to_string()))
+    } else if let Some(key_file) = vault_keys.filter(|_| key.is_empty()).and_then(|keys| keys.find(header)) {
+        Unlock::KeyHex(key_file.key.to_hex())
+    } else {
+        Unlock::KeyHex(Zeroizing::new(key.to_string()))
+    })
+}
+
+/// A copy of the secret that unlocks a file, which a background job can own.
+enum Unlock {
+    Identity(Identity),
+    Passphrase(Zeroizing<String>),
+    KeyHex(Zeroizing<String>),
+    Shares(Vec<Share>),
+}
+
+impl Unlock {
+    fn secret(&self) -> Secret<'_> {
+        match self {
+            Unlock::Identity(identity) => Secret::Identity(identity),
+            Unlock::Passphrase(passphrase) => Secret::Passphrase(passphrase),
+            Unlock::KeyHex(key) => Secret::KeyHex(key),
+            Unlock::Shares(shares) => Secret::Shares(shares),
+        }
+    }
+}
+
+/// The key shares in `text`, separated by spaces or new lines.
+fn parse_shares(text: &str) -> encora::Result<Vec<Share>> {
+    text.split_whitespace().map(Share::parse).collect()
+}
+
+/// Runs `job` on a thread of its own, so the window keeps responding, and
+/// reports its outcome with [`MyAppMessage::JobFinished`].
+fn run_job(job: impl FnOnce() -> JobOutcome + Send + 'static) -> Command<MyAppMessage> {
+    let (sender, receiver) = oneshot::channel();
+    std::thread::spawn(move || {
+        let _ = sender.send(job());
+    });
+    Command::perform(receiver, |outcome| {
+        // The sender is only dropped unused if the job panicked
+        MyAppMessage::JobFinished(outcome.unwrap_or_else(|_| JobOutcome::Failed("the operation stopped unexpectedly".into())))
+    })
+}
+
+/// Encrypts `source` as the Encrypt button asked, then shreds the original
+/// if `shred_original` is set and it was not replaced in place, counting
+/// both in the progress of `options`, whose cancel also stops shredding.
+fn encrypt_job(source: &std::path::Path, output: &OutputLocation, options: &EncryptOptions, in_place: bool, shred_original: bool) -> JobOutcome {
+    let result = output.encrypted_path(source).and_then(|output_path| {
+        let key = if in_place {
+            encrypt_in_place(source, options)
+        } else if source.is_dir() {
+            encrypt_dir(source, &output_path, options)
+        } else {
+            encrypt_file(source, &output_path, options)
+        };
+        key.map(|key| (key, output_path))
+    });
+    match result {
+        Ok((key, output)) => {
+            // Only once the encrypted file is complete; in place, the original is already gone
+            let shred_status = if shred_original && !in_place {
+                match shred_with_progress(source, DEFAULT_PASSES, options.progress.as_ref()) {
+                    Ok(report) => report.to_string(),
+                    // The encrypted file is complete by now, so it is kept
+                    Err(EncoraError::Cancelled) => "Shredding cancelled: the original was not removed and may be partly overwritten".into(),
+                    Err(e) => format!("The original was not shredded: {}", describe(&e)),
+                }
+            } else {
+                String::new()
+            };
+            JobOutcome::Encrypted { key, output, shred_status }
+        }
+        Err(e) => JobOutcome::failed(e),
+    }
+}
+
+/// Messages for files dragged over the window and dropped on it.
+fn file_drops() -> Subscription<MyAppMessage> {
+    event::listen_with(|event, _status| match event {
+        iced::Event::Window(_, window::Event::FileDropped(path)) => Some(MyAppMessage::FileDropped(path)),
+        iced::Event::Window(_, window::Event::FileHovered(_)) => Some(MyAppMessage::FilesHovered),
+        iced::Event::Window(_, window::Event::FilesHoveredLeft) => Some(MyAppMessage::FilesHoveredLeft),
+        _ => None,
+    })
+}
+
+/// Whether `path` is a file starting with an Encora header, even one this
+/// version cannot read, so that it is decrypted rather than encrypted again.
+fn has_encora_header(path: &std::path::Path) -> bool {
+    path.is_file() && !matches!(read_header(path), Err(EncoraError::NotEncoraFile | EncoraError::Io(_)))
+}
+
+/// Messages every [`PROGRESS_INTERVAL`] for as long as it is subscribed to.
+fn progress_ticks() -> Subscription<MyAppMessage> {
+    subscription::channel("progress-ticks", 1, |mut output| async move {
+        let (sender, mut ticks) = mpsc::channel(1);
+        // The timer thread stops once the subscription, and with it `ticks`, is dropped
+        std::thread::spawn(move || {
+            let mut sender = sender;
+            loop {
+                std::thread::sleep(PROGRESS_INTERVAL);
+                if sender.try_send(()).is_err_and(|e| e.is_disconnected()) {
+                    break;
+                }
+            }
+        });
+        loop {
+            ticks.next().await;
+            let _ = output.send(MyAppMessage::ProgressTick).await;
+        }
+    })
+}
+
+impl Job {
+    /// Bytes done out of the total, the throughput and the time left, e.g.
+    /// `12.0 MB of 80.0 MB at 40.0 MB/s, about 2 s left`.
+    fn status(&self) -> String {
+        if let JobKind::Vault { create } = self.kind {
+            return if create { "Creating the vault...".into() } else { "Unlocking the vault...".into() };
+        }
+        let processed = self.progress.processed();
+        let total = self.progress.total();
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let rate = if elapsed > 0.0 { processed as f64 / elapsed } else { 0.0 };
+        let mut status = format!("{} of {}", format_bytes(processed), format_bytes(total));
+        if rate > 0.0 {
+            let remaining = total.saturating_sub(processed) as f64 / rate;
+            status += &format!(" at {}/s, about {} left", format_bytes(rate as u64), format_duration(remaining));
+        }
+        status
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1000 {
+        return format!("{} bytes", bytes);
+    }
+    let mut value = bytes as f64 / 1000.0;
+    let mut unit = 0;
+    while value >= 1000.0 && unit + 1 < UNITS.len() {
+        value /= 1000.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.ceil() as u64;
+    match seconds {
+        0..=59 => format!("{} s", seconds),
+        60..=3599 => format!("{} min {} s", seconds / 60, seconds % 60),
+        _ => format!("{} h {} min", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+/// Options for encrypting to the public keys listed in `recipients` and to
+/// `passphrase`, each unless empty. With neither, a random key is generated.
+fn encryption_options(
+    recipients: &str,
+    passphrase: &str,
+    compress: bool,
+    algorithm: Algorithm,
+) -> encora::Result<EncryptOptions> {
+    let recipients = recipients
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|recipient| !recipient.is_empty())
+        .map(Recipient::parse)
+        .collect::<encora::Result<Vec<_>>>()?;
+    Ok(EncryptOptions {
+        passphrase: (!passphrase.is_empty()).then(|| Zeroizing::new(passphrase.to_string())),
+        recipients,
+        compression: compress.then_some(DEFAULT_LEVEL),
+        ..EncryptOptions::new(algorithm)
+    })
+}
+
+/// The error message followed by what the user can do about it.
+fn describe(error: &EncoraError) -> String {
+    match error.hint() {
+        Some(hint) => format!("{}. {}", error, hint),
+        None => error.to_string(),
+    }
+}
@@ -0,0 +1,54 @@
+//! Encora's encrypted file format and the functions that produce and read it.
+//!
+//! Encrypting a file and decrypting it again with the returned key:
+//!
+//! ```no_run
+//! use encora::{decrypt_file, encrypt_file, Algorithm, EncryptOptions, Secret};
+//!
+//! let options = EncryptOptions::new(Algorithm::XCHACHA20_POLY1305);
+//! let key = encrypt_file("report.pdf", "report.pdf.encora", &options)?;
+//! let header = decrypt_file("report.pdf.encora", "report.pdf", Secret::Key(&key))?;
+//! assert_eq!(header.algorithm, Algorithm::XCHACHA20_POLY1305);
+//! # Ok::<(), encora::EncoraError>(())
+//! ```
+//!
+//! [`encrypt`] and [`decrypt`] do the same over any reader and writer, and
+//! [`read_header`] inspects a file without a key. Files encrypted with
+//! [`encrypt_file`] carry their original name, modification time and
+//! permissions in an encrypted [`FileMetadata`] block, which
+//! [`decrypt_file`] restores and [`decrypt_with`] hands to the caller.
+//! [`encrypt_dir`] and [`decrypt_dir`] do the same for a whole directory tree,
+//! and an [`Archive`] lists such a folder and extracts single entries without
+//! decrypting the rest. [`add_keyslot`] and [`remove_keyslot`] change who can
+//! open a file without re-encrypting it. [`encrypt_in_place`] and
+//! [`decrypt_in_place`] replace a file with its encryption or decryption
+//! atomically, so no plaintext copy is left beside the ciphertext, and
+//! [`shred`] overwrites and removes a plaintext original after encryption.
+//! A [`Progress`] reports how far a long encryption or decryption has got
+//! and cancels it. A [`Vault`] keeps the keys of many files under one master
+//! password, and [`split_key`] divides a key into shares of which any
+//! threshold decrypt through [`combine_shares`] or [`Secret::Shares`].
+//! Failures are reported as an [`EncoraError`] saying whether the key was
+//! wrong, the file was tampered with or truncated, and so on.
+
+pub mod crypto;
+
+pub use crypto::archive::{Archive, Entry, EntryKind};
+pub use crypto::cipher::{Algorithm, FileCipher};
+pub use crypto::error::{EncoraError, Result};
+pub use crypto::header::{read_header, Header};
+pub use crypto::key::{KdfParams, Key, Secret};
+pub use crypto::keyfile::{KeyBundle, KeyFile};
+pub use crypto::keyslot::{add_keyslot, remove_keyslot, KeySlot, KeySlotKind, NewKeySlot};
+pub use crypto::metadata::FileMetadata;
+pub use crypto::progress::Progress;
+pub use crypto::recipient::{Identity, Recipient};
+pub use crypto::shamir::{combine_shares, split_key, Share};
+pub use crypto::shred::{shred, shred_with_progress, ShredReport};
+pub use crypto::vault::Vault;
+pub use crypto::{
+    decrypt, decrypt_dir, decrypt_file, decrypt_in_place, decrypt_to_chosen_path,
+    decrypt_to_location, decrypt_with, encrypt, encrypt_archive, encrypt_dir, encrypt_file,
+    encrypt_in_place, encrypt_to_location, open_decrypted, read_metadata, EncryptOptions,
+    OutputLocation,
+};
@@ -1,8 +1,7 @@
 mod gui;
-mod crypto;
 
-use iced::{Sandbox, Settings};
+use iced::{Application, Settings};
 
-fn main(){
-    gui::MyApp::run(Settings::default());
-}
\ No newline at end of file
+fn main() -> iced::Result {
+    gui::MyApp::run(Settings::default())
+}